@@ -0,0 +1,540 @@
+//! commands_mod
+
+// The functions behind the CLI subcommands.
+// main.rs only parses the arguments and calls this functions.
+// Every command returns an error and main returns a non-zero exit code.
+
+use qvs20::qvs20_diff_mod;
+use qvs20::qvs20_lenient_mod::ParseOptions;
+use qvs20::qvs20_patch_mod;
+use qvs20::qvs20_properties_mod::Property;
+use qvs20::qvs20_schema_mod::*;
+use qvs20::qvs20_table_mod::*;
+use qvs20::qvs20_validate_mod;
+use qvs20::qvs20_writer_mod;
+use qvs20::StreamReaderForQvs20;
+
+use anyhow::{anyhow, Context};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::Path;
+
+/// open the output file or the stdout if there is no output
+pub fn output_writer(output: Option<&Path>) -> anyhow::Result<Box<dyn Write>> {
+    match output {
+        Some(path) => {
+            let file =
+                File::create(path).with_context(|| format!("cannot create {}", path.display()))?;
+            Ok(Box::new(BufWriter::new(file)))
+        }
+        None => Ok(Box::new(BufWriter::new(io::stdout()))),
+    }
+}
+
+/// read and parse a qvs20 file
+pub fn read_table(path: &Path) -> anyhow::Result<Table> {
+    let bytes = std::fs::read(path).with_context(|| format!("cannot read {}", path.display()))?;
+    let table =
+        Table::from_qvs20_with_schema(&bytes).with_context(|| format!("in {}", path.display()))?;
+    //return
+    Ok(table)
+}
+
+/// read a qvs20 file in the lenient mode: the malformed rows are skipped and reported to stderr
+/// the rejected rows are written to the side file with the same schema rows
+pub fn read_table_lenient(path: &Path, rejected: Option<&Path>) -> anyhow::Result<Table> {
+    let bytes = std::fs::read(path).with_context(|| format!("cannot read {}", path.display()))?;
+    let report = Table::from_qvs20_with_options(&bytes, &ParseOptions::lenient())
+        .with_context(|| format!("in {}", path.display()))?;
+    for rejected_row in report.rejected_rows.iter() {
+        eprintln!("skipped in {}: {}", path.display(), rejected_row.error);
+    }
+    if let Some(rejected_path) = rejected {
+        let file = File::create(rejected_path)
+            .with_context(|| format!("cannot create {}", rejected_path.display()))?;
+        report.write_rejected(BufWriter::new(file))?;
+    }
+    //return
+    Ok(report.table)
+}
+
+/// write the table as qvs20
+pub fn write_table(table: &Table, output: Option<&Path>) -> anyhow::Result<()> {
+    let mut wtr = qvs20_writer_mod::Writer::new(output_writer(output)?);
+    wtr.write_table(table)?;
+    Ok(())
+}
+
+/// validate: the file must parse without errors
+/// the rows are streamed, so big files don't need to fit in memory
+pub fn validate(path: &Path, output: Option<&Path>) -> anyhow::Result<()> {
+    let file = File::open(path).with_context(|| format!("cannot open {}", path.display()))?;
+    let mut stream = StreamReaderForQvs20::new(BufReader::new(file))
+        .with_context(|| format!("in {}", path.display()))?;
+    let mut row_count = 0;
+    for result in &mut stream {
+        result.with_context(|| format!("in {}", path.display()))?;
+        row_count += 1;
+    }
+    let schema = stream.schema();
+    let mut wtr = output_writer(output)?;
+    writeln!(
+        wtr,
+        "{}: table {:?}, {} columns, {} rows",
+        path.display(),
+        schema.table_name,
+        schema.columns.len(),
+        row_count
+    )?;
+    wtr.flush()?;
+    Ok(())
+}
+
+/// validate --schema: the file must match the canonical schema file
+/// all the violations are written, not only the first one
+pub fn validate_with_schema(
+    path: &Path,
+    schema_path: &Path,
+    output: Option<&Path>,
+) -> anyhow::Result<()> {
+    let schema_bytes = std::fs::read(schema_path)
+        .with_context(|| format!("cannot read {}", schema_path.display()))?;
+    let schema = Schema::from_qvs20(&schema_bytes)
+        .with_context(|| format!("in {}", schema_path.display()))?;
+    let bytes = std::fs::read(path).with_context(|| format!("cannot read {}", path.display()))?;
+    let violations = qvs20_validate_mod::validate_with_schema(&schema, &bytes);
+    let mut wtr = output_writer(output)?;
+    for violation in violations.iter() {
+        writeln!(wtr, "{}: {}", path.display(), violation)?;
+    }
+    wtr.flush()?;
+    if !violations.is_empty() {
+        return Err(anyhow!(
+            "{} violations of the schema {}",
+            violations.len(),
+            schema_path.display()
+        ));
+    }
+    writeln!(
+        wtr,
+        "{}: valid against the schema {}",
+        path.display(),
+        schema_path.display()
+    )?;
+    wtr.flush()?;
+    Ok(())
+}
+
+/// convert: the format is recognized by the file extension
+/// .qvs20 -> .csv and .csv -> .qvs20
+/// lenient skips the malformed qvs20 rows, rejected is the side file for them
+pub fn convert(
+    path: &Path,
+    output: Option<&Path>,
+    lenient: bool,
+    rejected: Option<&Path>,
+) -> anyhow::Result<()> {
+    let input_extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+    let output_extension = output
+        .and_then(|p| p.extension())
+        .and_then(|e| e.to_str())
+        .unwrap_or("");
+    match (input_extension, output_extension) {
+        ("qvs20", "csv") | ("qvs20", "") => {
+            let table = if lenient {
+                read_table_lenient(path, rejected)?
+            } else {
+                read_table(path)?
+            };
+            let mut wtr = output_writer(output)?;
+            write_csv(&table, &mut wtr)?;
+            wtr.flush()?;
+            Ok(())
+        }
+        ("csv", "qvs20") | ("csv", "") => {
+            let file =
+                File::open(path).with_context(|| format!("cannot open {}", path.display()))?;
+            let table_name = path.file_stem().and_then(|s| s.to_str()).unwrap_or("");
+            let table = table_from_csv(file, table_name)
+                .with_context(|| format!("in {}", path.display()))?;
+            write_table(&table, output)
+        }
+        _ => Err(anyhow!(
+            "cannot convert from {:?} to {:?}, only qvs20 <-> csv",
+            input_extension,
+            output_extension
+        )),
+    }
+}
+
+/// write the column names and the rows as csv
+/// Null is the empty field without quotes, the empty String is "" in quotes
+pub fn write_csv<W: Write>(table: &Table, mut wtr: W) -> anyhow::Result<()> {
+    let header: Vec<String> = table
+        .schema
+        .columns
+        .iter()
+        .map(|c| csv_field(Some(&c.name)))
+        .collect();
+    writeln!(wtr, "{}", header.join(","))?;
+    for row in table.rows.iter() {
+        let fields: Vec<String> = row
+            .values
+            .iter()
+            .map(|v| csv_field(v.to_text().as_deref()))
+            .collect();
+        writeln!(wtr, "{}", fields.join(","))?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+/// the csv field: in quotes if it is empty or has a comma, a quote or a line break
+fn csv_field(text: Option<&str>) -> String {
+    match text {
+        None => String::new(),
+        Some(text) if text.is_empty() || text.contains(&[',', '"', '\r', '\n'][..]) => {
+            format!("\"{}\"", text.replace('"', "\"\""))
+        }
+        Some(text) => text.to_string(),
+    }
+}
+
+/// csv has no data types, all the columns are String
+/// like in write_csv() the empty field without quotes is Null, "" in quotes is the empty String,
+/// the columns with Null are nullable
+pub fn table_from_csv<R: io::Read>(mut rdr: R, table_name: &str) -> anyhow::Result<Table> {
+    // the raw bytes of the records tell if the empty field has quotes
+    let mut bytes = vec![];
+    rdr.read_to_end(&mut bytes)?;
+    let mut csv_rdr = csv::Reader::from_reader(&bytes[..]);
+    let mut table = Table {
+        schema: Schema {
+            table_name: table_name.to_string(),
+            columns: csv_rdr
+                .headers()?
+                .iter()
+                .map(|h| Column::new(h, DataType::String))
+                .collect(),
+            ..Default::default()
+        },
+        rows: vec![],
+    };
+    let mut record = csv::StringRecord::new();
+    loop {
+        let start = csv_rdr.position().byte() as usize;
+        if !csv_rdr.read_record(&mut record)? {
+            break;
+        }
+        let end = csv_rdr.position().byte() as usize;
+        let is_null = unquoted_empty_fields(&bytes[start..end]);
+        table.rows.push(Row {
+            values: record
+                .iter()
+                .enumerate()
+                .map(|(i, f)| match is_null.get(i) {
+                    Some(true) => Value::Null,
+                    _ => Value::String(f.to_string()),
+                })
+                .collect(),
+        });
+    }
+    for (i, column) in table.schema.columns.iter_mut().enumerate() {
+        if table
+            .rows
+            .iter()
+            .any(|row| row.values.get(i) == Some(&Value::Null))
+        {
+            column.properties.list.push(Property::Nullable);
+        }
+    }
+    //return
+    Ok(table)
+}
+
+/// for every field of the raw csv record: true if the field is empty and without quotes
+fn unquoted_empty_fields(raw: &[u8]) -> Vec<bool> {
+    let is_field_end = |b: &u8| *b == b',' || *b == b'\r' || *b == b'\n';
+    let mut fields = vec![];
+    let mut i = 0;
+    loop {
+        match raw.get(i) {
+            Some(b'"') => {
+                // in the quotes "" is one quote, the single quote ends the field
+                i += 1;
+                while i < raw.len() {
+                    if raw[i] == b'"' {
+                        if raw.get(i + 1) != Some(&b'"') {
+                            break;
+                        }
+                        i += 1;
+                    }
+                    i += 1;
+                }
+                fields.push(false);
+            }
+            Some(b) if !is_field_end(b) => fields.push(false),
+            _ => fields.push(true),
+        }
+        while i < raw.len() && !is_field_end(&raw[i]) {
+            i += 1;
+        }
+        if raw.get(i) != Some(&b',') {
+            break;
+        }
+        i += 1;
+    }
+    //return
+    fields
+}
+
+/// query: the rows where all the conditions are true,
+/// only the selected columns or all if select is empty
+/// Null is not equal to any text, also not to the empty text of column=
+pub fn query_table(
+    table: &Table,
+    conditions: &[(String, String)],
+    select: &[String],
+) -> anyhow::Result<Table> {
+    // column positions for conditions
+    let mut condition_columns = vec![];
+    for (column_name, text) in conditions.iter() {
+        condition_columns.push((column_position(table, column_name)?, text));
+    }
+    // column positions for select
+    let selected: Vec<usize> = if select.is_empty() {
+        (0..table.schema.columns.len()).collect()
+    } else {
+        select
+            .iter()
+            .map(|c| column_position(table, c))
+            .collect::<anyhow::Result<_>>()?
+    };
+    let mut result = Table {
+        schema: Schema {
+            columns: selected
+                .iter()
+                .map(|&i| table.schema.columns[i].clone())
+                .collect(),
+            ..table.schema.clone()
+        },
+        rows: vec![],
+    };
+    for row in table.rows.iter() {
+        if condition_columns
+            .iter()
+            .all(|(i, text)| row.values[*i].to_text().as_ref() == Some(*text))
+        {
+            result.rows.push(Row {
+                values: selected.iter().map(|&i| row.values[i].clone()).collect(),
+            });
+        }
+    }
+    //return
+    Ok(result)
+}
+
+/// position of the column by name
+pub fn column_position(table: &Table, column_name: &str) -> anyhow::Result<usize> {
+    table
+        .schema
+        .column_position(column_name)
+        .ok_or_else(|| anyhow!("column {:?} does not exist", column_name))
+}
+
+/// parse the condition column=value
+pub fn parse_condition(condition: &str) -> anyhow::Result<(String, String)> {
+    match condition.find('=') {
+        Some(pos) => Ok((
+            condition[..pos].to_string(),
+            condition[pos + 1..].to_string(),
+        )),
+        None => Err(anyhow!("condition {:?} must be column=value", condition)),
+    }
+}
+
+/// diff: the changes from the old to the new table, matched by the key column
+/// format text is human readable, format qvs20 is the change table
+pub fn diff(
+    old_path: &Path,
+    new_path: &Path,
+    key_column: &str,
+    format: &str,
+    output: Option<&Path>,
+) -> anyhow::Result<()> {
+    let old = read_table(old_path)?;
+    let new = read_table(new_path)?;
+    let table_diff = qvs20_diff_mod::diff_tables(&old, &new, key_column)?;
+    match format {
+        "qvs20" => write_table(&table_diff.to_change_table(), output),
+        _ => {
+            let mut wtr = output_writer(output)?;
+            wtr.write_all(table_diff.to_text().as_bytes())?;
+            wtr.flush()?;
+            Ok(())
+        }
+    }
+}
+
+/// make-patch: the patch from the old to the new snapshot
+pub fn make_patch(
+    old_path: &Path,
+    new_path: &Path,
+    key_column: &str,
+    output: Option<&Path>,
+) -> anyhow::Result<()> {
+    let old = read_table(old_path)?;
+    let new = read_table(new_path)?;
+    let patch = qvs20_patch_mod::make_patch(&old, &new, key_column)?;
+    write_table(&patch, output)
+}
+
+/// apply-patch: the next snapshot from the base and the patch
+pub fn apply_patch(
+    base_path: &Path,
+    patch_path: &Path,
+    output: Option<&Path>,
+) -> anyhow::Result<()> {
+    let base = read_table(base_path)?;
+    let patch = read_table(patch_path)?;
+    let next = qvs20_patch_mod::apply_patch(&base, &patch)
+        .with_context(|| format!("{} to {}", patch_path.display(), base_path.display()))?;
+    write_table(&next, output)
+}
+
+/// stats: human readable statistics for every column
+/// empty is the count of the empty text, null is the count of Null
+pub fn stats_text(table: &Table) -> String {
+    let mut text = format!(
+        "table: {}\ncolumns: {}\nrows: {}\n",
+        table.schema.table_name,
+        table.schema.columns.len(),
+        table.rows.len()
+    );
+    for (i, column) in table.schema.columns.iter().enumerate() {
+        let mut distinct = std::collections::HashSet::new();
+        let mut empty = 0;
+        let mut null = 0;
+        let mut max_len = 0;
+        for row in table.rows.iter() {
+            let value = row.values[i].to_text();
+            match &value {
+                None => null += 1,
+                Some(text) if text.is_empty() => empty += 1,
+                Some(_) => (),
+            }
+            max_len = max_len.max(value.as_deref().map_or(0, |t| t.chars().count()));
+            distinct.insert(value);
+        }
+        text.push_str(&format!(
+            "{}: {}, distinct: {}, empty: {}, null: {}, max_len: {}\n",
+            column.name,
+            column.data_type,
+            distinct.len(),
+            empty,
+            null,
+            max_len
+        ));
+    }
+    //return
+    text
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use unwrap::unwrap;
+    #[test]
+    pub fn test_01_query() {
+        let table = unwrap!(read_table(Path::new("sample_data/crates.qvs20")));
+        let conditions = vec![unwrap!(parse_condition("id=1602"))];
+        let select = vec!["name".to_string(), "last_version".to_string()];
+        let result = unwrap!(query_table(&table, &conditions, &select));
+        assert_eq!(result.schema.column_names(), select);
+        assert_eq!(result.rows.len(), 1);
+        assert_eq!(
+            result.rows[0].values,
+            vec![
+                Value::String("name_2".to_string()),
+                Value::String("0.1.2".to_string())
+            ]
+        );
+        // no conditions and no select is the whole table
+        let result = unwrap!(query_table(&table, &[], &[]));
+        assert_eq!(result.rows.len(), 5);
+        // wrong column
+        let err = query_table(&table, &[], &["no_column".to_string()]).unwrap_err();
+        assert_eq!(err.to_string(), r#"column "no_column" does not exist"#);
+        assert!(parse_condition("no equal sign").is_err());
+    }
+    #[test]
+    pub fn test_02_csv_round_trip() {
+        let table = unwrap!(read_table(Path::new("sample_data/crates.qvs20")));
+        let mut csv_bytes = vec![];
+        unwrap!(write_csv(&table, &mut csv_bytes));
+        let csv_text = unwrap!(String::from_utf8(csv_bytes.clone()));
+        assert!(csv_text.starts_with("name,description,repository,id,last_version\n"));
+        let table2 = unwrap!(table_from_csv(csv_bytes.as_slice(), "crates"));
+        assert_eq!(table2.schema.column_names(), table.schema.column_names());
+        assert_eq!(table2.rows.len(), table.rows.len());
+        assert_eq!(table2.rows[0].values[3], Value::String("1601".to_string()));
+        // Null and the empty String survive the round trip through csv
+        let table = unwrap!(Table::from_qvs20_with_schema(
+            b"[t]\n[String][String]\n[nullable][]\n[name][note]\n[\\0][a,\"b\"]\n[][]\n[x][\"\"]\n"
+        ));
+        let mut csv_bytes = vec![];
+        unwrap!(write_csv(&table, &mut csv_bytes));
+        let table2 = unwrap!(table_from_csv(csv_bytes.as_slice(), "t"));
+        assert_eq!(table2.rows, table.rows);
+        assert_eq!(
+            table2.schema.columns[0].properties,
+            table.schema.columns[0].properties
+        );
+    }
+    #[test]
+    pub fn test_03_stats() {
+        let table = unwrap!(read_table(Path::new("sample_data/crates.qvs20")));
+        let text = stats_text(&table);
+        assert!(text.starts_with("table: crates table\ncolumns: 5\nrows: 5\n"));
+        assert!(text.contains("id: Integer, distinct: 5, empty: 0, null: 0, max_len: 4\n"));
+    }
+    #[test]
+    pub fn test_04_lenient() {
+        let path = std::env::temp_dir().join("crates_io_database_test_04_lenient.qvs20");
+        let rejected_path = std::env::temp_dir().join("crates_io_database_test_04_rejected.qvs20");
+        let mut bytes = unwrap!(std::fs::read("sample_data/crates.qvs20"));
+        bytes.extend_from_slice(b"[name_6][broken\n");
+        unwrap!(std::fs::write(&path, &bytes));
+        assert!(read_table(&path).is_err());
+        let table = unwrap!(read_table_lenient(&path, Some(&rejected_path)));
+        assert_eq!(table.rows.len(), 5);
+        // the side file has the schema rows and the rejected row
+        let mut expected = unwrap!(table.schema.to_qvs20_bytes());
+        expected.extend_from_slice(b"[name_6][broken\n");
+        assert_eq!(unwrap!(std::fs::read(&rejected_path)), expected);
+        unwrap!(std::fs::remove_file(&path));
+        unwrap!(std::fs::remove_file(&rejected_path));
+    }
+    #[test]
+    pub fn test_05_null() {
+        // Null and the empty String are different in query, stats and csv
+        let table = unwrap!(Table::from_qvs20_with_schema(
+            b"[t]\n[String][Integer]\n[nullable][]\n[name][id]\n[\\0][1]\n[][2]\n[a,\"b\"][3]\n"
+        ));
+        let result = unwrap!(query_table(
+            &table,
+            &[unwrap!(parse_condition("name="))],
+            &[]
+        ));
+        assert_eq!(result.rows.len(), 1);
+        assert_eq!(result.rows[0].values[1], Value::Integer(2));
+        let text = stats_text(&table);
+        assert!(text.contains("name: String, distinct: 3, empty: 1, null: 1, max_len: 5\n"));
+        let mut csv_bytes = vec![];
+        unwrap!(write_csv(&table, &mut csv_bytes));
+        assert_eq!(
+            unwrap!(String::from_utf8(csv_bytes)),
+            "name,id\n,1\n\"\",2\n\"a,\"\"b\"\"\",3\n"
+        );
+    }
+}
@@ -0,0 +1,164 @@
+//! dump_source_mod
+
+// The crates.io database dump https://static.crates.io/db-dump.tar.gz
+// can be read directly from the downloaded archive or from the unpacked folder.
+// Inside the archive all files are in a dated root folder:
+// 2020-06-06-020024/data/crates.csv
+// The archive is streamed once and the csv members are never unpacked to disk.
+
+use anyhow::{anyhow, Context};
+use flate2::read::GzDecoder;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::{Component, Path, PathBuf};
+
+/// where to read the csv files from
+#[derive(Clone, Debug)]
+pub enum DumpSource {
+    /// the unpacked dated folder that contains the data/ folder
+    Dir(PathBuf),
+    /// the downloaded db-dump.tar.gz
+    Archive(PathBuf),
+}
+
+impl DumpSource {
+    /// the archive is recognized by the file extension .tar.gz or .tgz
+    pub fn new(path: &Path) -> DumpSource {
+        let file_name = path.file_name().and_then(|n| n.to_str()).unwrap_or("");
+        if file_name.ends_with(".tar.gz") || file_name.ends_with(".tgz") {
+            DumpSource::Archive(path.to_path_buf())
+        } else {
+            DumpSource::Dir(path.to_path_buf())
+        }
+    }
+
+    /// calls f(csv_file, reader) once for every csv file from the data/ folder.
+    /// The order of the calls is the order in the archive, so f must not depend on it.
+    /// Returns an error if any of the csv files is missing.
+    pub fn for_each_csv<F>(&self, csv_files: &[&str], mut f: F) -> anyhow::Result<()>
+    where
+        F: FnMut(&str, &mut dyn Read) -> anyhow::Result<()>,
+    {
+        match self {
+            DumpSource::Dir(dump_dir) => {
+                for csv_file in csv_files.iter() {
+                    let path = dump_dir.join("data").join(csv_file);
+                    let file = File::open(&path)
+                        .with_context(|| format!("cannot open {}", path.display()))?;
+                    f(csv_file, &mut BufReader::new(file))
+                        .with_context(|| format!("in {}", path.display()))?;
+                }
+            }
+            DumpSource::Archive(archive_path) => {
+                let file = File::open(archive_path)
+                    .with_context(|| format!("cannot open {}", archive_path.display()))?;
+                let mut archive = tar::Archive::new(GzDecoder::new(BufReader::new(file)));
+                let mut found = vec![false; csv_files.len()];
+                // the dated root folder is the first component of the first member
+                let mut root_folder: Option<String> = None;
+                let entries = archive
+                    .entries()
+                    .with_context(|| format!("cannot read {}", archive_path.display()))?;
+                for entry in entries {
+                    let mut entry =
+                        entry.with_context(|| format!("cannot read {}", archive_path.display()))?;
+                    let member_path = entry
+                        .path()
+                        .with_context(|| format!("cannot read {}", archive_path.display()))?
+                        .into_owned();
+                    let components: Vec<&str> = member_path
+                        .components()
+                        .filter_map(|c| match c {
+                            Component::Normal(p) => p.to_str(),
+                            _ => None,
+                        })
+                        .collect();
+                    let root = match components.first() {
+                        Some(p) => *p,
+                        None => continue,
+                    };
+                    match &root_folder {
+                        Some(r) if r != root => continue,
+                        Some(_) => {}
+                        None => root_folder = Some(root.to_string()),
+                    }
+                    // only root/data/file.csv
+                    if components.len() != 3 || components[1] != "data" {
+                        continue;
+                    }
+                    if let Some(i) = csv_files.iter().position(|c| *c == components[2]) {
+                        found[i] = true;
+                        f(csv_files[i], &mut entry).with_context(|| {
+                            format!("in {}:{}", archive_path.display(), member_path.display())
+                        })?;
+                    }
+                }
+                if let Some(i) = found.iter().position(|f| !f) {
+                    return Err(anyhow!(
+                        "{}/data/{} not found in {}",
+                        root_folder.unwrap_or_default(),
+                        csv_files[i],
+                        archive_path.display()
+                    ));
+                }
+            }
+        }
+        //return
+        Ok(())
+    }
+}
+
+/// builds a small db-dump.tar.gz from the unpacked folder, for tests
+#[cfg(test)]
+pub fn build_test_archive(dump_dir: &Path, root_folder: &str, archive_path: &Path) {
+    use unwrap::unwrap;
+    let file = unwrap!(File::create(archive_path));
+    let encoder = flate2::write::GzEncoder::new(file, flate2::Compression::default());
+    let mut builder = tar::Builder::new(encoder);
+    unwrap!(builder.append_dir(root_folder, dump_dir));
+    unwrap!(builder.append_dir_all(format!("{}/data", root_folder), dump_dir.join("data")));
+    unwrap!(unwrap!(builder.into_inner()).finish());
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use unwrap::unwrap;
+    #[test]
+    pub fn test_01_archive_same_as_dir() {
+        let dump_dir = Path::new("sample_data/database");
+        let archive_path = std::env::temp_dir().join("crates_io_database_test_01_dump.tar.gz");
+        build_test_archive(dump_dir, "2020-06-06-020024", &archive_path);
+        let csv_files = ["versions.csv", "crates.csv"];
+        let mut from_dir = vec![];
+        unwrap!(
+            DumpSource::new(dump_dir).for_each_csv(&csv_files, |csv_file, rdr| {
+                let mut text = String::new();
+                rdr.read_to_string(&mut text)?;
+                from_dir.push((csv_file.to_string(), text));
+                Ok(())
+            })
+        );
+        let source = DumpSource::new(&archive_path);
+        assert!(matches!(source, DumpSource::Archive(_)));
+        let mut from_archive = vec![];
+        unwrap!(source.for_each_csv(&csv_files, |csv_file, rdr| {
+            let mut text = String::new();
+            rdr.read_to_string(&mut text)?;
+            from_archive.push((csv_file.to_string(), text));
+            Ok(())
+        }));
+        // the order in the archive can be different
+        from_archive.sort();
+        from_dir.sort();
+        assert_eq!(from_archive, from_dir);
+        // missing member
+        let err = source
+            .for_each_csv(&["no_file.csv"], |_, _| Ok(()))
+            .unwrap_err();
+        unwrap!(std::fs::remove_file(&archive_path));
+        assert!(err
+            .to_string()
+            .starts_with("2020-06-06-020024/data/no_file.csv not found in "));
+    }
+}
@@ -1,91 +1,1165 @@
 //! extract_and_save_mod
 
-//use crate::qvs20_schema_mod;
-//use crate::qvs20_writer_mod;
-//use crate::utils_mod;
+// The crates.io database dump must be already downloaded.
+// https://static.crates.io/db-dump.tar.gz
+// The DumpSource is the archive or the unpacked dated folder that contains the data/ folder.
+
+use crate::dump_source_mod::DumpSource;
+use crate::reverse_dependencies_mod::*;
+use crate::utils_mod;
+use qvs20::qvs20_properties_mod::RowChecker;
+use qvs20::qvs20_schema_mod::*;
+use qvs20::qvs20_serde_mod;
+use qvs20::qvs20_table_mod::*;
+use qvs20::qvs20_writer_mod;
 
 #[allow(unused_imports)]
 use ansi_term::Colour::{Green, Yellow};
+use anyhow::{anyhow, Context};
 use serde_derive::{Deserialize, Serialize};
-//use std::fs::File;
-//use unwrap::unwrap;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
 
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct CrateData {
     pub name: String,
-    pub description: String,
-    pub repository: String,
+    /// the empty csv field is None
+    pub description: Option<String>,
+    pub repository: Option<String>,
     pub id: String,
 }
 
+/// one row of the crates table, the schema is derived from the fields
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct CrateRow {
+    pub name: String,
+    pub description: Option<String>,
+    pub repository: Option<String>,
+    pub id: i64,
+    /// None for the crates without any non-yanked version
+    pub last_version: Option<String>,
+}
+
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct VersionData {
+    pub id: String,
     pub crate_id: String,
     pub num: String,
     pub yanked: String,
     pub created_at: String,
-    pub version_for_sorting: Option<String>,
+    /// parsed num, it is not in the csv file
+    #[serde(skip)]
+    pub version: Option<utils_mod::Version>,
+}
+
+/// a version that is not semver, it cannot be the last version of the crate
+#[derive(Debug)]
+pub struct SkippedVersion {
+    pub crate_id: String,
+    pub num: String,
+    pub error: utils_mod::SemverError,
+}
+
+/// one selected column of a dump table
+pub struct ColumnConfig {
+    /// column name in the csv file and in the qvs20 table
+    pub name: &'static str,
+    pub data_type: DataType,
+    /// additional properties of the qvs20 column
+    pub properties: &'static str,
+}
+
+/// the extraction config of one dump table
+pub struct TableConfig {
+    /// csv file in the data/ folder of the dump
+    pub csv_file: &'static str,
+    /// qvs20 table name, also the output file name
+    pub table_name: &'static str,
+    /// minimal column selection
+    pub columns: &'static [ColumnConfig],
 }
-/*
-pub fn extract_and_save() {
-    let versions = versions_non_yanked();
-    let path = "database/data/crates.csv";
+
+/// shortcut for the configs
+const fn column(name: &'static str, data_type: DataType) -> ColumnConfig {
+    ColumnConfig {
+        name,
+        data_type,
+        properties: "",
+    }
+}
+
+/// shortcut for the id columns
+const fn primary_key(name: &'static str) -> ColumnConfig {
+    ColumnConfig {
+        name,
+        data_type: DataType::Integer,
+        properties: "primary_key",
+    }
+}
+
+/// the dump tables that are extracted with the same pipeline
+/// crates and versions have their own extractor for the last_version
+pub const TABLE_CONFIGS: &[TableConfig] = &[
+    TableConfig {
+        csv_file: "versions.csv",
+        table_name: "versions",
+        columns: &[
+            primary_key("id"),
+            column("crate_id", DataType::Integer),
+            column("num", DataType::String),
+            column("yanked", DataType::Bool),
+        ],
+    },
+    TableConfig {
+        csv_file: "dependencies.csv",
+        table_name: "dependencies",
+        columns: &[
+            primary_key("id"),
+            column("version_id", DataType::Integer),
+            column("crate_id", DataType::Integer),
+            column("req", DataType::String),
+            // 0 normal, 1 build, 2 dev
+            ColumnConfig {
+                name: "kind",
+                data_type: DataType::Integer,
+                properties: "enum=0|1|2",
+            },
+            column("optional", DataType::Bool),
+            column("default_features", DataType::Bool),
+            // the dependencies without a target platform have null
+            ColumnConfig {
+                name: "target",
+                data_type: DataType::String,
+                properties: "nullable",
+            },
+        ],
+    },
+    TableConfig {
+        csv_file: "categories.csv",
+        table_name: "categories",
+        columns: &[
+            primary_key("id"),
+            column("category", DataType::String),
+            column("slug", DataType::String),
+            column("description", DataType::String),
+        ],
+    },
+    TableConfig {
+        csv_file: "crates_categories.csv",
+        table_name: "crates_categories",
+        columns: &[
+            column("crate_id", DataType::Integer),
+            column("category_id", DataType::Integer),
+        ],
+    },
+    TableConfig {
+        csv_file: "keywords.csv",
+        table_name: "keywords",
+        columns: &[
+            primary_key("id"),
+            column("keyword", DataType::String),
+            column("crates_cnt", DataType::Integer),
+        ],
+    },
+    TableConfig {
+        csv_file: "crates_keywords.csv",
+        table_name: "crates_keywords",
+        columns: &[
+            column("crate_id", DataType::Integer),
+            column("keyword_id", DataType::Integer),
+        ],
+    },
+    TableConfig {
+        csv_file: "crate_owners.csv",
+        table_name: "crate_owners",
+        columns: &[
+            column("crate_id", DataType::Integer),
+            column("owner_id", DataType::Integer),
+            // 0 user, 1 team
+            column("owner_kind", DataType::Integer),
+        ],
+    },
+    TableConfig {
+        csv_file: "users.csv",
+        table_name: "users",
+        columns: &[
+            primary_key("id"),
+            column("gh_login", DataType::String),
+            column("name", DataType::String),
+        ],
+    },
+    TableConfig {
+        csv_file: "teams.csv",
+        table_name: "teams",
+        columns: &[
+            primary_key("id"),
+            column("login", DataType::String),
+            column("name", DataType::String),
+        ],
+    },
+    TableConfig {
+        csv_file: "version_downloads.csv",
+        table_name: "version_downloads",
+        columns: &[
+            column("version_id", DataType::Integer),
+            column("date", DataType::Date),
+            column("downloads", DataType::Integer),
+        ],
+    },
+];
+
+/// the versions of every crate embedded as a nested table in the crates table
+/// crate_id is only for grouping, it is not in the nested table
+const EMBEDDED_VERSIONS_CONFIG: TableConfig = TableConfig {
+    csv_file: "versions.csv",
+    table_name: "versions",
+    columns: &[
+        column("crate_id", DataType::Integer),
+        column("num", DataType::String),
+        column("yanked", DataType::Bool),
+        column("created_at", DataType::DateTime),
+    ],
+};
+
+/// the row delimiter of the nested versions tables, different from the crates table
+const EMBEDDED_ROW_DELIMITER: &[u8] = b"1";
+
+/// the nested versions tables: one schema and the rows of every crate
+struct EmbeddedVersions {
+    schema: Schema,
+    /// crate id -> rows
+    rows_by_crate: HashMap<String, Vec<Row>>,
+}
+
+/// one pass over the dump: the crates table and the selected dump tables.
+/// crates.qvs20 is written to output_path, the dump tables to output_dir/table_name.qvs20
+/// exclude_prerelease: the last_version is the last stable version
+/// embed_versions: the crates table has the column versions with a nested table
+/// The rows of the dump tables are written while the csv is read, they are never all in memory.
+/// Returns the versions that are not semver, they do not stop the extraction.
+pub fn extract_and_save(
+    source: &DumpSource,
+    output_path: &Path,
+    exclude_prerelease: bool,
+    embed_versions: bool,
+    output_dir: &Path,
+    table_names: &[String],
+) -> anyhow::Result<Vec<SkippedVersion>> {
+    // check all the names before the long extraction
+    let (configs, with_reverse_dependencies) = table_configs(table_names)?;
+    if !configs.is_empty() || with_reverse_dependencies {
+        std::fs::create_dir_all(output_dir)
+            .with_context(|| format!("cannot create {}", output_dir.display()))?;
+    }
+    let mut sinks: Vec<FileSink> = configs
+        .iter()
+        .map(|c| FileSink::new(output_dir.join(format!("{}.qvs20", c.table_name))))
+        .collect();
+    let (crates_table, reverse_table, skipped) = extract_to(
+        source,
+        exclude_prerelease,
+        embed_versions,
+        &configs,
+        with_reverse_dependencies,
+        &mut sinks,
+    )?;
+    save_table(&crates_table, output_path)?;
+    for sink in sinks {
+        let path = sink.path.clone();
+        if let Some(rows) = sink.finish()? {
+            print_saved(rows, &path);
+        }
+    }
+    if let Some(table) = reverse_table {
+        save_table(
+            &table,
+            &output_dir.join(format!("{}.qvs20", table.schema.table_name)),
+        )?;
+    }
+    //return
+    Ok(skipped)
+}
+
+/// one pass over the dump: the crates table and the selected dump tables
+/// are written in one bundle file, the crates table is the first.
+/// The index needs the number of rows before the tables,
+/// so the dump tables are written to temporary files next to the bundle and then copied.
+/// Returns the versions that are not semver, they do not stop the extraction.
+pub fn extract_and_save_bundle(
+    source: &DumpSource,
+    bundle_path: &Path,
+    exclude_prerelease: bool,
+    embed_versions: bool,
+    table_names: &[String],
+) -> anyhow::Result<Vec<SkippedVersion>> {
+    // check all the names before the long extraction
+    let (configs, with_reverse_dependencies) = table_configs(table_names)?;
+    let mut sinks: Vec<FileSink> = configs
+        .iter()
+        .map(|c| {
+            let mut file_name = bundle_path.file_name().unwrap_or_default().to_os_string();
+            file_name.push(format!(".{}.tmp", c.table_name));
+            FileSink::new(bundle_path.with_file_name(file_name))
+        })
+        .collect();
+    let temp_paths: Vec<PathBuf> = sinks.iter().map(|s| s.path.clone()).collect();
+    let result = (|| -> anyhow::Result<(usize, Vec<SkippedVersion>)> {
+        let (crates_table, reverse_table, skipped) = extract_to(
+            source,
+            exclude_prerelease,
+            embed_versions,
+            &configs,
+            with_reverse_dependencies,
+            &mut sinks,
+        )?;
+        let mut parts = vec![];
+        for (sink, config) in sinks.into_iter().zip(configs.iter()) {
+            let path = sink.path.clone();
+            if let Some(rows) = sink.finish()? {
+                parts.push((config.table_name, rows, path));
+            }
+        }
+        let tables_count =
+            write_bundle(bundle_path, &crates_table, &parts, reverse_table.as_ref())?;
+        Ok((tables_count, skipped))
+    })();
+    for path in temp_paths.iter() {
+        // the table was not started if the file does not exist
+        let _ = std::fs::remove_file(path);
+    }
+    let (tables_count, skipped) = result?;
+    println!(
+        "{} {} tables to {}",
+        Green.paint("Saved"),
+        tables_count,
+        Yellow.paint(bundle_path.display().to_string())
+    );
+    //return
+    Ok(skipped)
+}
+
+/// write the bundle: the index, the crates table, the written dump tables and the reverse dependencies
+/// returns the number of tables
+fn write_bundle(
+    bundle_path: &Path,
+    crates_table: &Table,
+    parts: &[(&str, usize, PathBuf)],
+    reverse_table: Option<&Table>,
+) -> anyhow::Result<usize> {
+    let mut index = vec![(
+        crates_table.schema.table_name.as_str(),
+        crates_table.rows.len(),
+    )];
+    for (table_name, rows, _path) in parts.iter() {
+        index.push((table_name, *rows));
+    }
+    if let Some(table) = reverse_table {
+        index.push((table.schema.table_name.as_str(), table.rows.len()));
+    }
+    let file = File::create(bundle_path)
+        .with_context(|| format!("cannot create {}", bundle_path.display()))?;
+    let write = || -> std::io::Result<()> {
+        let mut wtr = qvs20_writer_mod::Writer::new(BufWriter::new(file));
+        wtr.write_bundle_index(&index)?;
+        wtr.write_table(crates_table)?;
+        let mut wtr = wtr.into_inner();
+        // the written tables are complete qvs20 tables
+        for (_table_name, _rows, path) in parts.iter() {
+            std::io::copy(&mut File::open(path)?, &mut wtr)?;
+        }
+        let mut wtr = qvs20_writer_mod::Writer::new(wtr);
+        if let Some(table) = reverse_table {
+            wtr.write_table(table)?;
+        }
+        wtr.into_inner().flush()
+    };
+    write().with_context(|| format!("cannot write {}", bundle_path.display()))?;
+    //return
+    Ok(index.len())
+}
+
+/// the configs of the table names, reverse_dependencies has no config
+fn table_configs(table_names: &[String]) -> anyhow::Result<(Vec<&'static TableConfig>, bool)> {
+    let with_reverse_dependencies = table_names.iter().any(|n| n == REVERSE_DEPENDENCIES);
+    let configs = table_names
+        .iter()
+        .filter(|n| *n != REVERSE_DEPENDENCIES)
+        .map(|n| table_config(n))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    //return
+    Ok((configs, with_reverse_dependencies))
+}
+
+/// write the qvs20 file
+fn save_table(table: &Table, output_path: &Path) -> anyhow::Result<()> {
+    let file = File::create(output_path)
+        .with_context(|| format!("cannot create {}", output_path.display()))?;
+    let mut wtr = qvs20_writer_mod::Writer::new(BufWriter::new(file));
+    wtr.write_table(table)
+        .with_context(|| format!("cannot write {}", output_path.display()))?;
+    print_saved(table.rows.len(), output_path);
+    //return
+    Ok(())
+}
+
+fn print_saved(rows: usize, output_path: &Path) {
+    println!(
+        "{} {} rows to {}",
+        Green.paint("Saved"),
+        rows,
+        Yellow.paint(output_path.display().to_string())
+    );
+}
+
+/// the output of one dump table: the rows come one by one while the csv is read
+pub trait TableSink {
+    /// once, before the rows
+    fn start(&mut self, schema: &Schema) -> anyhow::Result<()>;
+    fn push(&mut self, row: Row) -> anyhow::Result<()>;
+}
+
+/// the table in memory, None until the csv is read
+impl TableSink for Option<Table> {
+    fn start(&mut self, schema: &Schema) -> anyhow::Result<()> {
+        *self = Some(Table {
+            schema: schema.clone(),
+            rows: vec![],
+        });
+        Ok(())
+    }
+    fn push(&mut self, row: Row) -> anyhow::Result<()> {
+        match self {
+            Some(table) => table.rows.push(row),
+            None => return Err(anyhow!("the table is not started")),
+        }
+        Ok(())
+    }
+}
+
+/// the same rows to both sinks
+struct BothSinks<'a>(&'a mut dyn TableSink, &'a mut dyn TableSink);
+
+impl TableSink for BothSinks<'_> {
+    fn start(&mut self, schema: &Schema) -> anyhow::Result<()> {
+        self.0.start(schema)?;
+        self.1.start(schema)
+    }
+    fn push(&mut self, row: Row) -> anyhow::Result<()> {
+        self.0.push(row.clone())?;
+        self.1.push(row)
+    }
+}
+
+/// the qvs20 file that is written row by row, checked like in Writer::write_table()
+struct FileSink {
+    path: PathBuf,
+    /// the file is created in start()
+    output: Option<(
+        qvs20_writer_mod::Writer<BufWriter<File>>,
+        RowChecker,
+        Schema,
+    )>,
+    rows: usize,
+}
+
+impl FileSink {
+    fn new(path: PathBuf) -> FileSink {
+        FileSink {
+            path,
+            output: None,
+            rows: 0,
+        }
+    }
+    /// flush the file, returns the number of rows or None if the table was never started
+    fn finish(self) -> anyhow::Result<Option<usize>> {
+        let FileSink { path, output, rows } = self;
+        match output {
+            Some((wtr, _checker, _schema)) => {
+                wtr.into_inner()
+                    .flush()
+                    .with_context(|| format!("cannot write {}", path.display()))?;
+                Ok(Some(rows))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+impl TableSink for FileSink {
+    fn start(&mut self, schema: &Schema) -> anyhow::Result<()> {
+        let file = File::create(&self.path)
+            .with_context(|| format!("cannot create {}", self.path.display()))?;
+        let mut wtr = qvs20_writer_mod::Writer::new(BufWriter::new(file));
+        wtr.write_schema(schema)
+            .with_context(|| format!("cannot write {}", self.path.display()))?;
+        self.output = Some((wtr, RowChecker::new(schema), schema.clone()));
+        Ok(())
+    }
+    fn push(&mut self, row: Row) -> anyhow::Result<()> {
+        let (wtr, checker, schema) = match &mut self.output {
+            Some(output) => output,
+            None => return Err(anyhow!("the table is not started")),
+        };
+        self.rows += 1;
+        checker.check_row(self.rows, &row)?;
+        wtr.write_row(self.rows, &row, schema)
+            .with_context(|| format!("cannot write {}", self.path.display()))?;
+        Ok(())
+    }
+}
+
+/// one pass over the dump in memory: the crates table and a table for every config.
+/// The reverse dependencies table is the last one, if requested.
+/// Only for the tests, the commands write the rows while the csv is read.
+#[cfg(test)]
+pub fn extract(
+    source: &DumpSource,
+    exclude_prerelease: bool,
+    embed_versions: bool,
+    configs: &[&TableConfig],
+    with_reverse_dependencies: bool,
+) -> anyhow::Result<(Table, Vec<Table>)> {
+    let mut tables: Vec<Option<Table>> = vec![None; configs.len()];
+    let (crates_table, reverse_table, _skipped) = extract_to(
+        source,
+        exclude_prerelease,
+        embed_versions,
+        configs,
+        with_reverse_dependencies,
+        &mut tables,
+    )?;
+    // for_each_csv returns an error if a file is missing
+    let mut tables: Vec<Table> = tables.into_iter().flatten().collect();
+    tables.extend(reverse_table);
+    //return
+    Ok((crates_table, tables))
+}
+
+/// one pass over the dump: the rows of every config go to its sink while the csv is read.
+/// Returns the crates table, the reverse dependencies table, if requested,
+/// and the versions that are not semver.
+/// The csv files come in the order of the archive,
+/// so the crates table is built after all the files are read.
+fn extract_to<S: TableSink>(
+    source: &DumpSource,
+    exclude_prerelease: bool,
+    embed_versions: bool,
+    configs: &[&TableConfig],
+    with_reverse_dependencies: bool,
+    sinks: &mut [S],
+) -> anyhow::Result<(Table, Option<Table>, Vec<SkippedVersion>)> {
+    // the reverse dependencies need the dependencies table in memory
+    let dependencies_config = table_config("dependencies")?;
+    let mut dependencies: Option<Table> = None;
+    let mut csv_files = vec!["versions.csv", "crates.csv"];
+    if with_reverse_dependencies {
+        csv_files.push(dependencies_config.csv_file);
+    }
+    for config in configs.iter() {
+        if !csv_files.contains(&config.csv_file) {
+            csv_files.push(config.csv_file);
+        }
+    }
+    let mut crates = vec![];
+    let mut last_versions = HashMap::new();
+    let mut embedded_versions = None;
+    let mut skipped = vec![];
+    source.for_each_csv(&csv_files, |csv_file, rdr| {
+        let position = configs.iter().position(|c| c.csv_file == csv_file);
+        match csv_file {
+            "versions.csv" => {
+                // the same csv for the last versions, the versions table and the embedded versions
+                let versions = position.map(|i| (configs[i], &mut sinks[i] as &mut dyn TableSink));
+                let result = versions_from_csv(rdr, exclude_prerelease, versions, embed_versions)?;
+                last_versions = result.last_versions;
+                embedded_versions = result.embedded_versions;
+                skipped = result.skipped;
+            }
+            "crates.csv" => crates = crates_from_csv(rdr)?,
+            _ if with_reverse_dependencies && csv_file == dependencies_config.csv_file => {
+                match position {
+                    Some(i) => table_from_csv(
+                        rdr,
+                        configs[i],
+                        &mut BothSinks(&mut sinks[i], &mut dependencies),
+                    )?,
+                    None => table_from_csv(rdr, dependencies_config, &mut dependencies)?,
+                }
+            }
+            _ => {
+                if let Some(i) = position {
+                    table_from_csv(rdr, configs[i], &mut sinks[i])?;
+                }
+            }
+        }
+        Ok(())
+    })?;
+    let reverse_table = match (with_reverse_dependencies, &dependencies) {
+        (false, _) => None,
+        (true, Some(dependencies)) => Some(reverse_dependencies_table(
+            &crates,
+            &last_versions,
+            dependencies,
+        )?),
+        (true, None) => return Err(anyhow!("the dependencies table is missing")),
+    };
+    let crates_table = crates_table(crates, &last_versions, embedded_versions.as_ref())?;
+    //return
+    Ok((crates_table, reverse_table, skipped))
+}
+
+/// read crates.csv
+fn crates_from_csv(rdr: &mut dyn Read) -> anyhow::Result<Vec<CrateData>> {
     // crates.csv:
     // created_at,description,documentation,downloads,homepage,id,max_upload_size,name,readme,repository,textsearchable_index_col,updated_at
-    let file = unwrap!(File::open(path));
     // Build the CSV reader and iterate over each record.
-    let mut rdr = csv::Reader::from_reader(file);
-    // prepare schema manually
-    let schema = qvs20_schema_mod::Schema::new_from_str(
-        r#"[crates]
-[String][String][String][String][String]
-[][][][][]
-[name][description][repository][id][last_version]
-"#,
-    );
-    // [Table] [versions[String]1[]1[version]]
-    let mut wtr = qvs20_writer_mod::Writer::new(schema);
+    let mut rdr = csv::Reader::from_reader(rdr);
+    let mut crates = vec![];
     for result in rdr.deserialize() {
-        // The iterator yields Result<StringRecord, Error>, so we check the
+        // The iterator yields Result<CrateData, Error>, so we check the
         // error here.
-        let crate_data: CrateData = unwrap!(result);
+        let crate_data: CrateData = result?;
+        crates.push(crate_data);
+    }
+    //return
+    Ok(crates)
+}
+
+/// join the crates with the last versions
+fn crates_table(
+    crates: Vec<CrateData>,
+    last_versions: &HashMap<String, VersionData>,
+    embedded_versions: Option<&EmbeddedVersions>,
+) -> anyhow::Result<Table> {
+    let mut crate_rows = vec![];
+    for crate_data in crates {
+        let id: i64 = crate_data
+            .id
+            .parse()
+            .with_context(|| format!("crate id {:?} in crates.csv", crate_data.id))?;
+        let last_version = last_versions.get(&crate_data.id).map(|v| v.num.clone());
+        crate_rows.push(CrateRow {
+            name: crate_data.name,
+            description: crate_data.description,
+            repository: crate_data.repository,
+            id,
+            last_version,
+        });
+    }
+    let mut table = if crate_rows.is_empty() {
+        // without rows the schema is from the struct fields
+        Table {
+            schema: qvs20_serde_mod::schema_of::<CrateRow>("crates")?,
+            rows: vec![],
+        }
+    } else {
+        qvs20_serde_mod::to_table("crates", &crate_rows).context("from crates.csv")?
+    };
+    // the serde schema has no properties
+    for column in table.schema.columns.iter_mut() {
+        column.properties = match column.name.as_str() {
+            "name" => "unique".parse().map_err(anyhow::Error::msg)?,
+            "id" => "primary_key".parse().map_err(anyhow::Error::msg)?,
+            "description" | "repository" | "last_version" => {
+                "nullable".parse().map_err(anyhow::Error::msg)?
+            }
+            _ => continue,
+        };
+    }
+    if let Some(embedded) = embedded_versions {
+        let id_pos = table
+            .schema
+            .column_position("id")
+            .ok_or_else(|| anyhow!("the crates table has no column id"))?;
+        table
+            .schema
+            .columns
+            .push(Column::new("versions", DataType::Table));
+        for row in table.rows.iter_mut() {
+            let id = row.values[id_pos].to_string();
+            let rows = embedded.rows_by_crate.get(&id).cloned().unwrap_or_default();
+            row.values.push(Value::Table(Box::new(Table {
+                schema: embedded.schema.clone(),
+                rows,
+            })));
+        }
+    }
+    //return
+    Ok(table)
+}
+
+/// the config by table name
+pub fn table_config(table_name: &str) -> anyhow::Result<&'static TableConfig> {
+    TABLE_CONFIGS
+        .iter()
+        .find(|c| c.table_name == table_name)
+        .ok_or_else(|| {
+            let mut names: Vec<&str> = TABLE_CONFIGS.iter().map(|c| c.table_name).collect();
+            names.push(REVERSE_DEPENDENCIES);
+            anyhow!(
+                "unknown dump table {:?}, expected one of: {}",
+                table_name,
+                names.join(", ")
+            )
+        })
+}
 
-        wtr.push_string(&crate_data.name);
-        wtr.push_string(&crate_data.description);
-        wtr.push_string(&crate_data.repository);
-        wtr.push_string(&crate_data.id);
-        // find first version with this crate_id
-        if let Some(vers) = versions.iter().find(|&x| &x.crate_id == &crate_data.id){
-            //last_version
-            wtr.push_string(&vers.num);
+/// the selected columns of a csv file and their position in the csv
+struct CsvColumns {
+    schema: Schema,
+    positions: Vec<usize>,
+}
+
+impl CsvColumns {
+    fn new(headers: &csv::StringRecord, config: &TableConfig) -> anyhow::Result<CsvColumns> {
+        let mut positions = vec![];
+        for column in config.columns.iter() {
+            let pos = headers
+                .iter()
+                .position(|h| h == column.name)
+                .ok_or_else(|| anyhow!("column {:?} not found", column.name))?;
+            positions.push(pos);
         }
-        else{
-            //last_version
-            wtr.push_string("0.0.0");
+        let schema = Schema {
+            table_name: config.table_name.to_string(),
+            columns: config
+                .columns
+                .iter()
+                .map(|c| {
+                    Ok(Column {
+                        properties: c
+                            .properties
+                            .parse()
+                            .map_err(|e| anyhow!("column {}: {}", c.name, e))?,
+                        ..Column::new(c.name, c.data_type.clone())
+                    })
+                })
+                .collect::<anyhow::Result<_>>()?,
+            ..Default::default()
+        };
+        //return
+        Ok(CsvColumns { schema, positions })
+    }
+
+    /// the row with the selected columns, record_number is 1-based
+    fn row(&self, record: &csv::StringRecord, record_number: usize) -> anyhow::Result<Row> {
+        let mut values = Vec::with_capacity(self.positions.len());
+        for (column, &pos) in self.schema.columns.iter().zip(self.positions.iter()) {
+            let text = record.get(pos).unwrap_or("");
+            let value = value_from_csv(text, column)
+                .with_context(|| format!("column {} in record {}", column.name, record_number))?;
+            values.push(value);
         }
+        //return
+        Ok(Row { values })
     }
-    //write vec_crate_data to qvs20 string and then to file
-    unwrap!(std::fs::write("crates.qvs20", wtr.bytes_for_file()));
 }
 
-pub fn versions_non_yanked()->Vec<VersionData>{
-    let mut versions=vec![];
-    let path = "database/data/versions.csv";
+/// the pipeline for all the configs: read the csv and keep only the selected columns
+fn table_from_csv(
+    rdr: &mut dyn Read,
+    config: &TableConfig,
+    sink: &mut dyn TableSink,
+) -> anyhow::Result<()> {
+    let mut rdr = csv::Reader::from_reader(rdr);
+    let columns = CsvColumns::new(rdr.headers()?, config)?;
+    sink.start(&columns.schema)?;
+    for (i, result) in rdr.records().enumerate() {
+        sink.push(columns.row(&result?, i + 1)?)?;
+    }
+    //return
+    Ok(())
+}
+
+/// the postgres csv export writes bool as t and f
+/// the empty field in a nullable column is null
+fn value_from_csv(text: &str, column: &Column) -> Result<Value, Qvs20ErrorTable> {
+    match (&column.data_type, text) {
+        (_, "") if column.properties.is_nullable() => Ok(Value::Null),
+        (DataType::Bool, "t") => Ok(Value::Bool(true)),
+        (DataType::Bool, "f") => Ok(Value::Bool(false)),
+        (data_type, _) => Value::from_text(text.to_string(), data_type),
+    }
+}
+
+/// the result of versions_from_csv()
+struct VersionsFromCsv {
+    last_versions: HashMap<String, VersionData>,
+    embedded_versions: Option<EmbeddedVersions>,
+    /// the versions that are not semver
+    skipped: Vec<SkippedVersion>,
+}
+
+/// read versions.csv once, record by record:
+/// for every crate_id the non-yanked version with the biggest version number by SemVer precedence,
+/// the rows of the versions table if requested
+/// and for every crate id the rows of the nested versions table if embed_versions
+fn versions_from_csv(
+    rdr: &mut dyn Read,
+    exclude_prerelease: bool,
+    versions: Option<(&TableConfig, &mut dyn TableSink)>,
+    embed_versions: bool,
+) -> anyhow::Result<VersionsFromCsv> {
+    let mut last_versions: HashMap<String, VersionData> = HashMap::new();
     // versions.csv:
     // crate_id,crate_size,created_at,downloads,features,id,license,num,published_by,updated_at,yanked
-    let file = unwrap!(File::open(path));
     // Build the CSV reader and iterate over each record.
-    let mut rdr = csv::Reader::from_reader(file);
-    for result in rdr.deserialize() {
-        let mut version_data: VersionData = unwrap!(result);
-        if &version_data.yanked == "f"{
-            version_data.version_for_sorting = Some( utils_mod::version_for_sorting(&version_data.num));
-            versions.push(version_data);
+    let mut rdr = csv::Reader::from_reader(rdr);
+    let headers = rdr.headers()?.clone();
+    let mut versions = match versions {
+        Some((config, sink)) => {
+            let columns = CsvColumns::new(&headers, config)?;
+            sink.start(&columns.schema)?;
+            Some((columns, sink))
+        }
+        None => None,
+    };
+    let embedded_columns = if embed_versions {
+        Some(CsvColumns::new(&headers, &EMBEDDED_VERSIONS_CONFIG)?)
+    } else {
+        None
+    };
+    let mut rows_by_crate: HashMap<String, Vec<Row>> = HashMap::new();
+    let mut skipped = vec![];
+    for (i, result) in rdr.records().enumerate() {
+        let record = result?;
+        if let Some((columns, sink)) = &mut versions {
+            sink.push(columns.row(&record, i + 1)?)?;
+        }
+        if let Some(columns) = &embedded_columns {
+            let mut row = columns.row(&record, i + 1)?;
+            let crate_id = row.values.remove(0).to_string();
+            rows_by_crate.entry(crate_id).or_default().push(row);
+        }
+        let mut version_data: VersionData = record.deserialize(Some(&headers))?;
+        if &version_data.yanked == "f" {
+            // a version that is not semver cannot be compared, it does not stop the extraction
+            let version = match utils_mod::parse_semver(&version_data.num) {
+                Ok(version) => version,
+                Err(error) => {
+                    skipped.push(SkippedVersion {
+                        crate_id: version_data.crate_id,
+                        num: version_data.num,
+                        error,
+                    });
+                    continue;
+                }
+            };
+            if exclude_prerelease && version.is_prerelease() {
+                continue;
+            }
+            version_data.version = Some(version);
+            let is_bigger = match last_versions.get(&version_data.crate_id) {
+                Some(last) => version_data.version > last.version,
+                None => true,
+            };
+            if is_bigger {
+                last_versions.insert(version_data.crate_id.clone(), version_data);
+            }
         }
     }
-    versions.sort_by(|a, b| b.version_for_sorting.partial_cmp(&a.version_for_sorting).unwrap() );
-    versions.sort_by(|a, b| a.crate_id.partial_cmp(&b.crate_id).unwrap() );
+    let embedded_versions = embedded_columns.map(|columns| {
+        let mut schema = columns.schema;
+        schema.columns.remove(0);
+        schema.row_delimiter = EMBEDDED_ROW_DELIMITER.to_vec();
+        EmbeddedVersions {
+            schema,
+            rows_by_crate,
+        }
+    });
     //return
-    versions
+    Ok(VersionsFromCsv {
+        last_versions,
+        embedded_versions,
+        skipped,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use qvs20::qvs20_bundle_mod;
+    use unwrap::unwrap;
+    #[test]
+    pub fn test_01_last_versions() {
+        let path = "sample_data/database/data/versions.csv";
+        let VersionsFromCsv {
+            last_versions,
+            skipped,
+            ..
+        } = unwrap!(versions_from_csv(
+            &mut unwrap!(File::open(path)),
+            false,
+            None,
+            false
+        ));
+        assert!(skipped.is_empty());
+        // 0.10.0 is bigger than 0.9.0 and 1.0.0 is yanked
+        assert_eq!(last_versions["1601"].num, "0.10.0");
+        // pre-release 2.1.0-beta.1 is bigger than 2.0.0
+        assert_eq!(last_versions["1602"].num, "2.1.0-beta.1");
+        // all versions are yanked
+        assert!(!last_versions.contains_key("1604"));
+
+        let last_versions = unwrap!(versions_from_csv(
+            &mut unwrap!(File::open(path)),
+            true,
+            None,
+            false
+        ))
+        .last_versions;
+        assert_eq!(last_versions["1602"].num, "2.0.0");
+        // the version that is not semver is skipped and does not stop the extraction
+        let csv = "crate_id,crate_size,created_at,downloads,features,id,license,num,published_by,updated_at,yanked
+1601,100,2015-05-13 02:52:11.011947,10,{},1,MIT,0.1.1,,2015-05-13 02:52:11.011947,f
+1601,100,2015-05-13 02:52:11.011947,10,{},2,MIT,0.2,,2015-05-13 02:52:11.011947,f
+";
+        let VersionsFromCsv {
+            last_versions,
+            skipped,
+            ..
+        } = unwrap!(versions_from_csv(&mut csv.as_bytes(), false, None, false));
+        assert_eq!(last_versions["1601"].num, "0.1.1");
+        assert_eq!(skipped.len(), 1);
+        assert_eq!(
+            (skipped[0].crate_id.as_str(), skipped[0].num.as_str()),
+            ("1601", "0.2")
+        );
+        assert_eq!(
+            skipped[0].error.to_string(),
+            r#"Error: Version "0.2" must have major.minor.patch"#
+        );
+    }
+    #[test]
+    pub fn test_02_extract_and_save() {
+        let output_path = std::env::temp_dir().join("crates_io_database_test_02_extract.qvs20");
+        unwrap!(extract_and_save(
+            &DumpSource::new(Path::new("sample_data/database")),
+            &output_path,
+            true,
+            false,
+            Path::new("."),
+            &[]
+        ));
+        let bytes = unwrap!(std::fs::read(&output_path));
+        unwrap!(std::fs::remove_file(&output_path));
+        let table = unwrap!(Table::from_qvs20_with_schema(&bytes));
+        assert_eq!(table.schema.table_name, "crates");
+        assert_eq!(table.rows.len(), 4);
+        assert_eq!(
+            table.rows[0].values,
+            vec![
+                Value::String("name_1".to_string()),
+                Value::String("A small git [library]".to_string()),
+                Value::String("https://github.com/1".to_string()),
+                Value::Integer(1601),
+                Value::String("0.10.0".to_string()),
+            ]
+        );
+        assert_eq!(
+            table.rows[1].values[1],
+            Value::String("Multi\nline description".to_string())
+        );
+        // the crates without versions have null last_version
+        assert_eq!(table.rows[2].values[4], Value::Null);
+        assert_eq!(table.rows[3].values[4], Value::Null);
+        assert!(bytes.starts_with(
+            b"[crates]\n[String][String][String][Integer][String]\n[unique][nullable][nullable][primary_key][nullable]\n"
+        ));
+        // deserialize back into structs by column name
+        let crate_rows: Vec<CrateRow> = unwrap!(qvs20_serde_mod::from_table(&table));
+        assert_eq!(crate_rows[0].id, 1601);
+        assert_eq!(crate_rows[0].last_version, Some("0.10.0".to_string()));
+        assert_eq!(crate_rows[2].last_version, None);
+    }
+    #[test]
+    pub fn test_03_missing_dump() {
+        let err = extract(
+            &DumpSource::new(Path::new("sample_data/no_such_dump")),
+            false,
+            false,
+            &[],
+            false,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "cannot open sample_data/no_such_dump/data/versions.csv"
+        );
+    }
+    #[test]
+    pub fn test_04_table_configs() {
+        let source = DumpSource::new(Path::new("sample_data/database"));
+        // every config works with the sample dump
+        let configs: Vec<&TableConfig> = TABLE_CONFIGS.iter().collect();
+        let (_crates_table, tables) = unwrap!(extract(&source, false, false, &configs, false));
+        assert_eq!(tables.len(), TABLE_CONFIGS.len());
+        for (table, config) in tables.iter().zip(TABLE_CONFIGS.iter()) {
+            assert_eq!(table.schema.table_name, config.table_name);
+            assert_eq!(table.schema.columns.len(), config.columns.len());
+            assert!(!table.rows.is_empty(), "{}", config.table_name);
+        }
+        assert_eq!(
+            tables[1].rows[1].values,
+            vec![
+                Value::Integer(2),
+                Value::Integer(6),
+                Value::Integer(1601),
+                Value::String("~0.9.0".to_string()),
+                Value::Integer(2),
+                Value::Bool(true),
+                Value::Bool(false),
+                Value::String("cfg(unix)".to_string()),
+            ]
+        );
+        assert_eq!(tables[9].rows[0].values[1].to_string(), "2020-06-01");
+        assert_eq!(tables[0].rows[3].values[3], Value::Bool(true));
+        assert_eq!(
+            unwrap!(table_config("no_table").err()).to_string(),
+            "unknown dump table \"no_table\", expected one of: versions, dependencies, categories, \
+crates_categories, keywords, crates_keywords, crate_owners, users, teams, version_downloads, \
+reverse_dependencies"
+        );
+    }
+    #[test]
+    pub fn test_05_extract_tables_and_save() {
+        let output_dir = std::env::temp_dir().join("crates_io_database_test_05_tables");
+        unwrap!(extract_and_save(
+            &DumpSource::new(Path::new("sample_data/database")),
+            &output_dir.join("crates.qvs20"),
+            false,
+            false,
+            &output_dir,
+            &["categories".to_string(), "users".to_string()]
+        ));
+        assert!(output_dir.join("users.qvs20").exists());
+        let bytes = unwrap!(std::fs::read(output_dir.join("categories.qvs20")));
+        unwrap!(std::fs::remove_dir_all(&output_dir));
+        assert!(bytes.starts_with(
+            b"[categories]\n[Integer][String][String][String]\n[primary_key][][][]\n[id][category][slug][description]\n[1][Parsing tools][parsing][Parsers, \\[lexers\\] and tokenizers.]\n"
+        ));
+    }
+    #[test]
+    pub fn test_06_from_archive() {
+        let dump_dir = Path::new("sample_data/database");
+        let archive_path = std::env::temp_dir().join("crates_io_database_test_06_dump.tar.gz");
+        crate::dump_source_mod::build_test_archive(dump_dir, "2020-06-06-020024", &archive_path);
+        let configs = vec![unwrap!(table_config("dependencies"))];
+        let from_archive = extract(
+            &DumpSource::new(&archive_path),
+            false,
+            false,
+            &configs,
+            true,
+        );
+        unwrap!(std::fs::remove_file(&archive_path));
+        let (crates_table, tables) = unwrap!(from_archive);
+        let (crates_table_2, tables_2) = unwrap!(extract(
+            &DumpSource::new(dump_dir),
+            false,
+            false,
+            &configs,
+            true
+        ));
+        assert_eq!(crates_table.rows.len(), 4);
+        assert_eq!(tables.len(), 2);
+        for (row, row_2) in crates_table.rows.iter().zip(crates_table_2.rows.iter()) {
+            assert_eq!(row.values, row_2.values);
+        }
+        for (table, table_2) in tables.iter().zip(tables_2.iter()) {
+            assert_eq!(table.rows.len(), table_2.rows.len());
+            for (row, row_2) in table.rows.iter().zip(table_2.rows.iter()) {
+                assert_eq!(row.values, row_2.values);
+            }
+        }
+    }
+    #[test]
+    pub fn test_07_embed_versions() {
+        let source = DumpSource::new(Path::new("sample_data/database"));
+        // the versions table is extracted from the same csv
+        let configs = vec![unwrap!(table_config("versions"))];
+        let (crates_table, tables) = unwrap!(extract(&source, false, true, &configs, false));
+        assert_eq!(tables[0].rows.len(), 8);
+        let versions_pos = unwrap!(crates_table.schema.column_position("versions"));
+        let versions = match &crates_table.rows[0].values[versions_pos] {
+            Value::Table(t) => t,
+            v => panic!("expected Table, found {:?}", v),
+        };
+        assert_eq!(versions.schema.row_delimiter, b"1");
+        assert_eq!(
+            versions.schema.column_names(),
+            vec!["num", "yanked", "created_at"]
+        );
+        assert_eq!(versions.rows.len(), 4);
+        assert_eq!(versions.rows[3].values[1], Value::Bool(true));
+        // the crates without versions have an empty nested table
+        match &crates_table.rows[2].values[versions_pos] {
+            Value::Table(t) => assert!(t.rows.is_empty()),
+            v => panic!("expected Table, found {:?}", v),
+        }
+        // the nested tables survive the round trip through qvs20
+        let bytes = unwrap!(crates_table.to_qvs20_bytes());
+        let table = unwrap!(Table::from_qvs20_with_schema(&bytes));
+        assert_eq!(table, crates_table);
+    }
+    #[test]
+    pub fn test_08_extract_bundle() {
+        let bundle_path = std::env::temp_dir().join("crates_io_database_test_08_bundle.qvs20");
+        unwrap!(extract_and_save_bundle(
+            &DumpSource::new(Path::new("sample_data/database")),
+            &bundle_path,
+            false,
+            true,
+            &[
+                "versions".to_string(),
+                "categories".to_string(),
+                "reverse_dependencies".to_string()
+            ]
+        ));
+        let bytes = unwrap!(std::fs::read(&bundle_path));
+        unwrap!(std::fs::remove_file(&bundle_path));
+        // the temporary files of the dump tables are removed
+        assert!(!std::env::temp_dir()
+            .join("crates_io_database_test_08_bundle.qvs20.versions.tmp")
+            .exists());
+        let bundle = unwrap!(qvs20_bundle_mod::Bundle::from_qvs20(&bytes));
+        assert_eq!(
+            bundle.table_names(),
+            vec!["crates", "versions", "categories", "reverse_dependencies"]
+        );
+        assert_eq!(unwrap!(bundle.table("crates")).rows.len(), 4);
+        // the versions table and the embedded versions from the same pass over versions.csv
+        assert_eq!(unwrap!(bundle.table("versions")).rows.len(), 8);
+        let crates = unwrap!(bundle.table("crates"));
+        assert!(crates.schema.column_position("versions").is_some());
+    }
+    #[test]
+    pub fn test_09_empty_crates() {
+        // the empty crates.csv gives the crates table with all the columns and no rows
+        let embedded = EmbeddedVersions {
+            schema: Schema::default(),
+            rows_by_crate: HashMap::new(),
+        };
+        let table = unwrap!(crates_table(vec![], &HashMap::new(), Some(&embedded)));
+        assert!(table.rows.is_empty());
+        assert_eq!(
+            table.schema.column_names(),
+            vec![
+                "name",
+                "description",
+                "repository",
+                "id",
+                "last_version",
+                "versions"
+            ]
+        );
+        let id_pos = unwrap!(table.schema.column_position("id"));
+        assert_eq!(table.schema.columns[id_pos].data_type, DataType::Integer);
+        assert_eq!(
+            table.schema.columns[id_pos].properties.to_string(),
+            "primary_key"
+        );
+    }
 }
-*/
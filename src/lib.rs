@@ -0,0 +1,62 @@
+//! # qvs20
+//!
+//! Read and write tables in the qvs20 format.  
+//! The 4 schema rows (table name, data types, additional properties, column names)
+//! are followed by the data rows.  
+//!
+//! ```text
+//! [crates]
+//! [String][Integer]
+//! [][]
+//! [name][id]
+//! [serde][463]
+//! ```
+//!
+//! `Table::from_qvs20_with_schema()` reads the bytes into a `Table`.  
+//! `Table::from_qvs20_with_options()` with `ParseOptions::lenient()` rejects the malformed rows and continues.  
+//! `Schema::from_qvs20()` reads a schema-only file, the `Schema` owned by every `Table`.  
+//! `ColumnProperties` are the additional properties like `primary_key` or `max_len=20`, enforced on load and write.  
+//! `Writer::write_table()` writes the `Table` to any `std::io::Write`.  
+//! `validate_with_schema()` checks a file against a canonical schema and reports all the violations.  
+//! `StreamReaderForQvs20` iterates the rows of big files from any `std::io::BufRead`.  
+//! `to_writer()` and `from_table()` map the rows to and from Rust structs with serde.  
+//! `diff_tables()` compares two snapshots of a table by a key column.  
+//! `Bundle` has many tables in one file, read by table name and written in order after the index.  
+//! `make_patch()` and `apply_patch()` move a snapshot to the next one, checked by the base sha256.  
+//! `ReaderForQvs20` is the low level tokenizer used by all of them, the row delimiter is 1 byte or `CRLF`.  
+//! The errors have the `Position` with line, column and an excerpt of the bytes.
+
+// region: Clippy
+#![deny(unused_must_use)]
+// CONS: Unnecessary code.
+// PROS: more readable without knowing that the type is bool.
+#![allow(clippy::bool_comparison)]
+// endregion: Clippy
+
+// region: mod, extern and use statements
+pub mod qvs20_bundle_mod;
+pub mod qvs20_diff_mod;
+pub mod qvs20_lenient_mod;
+pub mod qvs20_patch_mod;
+pub mod qvs20_properties_mod;
+pub mod qvs20_reader_mod;
+pub mod qvs20_schema_mod;
+pub mod qvs20_serde_mod;
+pub mod qvs20_stream_mod;
+pub mod qvs20_table_mod;
+pub mod qvs20_validate_mod;
+pub mod qvs20_writer_mod;
+
+pub use qvs20_bundle_mod::{Bundle, Qvs20ErrorBundle};
+pub use qvs20_diff_mod::{diff_tables, Qvs20ErrorDiff, TableDiff};
+pub use qvs20_lenient_mod::{ParseOptions, ParseReport, RejectedRow};
+pub use qvs20_patch_mod::{apply_patch, make_patch, table_sha256, Qvs20ErrorPatch};
+pub use qvs20_properties_mod::{ColumnProperties, Property, RowChecker};
+pub use qvs20_reader_mod::{Position, Qvs20ErrorReader, ReaderForQvs20, Token, CRLF, LF};
+pub use qvs20_serde_mod::{from_row, from_table, schema_of, to_table, to_writer, Qvs20ErrorSerde};
+pub use qvs20_stream_mod::StreamReaderForQvs20;
+pub use qvs20_schema_mod::{Column, DataType, Qvs20ErrorSchema, Schema};
+pub use qvs20_table_mod::{Qvs20ErrorTable, Row, Table, Value};
+pub use qvs20_validate_mod::{validate_with_schema, Violation};
+pub use qvs20_writer_mod::Writer;
+// endregion
@@ -4,32 +4,33 @@
 
 // region: Clippy
 #![deny(unused_must_use)]
-
 // CONS: Unnecessary code.
 // PROS: more readable without knowing that the type is bool.
-#[allow(clippy::bool_comparison)]
+#![allow(clippy::bool_comparison)]
 // endregion: Clippy
 
 // region: mod, extern and use statements
+// the qvs20 format is in the library src/lib.rs
+mod commands_mod;
+mod dump_source_mod;
 mod extract_and_save_mod;
-mod qvs20_error_mod;
-mod qvs20_reader_mod;
-mod qvs20_schema_mod;
-mod qvs20_table_mod;
-mod qvs20_writer_mod;
+mod resolve_mod;
+mod reverse_dependencies_mod;
 
 mod utils_mod;
 
-//use unwrap::unwrap;
+use unwrap::unwrap;
 
 #[allow(unused_imports)]
 use ansi_term::Colour::{Green, Red, Yellow};
 //use ansi_term::Style;
-use clap::App;
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
 use std::env;
+use std::io::Write;
+use std::path::Path;
 // endregion
 
-#[allow(clippy::print_stdout, clippy::integer_arithmetic)]
+#[allow(clippy::print_stdout, clippy::arithmetic_side_effects)]
 /// The program starts here.
 fn main() {
     // this function is different for Windows and for Linux.
@@ -37,31 +38,378 @@ fn main() {
     enable_ansi_support();
 
     // define the CLI input line parameters using the clap library
-    let _arguments = App::new(env!("CARGO_PKG_NAME"))
+    let output_arg = Arg::with_name("output")
+        .long("output")
+        .value_name("FILE")
+        .help("file to write, default is stdout");
+    let file_arg = Arg::with_name("file")
+        .value_name("FILE")
+        .required(true)
+        .help("qvs20 file");
+    let arguments = App::new(env!("CARGO_PKG_NAME"))
         .version(env!("CARGO_PKG_VERSION"))
         .author(env!("CARGO_PKG_AUTHORS"))
         .about(env!("CARGO_PKG_DESCRIPTION"))
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            SubCommand::with_name("extract")
+                .about("extract the crates.io database dump to qvs20")
+                .arg(
+                    Arg::with_name("dump_dir")
+                        .long("dump-dir")
+                        .value_name("PATH")
+                        .default_value("database")
+                        .help("crates.io database dump: db-dump.tar.gz or the unpacked folder that contains data/"),
+                )
+                .arg(output_arg.clone().default_value("crates.qvs20"))
+                .arg(
+                    Arg::with_name("exclude_prerelease")
+                        .long("exclude-prerelease")
+                        .help("last_version is the last stable version, without pre-releases"),
+                )
+                .arg(
+                    Arg::with_name("bundle")
+                        .long("bundle")
+                        .value_name("BUNDLE_FILE")
+                        .conflicts_with("output_dir")
+                        .help("write crates and the TABLES in one bundle file, instead of --output and --output-dir"),
+                )
+                .arg(
+                    Arg::with_name("embed_versions")
+                        .long("embed-versions")
+                        .help("every crate has the column versions with a nested table of all its versions"),
+                )
+                .arg(
+                    Arg::with_name("tables")
+                        .long("tables")
+                        .value_name("TABLES")
+                        .use_delimiter(true)
+                        .help("comma separated dump tables to extract too, reverse_dependencies or all"),
+                )
+                .arg(
+                    Arg::with_name("output_dir")
+                        .long("output-dir")
+                        .value_name("DIR")
+                        .default_value(".")
+                        .help("folder for the TABLES files, every table in its own .qvs20"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("validate")
+                .about("check that the qvs20 file is well-formed")
+                .arg(file_arg.clone())
+                .arg(
+                    Arg::with_name("schema")
+                        .long("schema")
+                        .value_name("SCHEMA_FILE")
+                        .help("also check against this schema-only qvs20 file, report all violations"),
+                )
+                .arg(output_arg.clone()),
+        )
+        .subcommand(
+            SubCommand::with_name("convert")
+                .about("convert qvs20 <-> csv, the format is recognized by the file extension")
+                .arg(file_arg.clone().help("qvs20 or csv file"))
+                .arg(
+                    Arg::with_name("lenient")
+                        .long("lenient")
+                        .help("skip the malformed qvs20 rows and report them, instead of the first error"),
+                )
+                .arg(
+                    Arg::with_name("rejected")
+                        .long("rejected")
+                        .value_name("REJECTED_FILE")
+                        .requires("lenient")
+                        .help("write the skipped rows to this qvs20 file with the same schema"),
+                )
+                .arg(output_arg.clone()),
+        )
+        .subcommand(
+            SubCommand::with_name("query")
+                .about("select rows and columns from a qvs20 file")
+                .arg(file_arg.clone())
+                .arg(
+                    Arg::with_name("where")
+                        .long("where")
+                        .value_name("COLUMN=VALUE")
+                        .multiple(true)
+                        .number_of_values(1)
+                        .help("only rows where the column has this value"),
+                )
+                .arg(
+                    Arg::with_name("select")
+                        .long("select")
+                        .value_name("COLUMNS")
+                        .use_delimiter(true)
+                        .help("comma separated column names, default is all"),
+                )
+                .arg(output_arg.clone()),
+        )
+        .subcommand(
+            SubCommand::with_name("diff")
+                .about("added, removed and modified rows between two snapshots of a table")
+                .arg(file_arg.clone().help("old qvs20 file"))
+                .arg(
+                    Arg::with_name("new_file")
+                        .value_name("NEW_FILE")
+                        .required(true)
+                        .help("new qvs20 file"),
+                )
+                .arg(
+                    Arg::with_name("key")
+                        .long("key")
+                        .value_name("COLUMN")
+                        .default_value("id")
+                        .help("the rows are matched by this column"),
+                )
+                .arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .possible_values(&["text", "qvs20"])
+                        .default_value("text")
+                        .help("human readable text or the qvs20 change table"),
+                )
+                .arg(output_arg.clone()),
+        )
+        .subcommand(
+            SubCommand::with_name("make-patch")
+                .about("patch table from the old to the new snapshot of a table")
+                .arg(file_arg.clone().help("old qvs20 file"))
+                .arg(
+                    Arg::with_name("new_file")
+                        .value_name("NEW_FILE")
+                        .required(true)
+                        .help("new qvs20 file"),
+                )
+                .arg(
+                    Arg::with_name("key")
+                        .long("key")
+                        .value_name("COLUMN")
+                        .default_value("id")
+                        .help("the rows are matched by this column"),
+                )
+                .arg(output_arg.clone()),
+        )
+        .subcommand(
+            SubCommand::with_name("apply-patch")
+                .about("apply the patch to the base snapshot it was made from")
+                .arg(file_arg.clone().help("base qvs20 file"))
+                .arg(
+                    Arg::with_name("patch_file")
+                        .value_name("PATCH_FILE")
+                        .required(true)
+                        .help("patch qvs20 file"),
+                )
+                .arg(output_arg.clone()),
+        )
+        .subcommand(
+            SubCommand::with_name("resolve")
+                .about("transitive dependency tree of a crate from the extracted tables")
+                .arg(
+                    Arg::with_name("name")
+                        .value_name("NAME")
+                        .required(true)
+                        .help("crate name"),
+                )
+                .arg(
+                    Arg::with_name("req")
+                        .value_name("REQ")
+                        .default_value("*")
+                        .help("version requirement ex. ^1.2"),
+                )
+                .arg(
+                    Arg::with_name("tables_dir")
+                        .long("tables-dir")
+                        .value_name("DIR")
+                        .default_value(".")
+                        .help("folder with crates.qvs20, versions.qvs20 and dependencies.qvs20"),
+                )
+                .arg(
+                    Arg::with_name("include_optional")
+                        .long("include-optional")
+                        .help("include optional dependencies"),
+                )
+                .arg(
+                    Arg::with_name("include_dev")
+                        .long("include-dev")
+                        .help("include dev dependencies of the crate"),
+                )
+                .arg(output_arg.clone().help("qvs20 file to write, default is the tree to stdout")),
+        )
+        .subcommand(
+            SubCommand::with_name("stats")
+                .about("statistics for every column of a qvs20 file")
+                .arg(file_arg)
+                .arg(output_arg),
+        )
         .get_matches();
 
-    // extract_and_save_mod::extract_and_save();
-    //use qvs20_table_mod::*;
-    //use std::str;
-    let s = r"[t]\n";
-    /*[crates table]
-    [String[String][String][Integer][String]
-    [][][][][]
-    [name][description][repository][id][last_version]
-    [name_1][A small git 1][https://github.com/ 1][1601][0.1.1]
-    [name_2][A small git 2][https://github.com/ 2][1602][0.1.2]
-    [name_3][A small git 3][https://github.com/ 3][1603][0.1.3]
-    [name_4][A small git 4][https://github.com/ 4][1604][0.1.4]
-    [name_5][ 5][https://github.com/ 5][1][0\[1\]\n5]
-    ";*/
-    
-    let table = qvs20_table_mod::Table::from_qvs20_with_schema(s.as_bytes());
-    //dbg!(&table);
-    let err = table.unwrap_err();
-    println!("{}", err);
+    let result = match arguments.subcommand() {
+        ("extract", Some(args)) => extract(args),
+        ("validate", Some(args)) => match args.value_of("schema") {
+            Some(schema_path) => commands_mod::validate_with_schema(
+                file_path(args),
+                Path::new(schema_path),
+                args.value_of("output").map(Path::new),
+            ),
+            None => commands_mod::validate(file_path(args), args.value_of("output").map(Path::new)),
+        },
+        ("convert", Some(args)) => commands_mod::convert(
+            file_path(args),
+            args.value_of("output").map(Path::new),
+            args.is_present("lenient"),
+            args.value_of("rejected").map(Path::new),
+        ),
+        ("query", Some(args)) => query(args),
+        ("diff", Some(args)) => commands_mod::diff(
+            file_path(args),
+            Path::new(unwrap!(args.value_of("new_file"))),
+            unwrap!(args.value_of("key")),
+            unwrap!(args.value_of("format")),
+            args.value_of("output").map(Path::new),
+        ),
+        ("make-patch", Some(args)) => commands_mod::make_patch(
+            file_path(args),
+            Path::new(unwrap!(args.value_of("new_file"))),
+            unwrap!(args.value_of("key")),
+            args.value_of("output").map(Path::new),
+        ),
+        ("apply-patch", Some(args)) => commands_mod::apply_patch(
+            file_path(args),
+            Path::new(unwrap!(args.value_of("patch_file"))),
+            args.value_of("output").map(Path::new),
+        ),
+        ("resolve", Some(args)) => resolve(args),
+        ("stats", Some(args)) => stats(args),
+        // SubcommandRequiredElseHelp does not come here
+        _ => Ok(()),
+    };
+    if let Err(err) = result {
+        eprintln!("{} {:#}", Red.paint("Error:"), err);
+        std::process::exit(1);
+    }
+}
+
+/// the mandatory file argument
+fn file_path<'a>(args: &'a ArgMatches) -> &'a Path {
+    Path::new(unwrap!(args.value_of("file")))
+}
+
+/// extract subcommand: crates and optionally the other dump tables
+fn extract(args: &ArgMatches) -> anyhow::Result<()> {
+    let source = dump_source_mod::DumpSource::new(Path::new(unwrap!(args.value_of("dump_dir"))));
+    let mut table_names: Vec<String> = vec![];
+    for table_name in args.values_of("tables").into_iter().flatten() {
+        if table_name == "all" {
+            table_names.extend(
+                extract_and_save_mod::TABLE_CONFIGS
+                    .iter()
+                    .map(|c| c.table_name.to_string()),
+            );
+            table_names.push(reverse_dependencies_mod::REVERSE_DEPENDENCIES.to_string());
+        } else {
+            table_names.push(table_name.to_string());
+        }
+    }
+    let skipped = match args.value_of("bundle") {
+        Some(bundle_path) => extract_and_save_mod::extract_and_save_bundle(
+            &source,
+            Path::new(bundle_path),
+            args.is_present("exclude_prerelease"),
+            args.is_present("embed_versions"),
+            &table_names,
+        )?,
+        None => extract_and_save_mod::extract_and_save(
+            &source,
+            Path::new(unwrap!(args.value_of("output"))),
+            args.is_present("exclude_prerelease"),
+            args.is_present("embed_versions"),
+            Path::new(unwrap!(args.value_of("output_dir"))),
+            &table_names,
+        )?,
+    };
+    // the versions that are not semver do not stop the extraction
+    for skipped_version in skipped.iter() {
+        eprintln!(
+            "skipped in versions.csv: crate_id {}, num {}: {}",
+            skipped_version.crate_id, skipped_version.num, skipped_version.error
+        );
+    }
+    if !skipped.is_empty() {
+        eprintln!(
+            "skipped {} versions that are not semver for the last versions",
+            skipped.len()
+        );
+    }
+    //return
+    Ok(())
+}
+
+/// query subcommand
+fn query(args: &ArgMatches) -> anyhow::Result<()> {
+    let table = commands_mod::read_table(file_path(args))?;
+    let mut conditions = vec![];
+    for condition in args.values_of("where").into_iter().flatten() {
+        conditions.push(commands_mod::parse_condition(condition)?);
+    }
+    let select: Vec<String> = args
+        .values_of("select")
+        .into_iter()
+        .flatten()
+        .map(|c| c.to_string())
+        .collect();
+    let result = commands_mod::query_table(&table, &conditions, &select)?;
+    commands_mod::write_table(&result, args.value_of("output").map(Path::new))
+}
+
+/// resolve subcommand
+fn resolve(args: &ArgMatches) -> anyhow::Result<()> {
+    let tables_dir = Path::new(unwrap!(args.value_of("tables_dir")));
+    let graph = resolve_mod::DependencyGraph::from_tables(
+        &commands_mod::read_table(&tables_dir.join("crates.qvs20"))?,
+        &commands_mod::read_table(&tables_dir.join("versions.qvs20"))?,
+        &commands_mod::read_table(&tables_dir.join("dependencies.qvs20"))?,
+    )?;
+    // the versions that are not semver do not stop the resolution
+    for (version_id, error) in graph.skipped().iter() {
+        eprintln!(
+            "skipped in the versions table: version id {}: {}",
+            version_id, error
+        );
+    }
+    if !graph.skipped().is_empty() {
+        eprintln!(
+            "skipped {} versions that are not semver",
+            graph.skipped().len()
+        );
+    }
+    let options = resolve_mod::ResolveOptions {
+        include_optional: args.is_present("include_optional"),
+        include_dev: args.is_present("include_dev"),
+    };
+    let root = graph.resolve(
+        unwrap!(args.value_of("name")),
+        unwrap!(args.value_of("req")),
+        &options,
+    )?;
+    match args.value_of("output").map(Path::new) {
+        Some(output) => commands_mod::write_table(&root.to_table(), Some(output)),
+        None => {
+            let mut wtr = commands_mod::output_writer(None)?;
+            wtr.write_all(root.tree_text().as_bytes())?;
+            wtr.flush()?;
+            Ok(())
+        }
+    }
+}
+
+/// stats subcommand
+fn stats(args: &ArgMatches) -> anyhow::Result<()> {
+    let table = commands_mod::read_table(file_path(args))?;
+    let mut wtr = commands_mod::output_writer(args.value_of("output").map(Path::new))?;
+    wtr.write_all(commands_mod::stats_text(&table).as_bytes())?;
+    wtr.flush()?;
+    Ok(())
 }
 
 // region: different function code for Linux and Windows
@@ -0,0 +1,280 @@
+// qvs20_bundle_mod
+
+// The bundle is one file or stream with many complete tables, one after the other.
+// It starts with the index table [bundle]: the name and the number of data rows of every table.
+// Then the tables follow in the same order, each with its own 4 schema rows:
+// [bundle]
+// [String][Integer]
+// [unique][]
+// [table_name][rows]
+// [crates][2]
+// [keywords][1]
+// [crates]
+// [String][Integer]
+// ...2 data rows
+// [keywords]
+// ...
+// The index rows have 2 fields, the first row of a table has only the table name,
+// so the reader knows where the index ends.
+// With the number of rows the reader knows where every table ends.
+
+use crate::qvs20_properties_mod::*;
+use crate::qvs20_reader_mod::*;
+use crate::qvs20_schema_mod::*;
+use crate::qvs20_table_mod::*;
+use crate::qvs20_writer_mod::Writer;
+
+use std::io::{self, Write};
+use thiserror::Error;
+
+/// the table name of the index table
+pub const BUNDLE_INDEX: &str = "bundle";
+
+#[derive(Error, Debug)]
+pub enum Qvs20ErrorBundle {
+    #[error("Error: not a bundle: {msg}")]
+    NotABundle { msg: String },
+    #[error("Error: the bundle index has {index:?}, but the table is {actual:?}.")]
+    TableNameMismatch { index: String, actual: String },
+    #[error("Error: duplicate table {table_name:?} in the bundle.")]
+    DuplicateTable { table_name: String },
+    #[error("Error in table {table_name:?}: {source}")]
+    ErrorInTable {
+        table_name: String,
+        source: Box<Qvs20ErrorTable>,
+    },
+    #[error(transparent)]
+    ErrorIndex {
+        #[from]
+        source: Qvs20ErrorTable,
+    },
+}
+
+/// many tables in one file, the table names are unique
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Bundle {
+    pub tables: Vec<Table>,
+}
+
+impl Bundle {
+    /// read the index and all the tables of the bundle
+    pub fn from_qvs20(input: &[u8]) -> Result<Bundle, Qvs20ErrorBundle> {
+        let mut rdr = ReaderForQvs20::new(input);
+        let index = read_index(&mut rdr)?;
+        let mut bundle = Bundle::default();
+        for row in index.rows.iter() {
+            let table_name = row.values[0].to_string();
+            let row_count = match &row.values[1] {
+                Value::Integer(i) if *i >= 0 => *i as usize,
+                v => {
+                    return Err(Qvs20ErrorBundle::NotABundle {
+                        msg: format!("table {} has {} rows in the index", table_name, v),
+                    })
+                }
+            };
+            let table = Table::read_table(&mut rdr, Some(row_count)).map_err(|e| {
+                Qvs20ErrorBundle::ErrorInTable {
+                    table_name: table_name.clone(),
+                    source: Box::new(e),
+                }
+            })?;
+            if table.schema.table_name != table_name {
+                return Err(Qvs20ErrorBundle::TableNameMismatch {
+                    index: table_name,
+                    actual: table.schema.table_name,
+                });
+            }
+            bundle.tables.push(table);
+        }
+        if rdr.next().is_some() {
+            return Err(Qvs20ErrorBundle::NotABundle {
+                msg: "there is more after the last table in the index".to_string(),
+            });
+        }
+        //return
+        Ok(bundle)
+    }
+
+    /// the table by table name
+    pub fn table(&self, table_name: &str) -> Option<&Table> {
+        self.tables
+            .iter()
+            .find(|t| t.schema.table_name == table_name)
+    }
+
+    /// the table names in the order of the bundle
+    pub fn table_names(&self) -> Vec<&str> {
+        self.tables
+            .iter()
+            .map(|t| t.schema.table_name.as_str())
+            .collect()
+    }
+
+    /// add a table at the end, the table name must be unique
+    pub fn push(&mut self, table: Table) -> Result<(), Qvs20ErrorBundle> {
+        if self.table(&table.schema.table_name).is_some() {
+            return Err(Qvs20ErrorBundle::DuplicateTable {
+                table_name: table.schema.table_name,
+            });
+        }
+        self.tables.push(table);
+        Ok(())
+    }
+
+    /// write the bundle to qvs20 bytes in memory
+    pub fn to_qvs20_bytes(&self) -> io::Result<Vec<u8>> {
+        let mut wtr = Writer::new(Vec::with_capacity(1000));
+        wtr.write_bundle(&self.tables)?;
+        //return
+        Ok(wtr.into_inner())
+    }
+}
+
+/// the index table with the name and the number of rows of every table
+fn index_table(index: &[(&str, usize)]) -> Table {
+    let mut table_name = Column::new("table_name", DataType::String);
+    table_name.properties.list.push(Property::Unique);
+    Table {
+        schema: Schema {
+            table_name: BUNDLE_INDEX.to_string(),
+            columns: vec![table_name, Column::new("rows", DataType::Integer)],
+            ..Default::default()
+        },
+        rows: index
+            .iter()
+            .map(|(table_name, rows)| Row {
+                values: vec![
+                    Value::String(table_name.to_string()),
+                    Value::Integer(*rows as i64),
+                ],
+            })
+            .collect(),
+    }
+}
+
+/// read the index table, the reader stays at the start of the first table
+fn read_index(rdr: &mut ReaderForQvs20) -> Result<Table, Qvs20ErrorBundle> {
+    let schema = Schema::read_schema(rdr).map_err(Qvs20ErrorTable::from)?;
+    let expected = index_table(&[]).schema;
+    if schema != expected {
+        return Err(Qvs20ErrorBundle::NotABundle {
+            msg: format!(
+                "the first table must be the index [{}] with the columns table_name and rows",
+                BUNDLE_INDEX
+            ),
+        });
+    }
+    let mut index = Table {
+        schema,
+        rows: vec![],
+    };
+    let mut checker = RowChecker::new(&index.schema);
+    let mut row_number = 1;
+    while !is_next_table(rdr) {
+        let row_pos = rdr.pos();
+        let row = match index.schema.data_row(rdr, row_number) {
+            Some(result) => result?,
+            None => break,
+        };
+        checker
+            .check_row(row_number, &row)
+            .map_err(|e| e.with_field_position(rdr, row_pos))?;
+        index.rows.push(row);
+        row_number += 1;
+    }
+    //return
+    Ok(index)
+}
+
+/// look ahead: the first row of the next table has only one field, the table name
+fn is_next_table(rdr: &ReaderForQvs20) -> bool {
+    let mut look_ahead = rdr.clone();
+    matches!(
+        (look_ahead.next(), look_ahead.next()),
+        (Some(Ok(Token::Field(_))), Some(Ok(Token::RowDelimiter(_))))
+    )
+}
+
+impl<W: Write> Writer<W> {
+    /// write the index and then all the tables in order
+    pub fn write_bundle(&mut self, tables: &[Table]) -> io::Result<()> {
+        let index: Vec<(&str, usize)> = tables
+            .iter()
+            .map(|t| (t.schema.table_name.as_str(), t.rows.len()))
+            .collect();
+        self.write_bundle_index(&index)?;
+        for table in tables.iter() {
+            self.write_table(table)?;
+        }
+        //return
+        Ok(())
+    }
+
+    /// write only the index with the table names and the number of rows,
+    /// for the tables that are written later one by one, in the same order
+    pub fn write_bundle_index(&mut self, index: &[(&str, usize)]) -> io::Result<()> {
+        for (i, (table_name, _rows)) in index.iter().enumerate() {
+            if index[..i].iter().any(|(t, _)| t == table_name) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    Qvs20ErrorBundle::DuplicateTable {
+                        table_name: table_name.to_string(),
+                    },
+                ));
+            }
+        }
+        self.write_table(&index_table(index))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use unwrap::unwrap;
+
+    const BUNDLE: &str = "[bundle]\n[String][Integer]\n[unique][]\n[table_name][rows]\n\
+                          [crates][2]\n[keywords][1]\n\
+                          [crates]\n[String][Integer]\n[][primary_key]\n[name][id]\n\
+                          [serde][1]\n[rand][2]\n\
+                          [keywords]\r\n[String]\r\n[]\r\n[keyword]\r\n[parser]\r\n";
+
+    #[test]
+    pub fn test_01_read_and_write() {
+        let bundle = unwrap!(Bundle::from_qvs20(BUNDLE.as_bytes()));
+        assert_eq!(bundle.table_names(), vec!["crates", "keywords"]);
+        let crates = unwrap!(bundle.table("crates"));
+        assert_eq!(crates.rows[1].values[0], Value::String("rand".to_string()));
+        // every table has its own row delimiter
+        assert_eq!(unwrap!(bundle.table("keywords")).schema.row_delimiter, CRLF);
+        assert!(bundle.table("versions").is_none());
+        // the writer emits the tables in order
+        assert_eq!(unwrap!(bundle.to_qvs20_bytes()), BUNDLE.as_bytes());
+        // the empty bundle has only the index
+        let empty = unwrap!(Bundle::default().to_qvs20_bytes());
+        assert!(unwrap!(Bundle::from_qvs20(&empty)).tables.is_empty());
+    }
+
+    #[test]
+    pub fn test_02_errors() {
+        // a plain table is not a bundle
+        let err = unwrap!(Bundle::from_qvs20(b"[t]\n[String]\n[]\n[name]\n[one]\n").err());
+        assert!(matches!(err, Qvs20ErrorBundle::NotABundle { .. }));
+        // the index has a wrong number of rows
+        let wrong = BUNDLE.replace("[crates][2]", "[crates][3]");
+        let err = unwrap!(Bundle::from_qvs20(wrong.as_bytes()).err());
+        assert_eq!(
+            err.to_string(),
+            r#"Error in table "crates": Error in data row 3: wrong row delimiter "\r\n" instead of "\n" (line 13, column 11, pos: 168, near "eywords]\r\n[String]\r\n[]\r\n")"#
+        );
+        // the index has the tables in a different order
+        let wrong = BUNDLE.replace("[crates][2]\n[keywords][1]", "[keywords][2]\n[crates][1]");
+        let err = unwrap!(Bundle::from_qvs20(wrong.as_bytes()).err());
+        assert!(matches!(err, Qvs20ErrorBundle::TableNameMismatch { .. }));
+        // duplicate table name
+        let mut bundle = unwrap!(Bundle::from_qvs20(BUNDLE.as_bytes()));
+        let crates = unwrap!(bundle.table("crates")).clone();
+        assert!(bundle.push(crates.clone()).is_err());
+        bundle.tables.push(crates);
+        assert!(bundle.to_qvs20_bytes().is_err());
+    }
+}
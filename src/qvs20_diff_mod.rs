@@ -0,0 +1,383 @@
+// qvs20_diff_mod
+
+// Diff of two tables with the same schema, the rows are matched by a key column.
+// The key is compared as the canonical text of the value, Null is a key of its own
+// and it is not the same as the empty String.
+// The changes are: removed and modified in the order of the old table,
+// then added in the order of the new table.
+
+use crate::qvs20_properties_mod::*;
+use crate::qvs20_schema_mod::*;
+use crate::qvs20_table_mod::*;
+
+use std::collections::HashMap;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Qvs20ErrorDiff {
+    #[error("Error: the schemas are not compatible: {msg}")]
+    IncompatibleSchema { msg: String },
+    #[error("Error: key column {column:?} does not exist.")]
+    KeyColumnNotFound { column: String },
+    #[error("Error: duplicate key {} in data row {row_number}.", quoted_or_null(.key))]
+    DuplicateKey {
+        key: Option<String>,
+        row_number: usize,
+    },
+}
+
+/// the text of the key or the value in quotes, or null without quotes
+pub fn quoted_or_null(text: &Option<String>) -> String {
+    match text {
+        Some(text) => format!("{:?}", text),
+        None => "null".to_string(),
+    }
+}
+
+/// one changed column of a modified row
+#[derive(Clone, Debug, PartialEq)]
+pub struct ColumnChange {
+    pub column: String,
+    pub old: Value,
+    pub new: Value,
+}
+
+/// one changed row
+#[derive(Clone, Debug, PartialEq)]
+pub enum RowChange {
+    Added {
+        key: Option<String>,
+        row: Row,
+    },
+    Removed {
+        key: Option<String>,
+        row: Row,
+    },
+    Modified {
+        key: Option<String>,
+        changes: Vec<ColumnChange>,
+    },
+}
+
+/// all the changes from the old to the new table
+#[derive(Clone, Debug)]
+pub struct TableDiff {
+    pub key_column: String,
+    pub changes: Vec<RowChange>,
+}
+
+/// the table must have the same column names and data types
+pub fn check_compatible_schema(old: &Table, new: &Table) -> Result<(), Qvs20ErrorDiff> {
+    let (old_names, new_names) = (old.schema.column_names(), new.schema.column_names());
+    if old_names != new_names {
+        return Err(Qvs20ErrorDiff::IncompatibleSchema {
+            msg: format!("columns {:?} and {:?}", old_names, new_names),
+        });
+    }
+    let (old_types, new_types) = (old.schema.data_types(), new.schema.data_types());
+    if old_types != new_types {
+        return Err(Qvs20ErrorDiff::IncompatibleSchema {
+            msg: format!("data types {:?} and {:?}", old_types, new_types),
+        });
+    }
+    Ok(())
+}
+
+/// the position of the key column
+pub fn key_position(table: &Table, key_column: &str) -> Result<usize, Qvs20ErrorDiff> {
+    table
+        .schema
+        .column_position(key_column)
+        .ok_or_else(|| Qvs20ErrorDiff::KeyColumnNotFound {
+            column: key_column.to_string(),
+        })
+}
+
+/// key text (None for Null) -> row index, the keys must be unique
+pub fn key_index(
+    table: &Table,
+    key_pos: usize,
+) -> Result<HashMap<Option<String>, usize>, Qvs20ErrorDiff> {
+    let mut index = HashMap::with_capacity(table.rows.len());
+    for (i, row) in table.rows.iter().enumerate() {
+        let key = row.values[key_pos].to_text();
+        if index.insert(key.clone(), i).is_some() {
+            return Err(Qvs20ErrorDiff::DuplicateKey {
+                key,
+                row_number: i + 1,
+            });
+        }
+    }
+    Ok(index)
+}
+
+/// the changes from old to new, the rows are matched by the key column
+pub fn diff_tables(
+    old: &Table,
+    new: &Table,
+    key_column: &str,
+) -> Result<TableDiff, Qvs20ErrorDiff> {
+    check_compatible_schema(old, new)?;
+    let key_pos = key_position(old, key_column)?;
+    let old_index = key_index(old, key_pos)?;
+    let new_index = key_index(new, key_pos)?;
+    let mut diff = TableDiff {
+        key_column: key_column.to_string(),
+        changes: vec![],
+    };
+    for old_row in old.rows.iter() {
+        let key = old_row.values[key_pos].to_text();
+        match new_index.get(&key) {
+            None => diff.changes.push(RowChange::Removed {
+                key,
+                row: old_row.clone(),
+            }),
+            Some(&i) => {
+                let changes: Vec<ColumnChange> = old
+                    .schema
+                    .columns
+                    .iter()
+                    .zip(old_row.values.iter().zip(new.rows[i].values.iter()))
+                    .filter(|(_, (old_value, new_value))| old_value != new_value)
+                    .map(|(column, (old_value, new_value))| ColumnChange {
+                        column: column.name.clone(),
+                        old: old_value.clone(),
+                        new: new_value.clone(),
+                    })
+                    .collect();
+                if !changes.is_empty() {
+                    diff.changes.push(RowChange::Modified { key, changes });
+                }
+            }
+        }
+    }
+    for new_row in new.rows.iter() {
+        let key = new_row.values[key_pos].to_text();
+        if !old_index.contains_key(&key) {
+            diff.changes.push(RowChange::Added {
+                key,
+                row: new_row.clone(),
+            });
+        }
+    }
+    //return
+    Ok(diff)
+}
+
+impl TableDiff {
+    /// count of added, removed and modified rows
+    pub fn counts(&self) -> (usize, usize, usize) {
+        let mut counts = (0, 0, 0);
+        for change in self.changes.iter() {
+            match change {
+                RowChange::Added { .. } => counts.0 += 1,
+                RowChange::Removed { .. } => counts.1 += 1,
+                RowChange::Modified { .. } => counts.2 += 1,
+            }
+        }
+        counts
+    }
+
+    /// the key column and the key: id=1 or id is null
+    fn key_text(&self, key: &Option<String>) -> String {
+        match key {
+            Some(key) => format!("{}={}", self.key_column, key),
+            None => format!("{} is null", self.key_column),
+        }
+    }
+
+    /// human readable: one line for every changed row,
+    /// the changed values are in quotes and Null is null without quotes
+    /// + added, - removed, ~ modified with the changed columns
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        for change in self.changes.iter() {
+            match change {
+                RowChange::Added { key, .. } => {
+                    text.push_str(&format!("+ {}\n", self.key_text(key)))
+                }
+                RowChange::Removed { key, .. } => {
+                    text.push_str(&format!("- {}\n", self.key_text(key)))
+                }
+                RowChange::Modified { key, changes } => {
+                    text.push_str(&format!("~ {}", self.key_text(key)));
+                    for change in changes.iter() {
+                        text.push_str(&format!(
+                            " {}: {} -> {}",
+                            change.column,
+                            quoted_or_null(&change.old.to_text()),
+                            quoted_or_null(&change.new.to_text())
+                        ));
+                    }
+                    text.push('\n');
+                }
+            }
+        }
+        let (added, removed, modified) = self.counts();
+        text.push_str(&format!(
+            "added: {}, removed: {}, modified: {}\n",
+            added, removed, modified
+        ));
+        text
+    }
+
+    /// the change table: one row for every added or removed row
+    /// and one row for every changed column of a modified row
+    /// [change][key][column][old_value][new_value]
+    /// key, old_value and new_value are nullable, Null stays Null
+    pub fn to_change_table(&self) -> Table {
+        let mut table = Table {
+            schema: Schema {
+                table_name: "changes".to_string(),
+                columns: ["change", "key", "column", "old_value", "new_value"]
+                    .iter()
+                    .map(|name| Column::new(name, DataType::String))
+                    .collect(),
+                ..Default::default()
+            },
+            rows: vec![],
+        };
+        for column in table.schema.columns.iter_mut() {
+            if column.name != "change" && column.name != "column" {
+                column.properties.list.push(Property::Nullable);
+            }
+        }
+        let text_value = |text: Option<String>| text.map(Value::String).unwrap_or(Value::Null);
+        let mut push =
+            |change: &str, key: &Option<String>, column: &str, old: Value, new: Value| {
+                table.rows.push(Row {
+                    values: vec![
+                        Value::String(change.to_string()),
+                        text_value(key.clone()),
+                        Value::String(column.to_string()),
+                        old,
+                        new,
+                    ],
+                })
+            };
+        for change in self.changes.iter() {
+            match change {
+                RowChange::Added { key, .. } => push(
+                    "added",
+                    key,
+                    "",
+                    Value::String(String::new()),
+                    Value::String(String::new()),
+                ),
+                RowChange::Removed { key, .. } => push(
+                    "removed",
+                    key,
+                    "",
+                    Value::String(String::new()),
+                    Value::String(String::new()),
+                ),
+                RowChange::Modified { key, changes } => {
+                    for c in changes.iter() {
+                        push(
+                            "modified",
+                            key,
+                            &c.column,
+                            text_value(c.old.to_text()),
+                            text_value(c.new.to_text()),
+                        );
+                    }
+                }
+            }
+        }
+        table
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use unwrap::unwrap;
+    #[test]
+    pub fn test_01_diff() {
+        let old = unwrap!(Table::from_qvs20_with_schema(
+            b"[t]\n[Integer][String][String]\n[][][]\n[id][name][version]\n[1][one][0.1.0]\n[2][two][0.2.0]\n[3][three][0.3.0]\n"
+        ));
+        let new = unwrap!(Table::from_qvs20_with_schema(
+            b"[t]\n[Integer][String][String]\n[][][]\n[id][name][version]\n[3][three][0.3.1]\n[1][one][0.1.0]\n[4][four][0.4.0]\n"
+        ));
+        let diff = unwrap!(diff_tables(&old, &new, "id"));
+        assert_eq!(diff.counts(), (1, 1, 1));
+        assert_eq!(
+            diff.to_text(),
+            r#"- id=2
+~ id=3 version: "0.3.0" -> "0.3.1"
++ id=4
+added: 1, removed: 1, modified: 1
+"#
+        );
+        let table = diff.to_change_table();
+        assert_eq!(table.rows.len(), 3);
+        assert_eq!(
+            table.rows[1].values,
+            vec![
+                Value::String("modified".to_string()),
+                Value::String("3".to_string()),
+                Value::String("version".to_string()),
+                Value::String("0.3.0".to_string()),
+                Value::String("0.3.1".to_string()),
+            ]
+        );
+        // no changes
+        let diff = unwrap!(diff_tables(&old, &old, "name"));
+        assert!(diff.changes.is_empty());
+    }
+    #[test]
+    pub fn test_02_errors() {
+        let old = unwrap!(Table::from_qvs20_with_schema(
+            b"[t]\n[Integer][String]\n[][]\n[id][name]\n[1][one]\n[1][two]\n"
+        ));
+        let other = unwrap!(Table::from_qvs20_with_schema(
+            b"[t]\n[Integer][Integer]\n[][]\n[id][name]\n"
+        ));
+        assert_eq!(
+            unwrap!(diff_tables(&old, &old, "no_column").err()).to_string(),
+            r#"Error: key column "no_column" does not exist."#
+        );
+        assert_eq!(
+            unwrap!(diff_tables(&old, &old, "id").err()).to_string(),
+            r#"Error: duplicate key "1" in data row 2."#
+        );
+        assert!(matches!(
+            diff_tables(&old, &other, "id"),
+            Err(Qvs20ErrorDiff::IncompatibleSchema { .. })
+        ));
+    }
+    #[test]
+    pub fn test_03_null() {
+        // Null and the empty String are different keys and different values
+        let old = unwrap!(Table::from_qvs20_with_schema(
+            b"[t]\n[String][String]\n[nullable][nullable]\n[id][name]\n[\\0][a]\n[][b]\n"
+        ));
+        let new = unwrap!(Table::from_qvs20_with_schema(
+            b"[t]\n[String][String]\n[nullable][nullable]\n[id][name]\n[\\0][c]\n[][\\0]\n"
+        ));
+        let diff = unwrap!(diff_tables(&old, &new, "id"));
+        assert_eq!(
+            diff.to_text(),
+            r#"~ id is null name: "a" -> "c"
+~ id= name: "b" -> null
+added: 0, removed: 0, modified: 2
+"#
+        );
+        let table = diff.to_change_table();
+        assert_eq!(table.rows[0].values[1], Value::Null);
+        assert_eq!(table.rows[1].values[1], Value::String("".to_string()));
+        assert_eq!(table.rows[1].values[4], Value::Null);
+        // the change table with Null survives the round trip through qvs20
+        let bytes = unwrap!(table.to_qvs20_bytes());
+        assert_eq!(unwrap!(Table::from_qvs20_with_schema(&bytes)), table);
+        // two Null keys are duplicates
+        let twice = unwrap!(Table::from_qvs20_with_schema(
+            b"[t]\n[String][String]\n[nullable][]\n[id][name]\n[\\0][a]\n[\\0][b]\n"
+        ));
+        assert_eq!(
+            unwrap!(diff_tables(&twice, &twice, "id").err()).to_string(),
+            "Error: duplicate key null in data row 2."
+        );
+    }
+}
@@ -0,0 +1,294 @@
+// qvs20_lenient_mod
+
+// The lenient parse mode is for the files from third parties.
+// A malformed data row does not stop the parsing: the row is rejected with its error
+// and the parsing continues after the next row delimiter.
+// The schema rows must be correct, without them the fields cannot be understood.
+// Strict is the default: the first error stops the parsing like in Table::from_qvs20_with_schema().
+// The rejected rows can be written to a side file with the same schema rows,
+// to fix them by hand and load them later.
+// The row delimiter can be inside of a field: the reader accepts a raw LF in a field
+// and the nested tables have 1, 2, 3... in the text. So the row delimiter ends the row
+// only after an unescaped ] and before [ or the end of file, like the reader finds it.
+// A correct row is never split, a broken row can take the next row with it.
+
+use crate::qvs20_properties_mod::RowChecker;
+use crate::qvs20_reader_mod::*;
+use crate::qvs20_schema_mod::*;
+use crate::qvs20_table_mod::*;
+use crate::qvs20_writer_mod::Writer;
+
+use std::io::{self, Write};
+
+/// options for Table::from_qvs20_with_options()
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ParseOptions {
+    /// reject the malformed data rows and continue, instead of stopping at the first error
+    pub lenient: bool,
+}
+
+impl ParseOptions {
+    /// the lenient mode
+    pub fn lenient() -> Self {
+        ParseOptions { lenient: true }
+    }
+}
+
+/// a data row that is not in the table
+#[derive(Debug)]
+pub struct RejectedRow {
+    /// 1-based data row number
+    pub row_number: usize,
+    /// the raw bytes of the row, with the row delimiter if there is one
+    pub bytes: Vec<u8>,
+    /// the diagnostic with the position in the whole file
+    pub error: Qvs20ErrorTable,
+}
+
+/// the table with the good rows and the rejected rows
+#[derive(Debug)]
+pub struct ParseReport {
+    pub table: Table,
+    /// always empty in the strict mode
+    pub rejected_rows: Vec<RejectedRow>,
+}
+
+impl ParseReport {
+    /// write the schema rows and the raw bytes of the rejected rows to the side file
+    pub fn write_rejected<W: Write>(&self, wtr: W) -> io::Result<()> {
+        let row_delimiter = &self.table.schema.row_delimiter;
+        let mut wtr = Writer::new(wtr);
+        wtr.write_schema(&self.table.schema)?;
+        let mut wtr = wtr.into_inner();
+        for rejected in self.rejected_rows.iter() {
+            wtr.write_all(&rejected.bytes)?;
+            // the last row of the file can be without the row delimiter
+            if !rejected.bytes.ends_with(row_delimiter) {
+                wtr.write_all(row_delimiter)?;
+            }
+        }
+        wtr.flush()
+    }
+}
+
+impl Table {
+    /// parse the qvs20 bytes with options
+    /// the lenient mode returns the error only for the schema rows
+    pub fn from_qvs20_with_options(
+        input: &[u8],
+        options: &ParseOptions,
+    ) -> Result<ParseReport, Qvs20ErrorTable> {
+        if !options.lenient {
+            return Ok(ParseReport {
+                table: Self::from_qvs20_with_schema(input)?,
+                rejected_rows: vec![],
+            });
+        }
+        let mut rdr = ReaderForQvs20::new(input);
+        let mut report = ParseReport {
+            table: Table {
+                schema: Schema::read_schema(&mut rdr)?,
+                rows: vec![],
+            },
+            rejected_rows: vec![],
+        };
+        let schema = &report.table.schema;
+        let mut checker = RowChecker::new(schema);
+        let mut row_start = rdr.pos();
+        // the position of the row is moved forward row by row, not counted from the start
+        let mut origin = Position::new(input, row_start);
+        let mut row_number = 1;
+        while row_start < input.len() {
+            let row_end = next_row_end(input, row_start, &schema.row_delimiter);
+            let bytes = &input[row_start..row_end];
+            match lenient_row(schema, &mut checker, bytes, origin.clone(), row_number) {
+                Ok(row) => report.table.rows.push(row),
+                Err(error) => report.rejected_rows.push(RejectedRow {
+                    row_number,
+                    bytes: bytes.to_vec(),
+                    error,
+                }),
+            }
+            origin = origin.advance(bytes);
+            row_start = row_end;
+            row_number += 1;
+        }
+        //return
+        Ok(report)
+    }
+}
+
+/// parse and check one row, the bytes are exactly one row
+fn lenient_row(
+    schema: &Schema,
+    checker: &mut RowChecker,
+    bytes: &[u8],
+    origin: Position,
+    row_number: usize,
+) -> Result<Row, Qvs20ErrorTable> {
+    let mut rdr = ReaderForQvs20::with_origin(bytes, origin);
+    let row = match schema.data_row(&mut rdr, row_number) {
+        Some(result) => result?,
+        None => {
+            return Err(Qvs20ErrorTable::ErrorInDataRow {
+                row_number,
+                msg: "empty row".to_string(),
+                position: rdr.position_at(0),
+                source: None,
+            })
+        }
+    };
+    // the reader found the row delimiter before the end of the bytes
+    if rdr.pos() != bytes.len() {
+        return Err(Qvs20ErrorTable::ErrorInDataRow {
+            row_number,
+            msg: "the row continues after the row delimiter".to_string(),
+            position: rdr.position_at(rdr.pos()),
+            source: None,
+        });
+    }
+    checker
+        .check_row(row_number, &row)
+        .map_err(|e| e.with_field_position(&rdr, 0))?;
+    //return
+    Ok(row)
+}
+
+/// the end of the row that starts at start: after the row delimiter or the end of file
+fn next_row_end(input: &[u8], start: usize, row_delimiter: &[u8]) -> usize {
+    let mut pos = start;
+    while let Some(found) = input[pos..]
+        .windows(row_delimiter.len())
+        .position(|w| w == row_delimiter)
+    {
+        let found = pos + found;
+        let end = found + row_delimiter.len();
+        if is_field_end(input, start, found) && is_row_start(input, end) {
+            return end;
+        }
+        pos = found + 1;
+    }
+    //return
+    input.len()
+}
+
+/// the byte before pos is ] and it is not escaped
+fn is_field_end(input: &[u8], start: usize, pos: usize) -> bool {
+    if pos <= start || input[pos - 1] != b']' {
+        return false;
+    }
+    let backslashes = input[start..pos - 1]
+        .iter()
+        .rev()
+        .take_while(|b| **b == b'\\')
+        .count();
+    //return
+    backslashes % 2 == 0
+}
+
+/// the next field starts at pos or this is the end of file
+fn is_row_start(input: &[u8], pos: usize) -> bool {
+    pos >= input.len() || input[pos] == b'['
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use unwrap::unwrap;
+    const SCHEMA: &str = "[t]\n[String][Integer]\n[][unique]\n[name][id]\n";
+    #[test]
+    pub fn test_01_lenient() {
+        let s = format!(
+            "{}[one][1]\n[two][x]\n[three]x[3]\n[four][4][too many]\n[five][1]\n[six][6]\n[a\nb][8]\n[seven][7]",
+            SCHEMA
+        );
+        // the strict mode is the default and stops at the first error
+        let err =
+            Table::from_qvs20_with_options(s.as_bytes(), &ParseOptions::default()).unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("Error in data row 2, field 2, column id:"));
+        // the lenient mode continues on the next row
+        let report = unwrap!(Table::from_qvs20_with_options(
+            s.as_bytes(),
+            &ParseOptions::lenient()
+        ));
+        let names: Vec<String> = report
+            .table
+            .rows
+            .iter()
+            .map(|r| r.values[0].to_string())
+            .collect();
+        // the raw LF inside of a field does not split the row
+        assert_eq!(names, vec!["one", "six", "a\nb"]);
+        let rejected: Vec<(usize, String)> = report
+            .rejected_rows
+            .iter()
+            .map(|r| (r.row_number, r.error.to_string()))
+            .collect();
+        assert_eq!(
+            rejected,
+            vec![
+                (2, r#"Error in data row 2, field 2, column id: Error: Integer "x" invalid digit found in string (line 6, column 6, pos: 58, near "[two][x]\n")"#.to_string()),
+                (3, r#"Error in data row 3: wrong row delimiter "x" instead of "\n" (line 7, column 8, pos: 69, near "[three]x[3]\n")"#.to_string()),
+                (4, r#"Error in data row 4: more than 2 fields (line 8, column 10, pos: 83, near "four][4][too many]\n")"#.to_string()),
+                (5, r#"Error in data row 5, field 2, column id: duplicate value "1". (line 9, column 7, pos: 100, near "[five][1]\n")"#.to_string()),
+                (8, r#"Error in data row 8: Last row delimiter is missing (line 13, column 11, pos: 132, near "even][7]")"#.to_string()),
+            ]
+        );
+        // the side file has the schema and the raw rejected rows
+        let mut side = vec![];
+        unwrap!(report.write_rejected(&mut side));
+        assert_eq!(
+            unwrap!(String::from_utf8(side)),
+            format!(
+                "{}[two][x]\n[three]x[3]\n[four][4][too many]\n[five][1]\n[seven][7]\n",
+                SCHEMA
+            )
+        );
+        // the schema rows must be correct also in the lenient mode
+        assert!(
+            Table::from_qvs20_with_options(b"[t]\n[Strin]\n", &ParseOptions::lenient()).is_err()
+        );
+    }
+    #[test]
+    pub fn test_02_row_end() {
+        // the row delimiter 1 ends the row only between fields
+        let s = b"[a1][x\\]1[]1[b]1[c]";
+        assert_eq!(next_row_end(s, 0, b"1"), 12);
+        assert_eq!(next_row_end(s, 12, b"1"), 16);
+        assert_eq!(next_row_end(s, 16, b"1"), s.len());
+        // the raw LF inside of a field is not the end of the row
+        assert_eq!(next_row_end(b"[a\n[b]\n", 0, LF), 7);
+        assert_eq!(next_row_end(b"[a\nb][1]\n[c]\n", 0, LF), 9);
+        assert_eq!(next_row_end(b"[a]\r\n[b]\r\n", 0, CRLF), 5);
+    }
+    #[test]
+    pub fn test_03_raw_lf_in_field() {
+        // the correct rows are the same in the strict and the lenient mode
+        let s = b"[t]\n[String][Integer]\n[][]\n[name][id]\n[a\nb][1]\n[c][2]\n";
+        let strict = unwrap!(Table::from_qvs20_with_options(s, &ParseOptions::default()));
+        let lenient = unwrap!(Table::from_qvs20_with_options(s, &ParseOptions::lenient()));
+        assert_eq!(strict.table.rows.len(), 2);
+        assert_eq!(lenient.table.rows.len(), 2);
+        assert!(lenient.rejected_rows.is_empty());
+        assert_eq!(lenient.table.rows[0].values[0].to_string(), "a\nb");
+    }
+    #[test]
+    pub fn test_04_not_utf8() {
+        // the field that is not UTF-8 is a rejected row, not a panic
+        let mut s = SCHEMA.as_bytes().to_vec();
+        s.extend_from_slice(b"[a][1]\n[\xff][2]\n[c][3]\n");
+        let report = unwrap!(Table::from_qvs20_with_options(&s, &ParseOptions::lenient()));
+        assert_eq!(report.table.rows.len(), 2);
+        assert_eq!(report.rejected_rows.len(), 1);
+        assert_eq!(report.rejected_rows[0].row_number, 2);
+        assert_eq!(report.rejected_rows[0].bytes, b"[\xff][2]\n");
+        assert!(report.rejected_rows[0]
+            .error
+            .to_string()
+            .starts_with("Error in data row 2, field 1, column name: Error: invalid UTF-8 at byte 0 of the field (line 6, column 1,"));
+        // the strict mode returns the same error
+        assert!(Table::from_qvs20_with_options(&s, &ParseOptions::default()).is_err());
+    }
+}
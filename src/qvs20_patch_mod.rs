@@ -0,0 +1,484 @@
+// qvs20_patch_mod
+
+// The patch is a qvs20 table with the changes from the base to the next snapshot.
+// The first column is op: insert, update, delete or move.
+// The second column is the position of the row in the next snapshot, Null for delete.
+// The other columns are the same as in the base table.
+// insert, update and move have the full new row, delete has the old row.
+// move is an unchanged row that is not in the same order as in the base.
+// The unchanged rows fill the free positions in the order of the base,
+// so the next snapshot has exactly the rows and the order of the new table.
+// The additional property of the op column has the key column
+// and the checksum of the base table:
+// [op][position][id][name]
+// [key=id base_sha256=9f86d0...][nullable][][]
+// The checksum is sha256 of the canonical qvs20 bytes from the Writer,
+// so a patch cannot be applied to the wrong base.
+// The other columns have the properties of the base without sorted,
+// because the patch rows are in the order of the changes.
+
+use crate::qvs20_diff_mod::*;
+use crate::qvs20_properties_mod::*;
+use crate::qvs20_schema_mod::*;
+use crate::qvs20_table_mod::*;
+
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use thiserror::Error;
+
+/// the name of the first column
+pub const OP_COLUMN: &str = "op";
+/// the name of the second column
+pub const POSITION_COLUMN: &str = "position";
+
+#[derive(Error, Debug)]
+pub enum Qvs20ErrorPatch {
+    #[error("Error: not a patch table: {msg}")]
+    NotAPatch { msg: String },
+    #[error("Error: the patch is for another base, sha256 {expected} instead of {actual}.")]
+    ChecksumMismatch { expected: String, actual: String },
+    #[error(
+        "Error in patch row {row_number}: {op} key {} does not exist in the base.",
+        quoted_or_null(.key)
+    )]
+    KeyNotFound {
+        op: String,
+        key: Option<String>,
+        row_number: usize,
+    },
+    #[error(
+        "Error in patch row {row_number}: insert key {} already exists in the base.",
+        quoted_or_null(.key)
+    )]
+    KeyExists {
+        key: Option<String>,
+        row_number: usize,
+    },
+    #[error(
+        "Error in patch row {row_number}: key {} has already an op in the patch.",
+        quoted_or_null(.key)
+    )]
+    KeyRepeated {
+        key: Option<String>,
+        row_number: usize,
+    },
+    #[error("Error in patch row {row_number}: wrong position {position} for {op}.")]
+    WrongPosition {
+        op: String,
+        position: String,
+        row_number: usize,
+    },
+    #[error("Error: the patch does not give the same rows and order as the new table.")]
+    NotTheNewTable,
+    #[error("Error in patch row {row_number}: unknown op {op:?}.")]
+    UnknownOp { op: String, row_number: usize },
+    #[error("Error: the patched table is not valid: {source}")]
+    ErrorInResult { source: Box<Qvs20ErrorTable> },
+    #[error("{source}")]
+    ErrorDiff {
+        #[from]
+        source: Qvs20ErrorDiff,
+    },
+    #[error("Error: {source}")]
+    ErrorIo {
+        #[from]
+        source: std::io::Error,
+    },
+}
+
+/// sha256 of the canonical qvs20 bytes as lowercase hex
+pub fn table_sha256(table: &Table) -> Result<String, Qvs20ErrorPatch> {
+    let bytes = table.to_qvs20_bytes()?;
+    let hash = Sha256::digest(&bytes);
+    //return
+    Ok(hash.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// the patch from the old to the new table, the rows are matched by the key column.
+/// Applied to the old table the patch gives exactly the new table.
+pub fn make_patch(old: &Table, new: &Table, key_column: &str) -> Result<Table, Qvs20ErrorPatch> {
+    let diff = diff_tables(old, new, key_column)?;
+    let key_pos = key_position(new, key_column)?;
+    let old_index = key_index(old, key_pos)?;
+    let new_index = key_index(new, key_pos)?;
+    let mut patch = Table {
+        schema: Schema {
+            columns: vec![
+                Column {
+                    name: OP_COLUMN.to_string(),
+                    data_type: DataType::String,
+                    properties: ColumnProperties {
+                        list: vec![
+                            Property::Unknown(format!("key={}", key_column)),
+                            Property::Unknown(format!("base_sha256={}", table_sha256(old)?)),
+                        ],
+                    },
+                },
+                Column {
+                    name: POSITION_COLUMN.to_string(),
+                    data_type: DataType::Integer,
+                    properties: ColumnProperties {
+                        list: vec![Property::Nullable],
+                    },
+                },
+            ],
+            ..old.schema.clone()
+        },
+        rows: vec![],
+    };
+    patch
+        .schema
+        .columns
+        .extend(old.schema.columns.iter().map(patch_column));
+    let mut push = |op: &str, position: Option<usize>, row: &Row| {
+        let mut values = Vec::with_capacity(row.values.len() + 2);
+        values.push(Value::String(op.to_string()));
+        values.push(match position {
+            Some(position) => Value::Integer(position as i64),
+            None => Value::Null,
+        });
+        values.extend(row.values.iter().cloned());
+        patch.rows.push(Row { values });
+    };
+    let mut modified = HashSet::new();
+    for change in diff.changes.iter() {
+        match change {
+            RowChange::Added { key, row } => push("insert", Some(new_index[key]), row),
+            RowChange::Removed { row, .. } => push("delete", None, row),
+            RowChange::Modified { key, .. } => {
+                modified.insert(key.clone());
+                push("update", Some(new_index[key]), &new.rows[new_index[key]])
+            }
+        }
+    }
+    // the unchanged rows in the order of the old and of the new table,
+    // the rows that are not at the same place in both orders are moved
+    let unchanged = |table: &Table, other_index: &HashMap<Option<String>, usize>| {
+        table
+            .rows
+            .iter()
+            .map(|row| row.values[key_pos].to_text())
+            .filter(|key| other_index.contains_key(key) && !modified.contains(key))
+            .collect::<Vec<_>>()
+    };
+    let old_order = unchanged(old, &new_index);
+    let new_order = unchanged(new, &old_index);
+    for (old_key, new_key) in old_order.iter().zip(new_order.iter()) {
+        if old_key != new_key {
+            let position = new_index[new_key];
+            push("move", Some(position), &new.rows[position]);
+        }
+    }
+    if table_sha256(&apply_patch(old, &patch)?)? != table_sha256(new)? {
+        return Err(Qvs20ErrorPatch::NotTheNewTable);
+    }
+    //return
+    Ok(patch)
+}
+
+/// the base column in the patch
+fn patch_column(column: &Column) -> Column {
+    Column {
+        properties: column.properties.without_sorted(),
+        ..column.clone()
+    }
+}
+
+/// key column and base checksum from the additional property of the op column
+fn patch_properties(patch: &Table) -> Result<(String, String), Qvs20ErrorPatch> {
+    let not_a_patch = |msg: &str| Qvs20ErrorPatch::NotAPatch {
+        msg: msg.to_string(),
+    };
+    let op_column = match patch.schema.columns.first() {
+        Some(column) if column.name == OP_COLUMN => column,
+        _ => return Err(not_a_patch("the first column must be op")),
+    };
+    match patch.schema.columns.get(1) {
+        Some(column) if column.name == POSITION_COLUMN => (),
+        _ => return Err(not_a_patch("the second column must be position")),
+    }
+    let mut key_column = None;
+    let mut base_sha256 = None;
+    for property in op_column.properties.list.iter() {
+        if let Property::Unknown(token) = property {
+            if let Some(value) = token.strip_prefix("key=") {
+                key_column = Some(value.to_string());
+            } else if let Some(value) = token.strip_prefix("base_sha256=") {
+                base_sha256 = Some(value.to_string());
+            }
+        }
+    }
+    match (key_column, base_sha256) {
+        (Some(key_column), Some(base_sha256)) => Ok((key_column, base_sha256)),
+        _ => Err(not_a_patch(
+            "the op column must have the properties key and base_sha256",
+        )),
+    }
+}
+
+/// apply the patch to the base and return the next snapshot.
+/// The rows with a position are placed there, the other rows of the base
+/// fill the free positions in the same order as in the base.
+/// Every key can have only one op and the next snapshot must pass the RowChecker.
+pub fn apply_patch(base: &Table, patch: &Table) -> Result<Table, Qvs20ErrorPatch> {
+    let (key_column, base_sha256) = patch_properties(patch)?;
+    let base_columns: Vec<Column> = base.schema.columns.iter().map(patch_column).collect();
+    if patch.schema.columns[2..] != base_columns[..] {
+        return Err(Qvs20ErrorPatch::NotAPatch {
+            msg: "the columns are not the same as in the base".to_string(),
+        });
+    }
+    let actual = table_sha256(base)?;
+    if actual != base_sha256 {
+        return Err(Qvs20ErrorPatch::ChecksumMismatch {
+            expected: base_sha256,
+            actual,
+        });
+    }
+    let key_pos = key_position(base, &key_column)?;
+    let base_index = key_index(base, key_pos)?;
+    // the index is of the base, so a second op on the same key would see the old row
+    let mut applied = HashSet::new();
+    // the base rows that keep their order, None is a row with an op
+    let mut base_rows: Vec<Option<&Row>> = base.rows.iter().map(Some).collect();
+    // the rows with the position in the next snapshot
+    let mut placed: Vec<(usize, Row, usize)> = vec![];
+    let mut len = base.rows.len();
+    for (i, patch_row) in patch.rows.iter().enumerate() {
+        let op = patch_row.values[0].to_string();
+        let row = Row {
+            values: patch_row.values[2..].to_vec(),
+        };
+        let key = row.values[key_pos].to_text();
+        if !applied.insert(key.clone()) {
+            return Err(Qvs20ErrorPatch::KeyRepeated {
+                key,
+                row_number: i + 1,
+            });
+        }
+        let wrong_position = || Qvs20ErrorPatch::WrongPosition {
+            op: op.clone(),
+            position: quoted_or_null(&patch_row.values[1].to_text()),
+            row_number: i + 1,
+        };
+        let position = match (op.as_str(), &patch_row.values[1]) {
+            ("delete", Value::Null) => None,
+            ("insert", Value::Integer(p))
+            | ("update", Value::Integer(p))
+            | ("move", Value::Integer(p))
+                if *p >= 0 =>
+            {
+                Some(*p as usize)
+            }
+            ("insert", _) | ("update", _) | ("move", _) | ("delete", _) => {
+                return Err(wrong_position())
+            }
+            _ => {
+                return Err(Qvs20ErrorPatch::UnknownOp {
+                    op,
+                    row_number: i + 1,
+                })
+            }
+        };
+        match (op.as_str(), base_index.get(&key).copied()) {
+            ("insert", None) => len += 1,
+            ("insert", Some(_)) => {
+                return Err(Qvs20ErrorPatch::KeyExists {
+                    key,
+                    row_number: i + 1,
+                })
+            }
+            ("delete", Some(pos)) => {
+                base_rows[pos] = None;
+                len -= 1;
+            }
+            (_, Some(pos)) => base_rows[pos] = None,
+            (_, None) => {
+                return Err(Qvs20ErrorPatch::KeyNotFound {
+                    op,
+                    key,
+                    row_number: i + 1,
+                })
+            }
+        }
+        if let Some(position) = position {
+            placed.push((position, row, i + 1));
+        }
+    }
+    let mut rows: Vec<Option<Row>> = vec![None; len];
+    for (position, row, row_number) in placed {
+        match rows.get_mut(position) {
+            Some(place @ None) => *place = Some(row),
+            _ => {
+                return Err(Qvs20ErrorPatch::WrongPosition {
+                    op: patch.rows[row_number - 1].values[0].to_string(),
+                    position: position.to_string(),
+                    row_number,
+                })
+            }
+        }
+    }
+    // the other rows of the base fill the free positions, the count is exactly right
+    let mut base_rows = base_rows.into_iter().flatten();
+    for place in rows.iter_mut().filter(|place| place.is_none()) {
+        *place = base_rows.next().cloned();
+    }
+    let next = Table {
+        schema: base.schema.clone(),
+        rows: rows.into_iter().flatten().collect(),
+    };
+    let mut checker = RowChecker::new(&next.schema);
+    for (i, row) in next.rows.iter().enumerate() {
+        checker
+            .check_row(i + 1, row)
+            .map_err(|e| Qvs20ErrorPatch::ErrorInResult {
+                source: Box::new(e),
+            })?;
+    }
+    //return
+    Ok(next)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use unwrap::unwrap;
+
+    fn sample() -> (Table, Table) {
+        let old = unwrap!(Table::from_qvs20_with_schema(
+            b"[t]\n[Integer][String]\n[][]\n[id][version]\n[1][0.1.0]\n[2][0.2.0]\n[3][0.3.0]\n"
+        ));
+        let new = unwrap!(Table::from_qvs20_with_schema(
+            b"[t]\n[Integer][String]\n[][]\n[id][version]\n[1][0.1.0]\n[3][0.3.1]\n[4][0.4.0]\n"
+        ));
+        (old, new)
+    }
+
+    #[test]
+    pub fn test_01_make_and_apply() {
+        let (old, new) = sample();
+        let patch = unwrap!(make_patch(&old, &new, "id"));
+        let text = unwrap!(String::from_utf8(unwrap!(patch.to_qvs20_bytes())));
+        assert_eq!(
+            text,
+            format!(
+                "[t]\n[String][Integer][Integer][String]\n[key=id base_sha256={}][nullable][][]\n\
+                 [op][position][id][version]\n\
+                 [delete][\\0][2][0.2.0]\n[update][1][3][0.3.1]\n[insert][2][4][0.4.0]\n",
+                unwrap!(table_sha256(&old))
+            )
+        );
+        // the patch survives the round trip through qvs20
+        let patch = unwrap!(Table::from_qvs20_with_schema(text.as_bytes()));
+        let next = unwrap!(apply_patch(&old, &patch));
+        assert_eq!(unwrap!(table_sha256(&next)), unwrap!(table_sha256(&new)));
+    }
+
+    #[test]
+    pub fn test_02_errors() {
+        let (old, new) = sample();
+        let patch = unwrap!(make_patch(&old, &new, "id"));
+        // wrong base
+        assert!(matches!(
+            apply_patch(&new, &patch),
+            Err(Qvs20ErrorPatch::ChecksumMismatch { .. })
+        ));
+        // not a patch
+        assert!(matches!(
+            apply_patch(&old, &new),
+            Err(Qvs20ErrorPatch::NotAPatch { .. })
+        ));
+        // unknown op
+        let mut wrong = patch.clone();
+        wrong.rows[0].values[0] = Value::String("upsert".to_string());
+        assert_eq!(
+            unwrap!(apply_patch(&old, &wrong).err()).to_string(),
+            r#"Error in patch row 1: unknown op "upsert"."#
+        );
+        // insert of an existing key
+        let mut wrong = patch;
+        wrong.rows[2].values[2] = Value::Integer(1);
+        assert_eq!(
+            unwrap!(apply_patch(&old, &wrong).err()).to_string(),
+            r#"Error in patch row 3: insert key "1" already exists in the base."#
+        );
+        // the position is outside of the next snapshot
+        let mut wrong = unwrap!(make_patch(&old, &new, "id"));
+        wrong.rows[2].values[1] = Value::Integer(3);
+        assert_eq!(
+            unwrap!(apply_patch(&old, &wrong).err()).to_string(),
+            "Error in patch row 3: wrong position 3 for insert."
+        );
+    }
+    #[test]
+    pub fn test_03_repeated_key_and_check() {
+        let old = unwrap!(Table::from_qvs20_with_schema(
+            b"[t]\n[Integer][String]\n[][unique]\n[id][version]\n[1][0.1.0]\n[2][0.2.0]\n"
+        ));
+        let new = unwrap!(Table::from_qvs20_with_schema(
+            b"[t]\n[Integer][String]\n[][unique]\n[id][version]\n[1][0.1.0]\n[3][0.3.0]\n"
+        ));
+        let patch = unwrap!(make_patch(&old, &new, "id"));
+        // the same insert twice
+        let mut wrong = patch.clone();
+        wrong.rows.push(wrong.rows[1].clone());
+        assert_eq!(
+            unwrap!(apply_patch(&old, &wrong).err()).to_string(),
+            r#"Error in patch row 3: key "3" has already an op in the patch."#
+        );
+        // update after delete does not bring back the deleted row
+        let mut wrong = patch.clone();
+        let mut update = wrong.rows[0].clone();
+        update.values[0] = Value::String("update".to_string());
+        wrong.rows.push(update);
+        assert_eq!(
+            unwrap!(apply_patch(&old, &wrong).err()).to_string(),
+            r#"Error in patch row 3: key "2" has already an op in the patch."#
+        );
+        // the next snapshot must have unique versions
+        let mut wrong = patch;
+        wrong.rows[1].values[3] = Value::String("0.1.0".to_string());
+        let err = unwrap!(apply_patch(&old, &wrong).err());
+        assert!(matches!(err, Qvs20ErrorPatch::ErrorInResult { .. }));
+        assert!(err.to_string().starts_with(
+            "Error: the patched table is not valid: Error in data row 2, field 2, column version:"
+        ));
+    }
+    #[test]
+    pub fn test_04_row_order() {
+        let table = |rows: &str| {
+            let text = format!(
+                "[t]\n[Integer][String]\n[sorted][]\n[id][version]\n{}",
+                rows
+            );
+            unwrap!(Table::from_qvs20_with_schema(text.as_bytes()))
+        };
+        let check = |old: &Table, new: &Table| {
+            let patch = unwrap!(make_patch(old, new, "id"));
+            let next = unwrap!(apply_patch(old, &patch));
+            assert_eq!(unwrap!(table_sha256(&next)), unwrap!(table_sha256(new)));
+            patch
+        };
+        // the insert in the middle of the sorted key column
+        let old = table("[1][a]\n[3][c]\n[5][e]\n");
+        let new = table("[1][a]\n[2][b]\n[3][c]\n[4][d]\n[5][e]\n");
+        assert_eq!(check(&old, &new).rows.len(), 2);
+        // delete and update in the middle
+        check(&new, &table("[1][a]\n[3][x]\n[5][e]\n"));
+        // a pure reorder is a patch with the moved rows, without sorted
+        let unsorted = |rows: &str| {
+            let text = format!("[t]\n[Integer][String]\n[][]\n[id][version]\n{}", rows);
+            unwrap!(Table::from_qvs20_with_schema(text.as_bytes()))
+        };
+        let old = unsorted("[1][a]\n[2][b]\n[3][c]\n[4][d]\n");
+        let new = unsorted("[4][d]\n[2][b]\n[1][a]\n[3][c]\n");
+        let patch = check(&old, &new);
+        assert_eq!(patch.rows.len(), 3);
+        assert!(patch
+            .rows
+            .iter()
+            .all(|row| row.values[0] == Value::String("move".to_string())));
+        // reorder together with all the other ops
+        check(&new, &unsorted("[5][e]\n[3][c]\n[1][x]\n[2][b]\n"));
+    }
+}
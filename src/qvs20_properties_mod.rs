@@ -0,0 +1,520 @@
+// qvs20_properties_mod
+
+// The third schema row has the additional properties of every column.
+// The properties of one column are separated by space:
+// [primary_key][unique max_len=64][default=0][enum=normal|build|dev][sorted nullable]
+// The text is split on whitespace, so the values of default= and enum= cannot contain spaces.
+// The default value is only for the missing value [\0], the empty field [] is the empty text.
+// The defined properties are enforced when a table is loaded, streamed or written.
+// The unknown properties are preserved verbatim, so other tools can add their own.
+// The order of the properties is preserved, the text is written back as it was read.
+
+use crate::qvs20_reader_mod::{ReaderForQvs20, CRLF};
+use crate::qvs20_schema_mod::*;
+use crate::qvs20_table_mod::*;
+
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::fmt;
+use std::str::FromStr;
+
+/// one property of a column
+#[derive(Clone, Debug, PartialEq)]
+pub enum Property {
+    /// the column can have null values
+    Nullable,
+    /// the values are unique in the table
+    Unique,
+    /// unique and not nullable, only one column in the table
+    PrimaryKey,
+    /// max length of the canonical text in characters
+    MaxLen(usize),
+    /// the canonical text of the value for the missing value [\0], without whitespace
+    Default(String),
+    /// the canonical text must be one of these values, without whitespace
+    Enum(Vec<String>),
+    /// the rows are sorted ascending by this column
+    Sorted,
+    /// not defined by qvs20, preserved verbatim
+    Unknown(String),
+}
+
+/// all the properties of one column in the original order
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ColumnProperties {
+    pub list: Vec<Property>,
+}
+
+impl FromStr for ColumnProperties {
+    type Err = String;
+    /// parse the text of the third row field
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let mut list = vec![];
+        for token in text.split_whitespace() {
+            let property = match token.split_once('=') {
+                None => match token {
+                    "nullable" => Property::Nullable,
+                    "unique" => Property::Unique,
+                    "primary_key" => Property::PrimaryKey,
+                    "sorted" => Property::Sorted,
+                    _ => Property::Unknown(token.to_string()),
+                },
+                Some(("max_len", value)) => match value.parse::<usize>() {
+                    Ok(max_len) => Property::MaxLen(max_len),
+                    Err(_) => return Err(format!("max_len {:?} is not a number", value)),
+                },
+                Some(("default", value)) => Property::Default(value.to_string()),
+                Some(("enum", value)) => {
+                    if value.is_empty() {
+                        return Err("enum has no values".to_string());
+                    }
+                    Property::Enum(value.split('|').map(|v| v.to_string()).collect())
+                }
+                Some(_) => Property::Unknown(token.to_string()),
+            };
+            list.push(property);
+        }
+        //return
+        Ok(ColumnProperties { list })
+    }
+}
+
+impl fmt::Display for Property {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Property::Nullable => write!(f, "nullable"),
+            Property::Unique => write!(f, "unique"),
+            Property::PrimaryKey => write!(f, "primary_key"),
+            Property::MaxLen(max_len) => write!(f, "max_len={}", max_len),
+            Property::Default(value) => write!(f, "default={}", value),
+            Property::Enum(values) => write!(f, "enum={}", values.join("|")),
+            Property::Sorted => write!(f, "sorted"),
+            Property::Unknown(token) => write!(f, "{}", token),
+        }
+    }
+}
+
+/// the text of the third row field, the properties separated by space
+impl fmt::Display for ColumnProperties {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, property) in self.list.iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{}", property)?;
+        }
+        Ok(())
+    }
+}
+
+impl ColumnProperties {
+    pub fn is_nullable(&self) -> bool {
+        self.list.contains(&Property::Nullable)
+    }
+    /// unique or primary_key
+    pub fn is_unique(&self) -> bool {
+        self.list.contains(&Property::Unique) || self.is_primary_key()
+    }
+    pub fn is_primary_key(&self) -> bool {
+        self.list.contains(&Property::PrimaryKey)
+    }
+    pub fn is_sorted(&self) -> bool {
+        self.list.contains(&Property::Sorted)
+    }
+    pub fn max_len(&self) -> Option<usize> {
+        self.list.iter().find_map(|p| match p {
+            Property::MaxLen(max_len) => Some(*max_len),
+            _ => None,
+        })
+    }
+    pub fn default_value(&self) -> Option<&str> {
+        self.list.iter().find_map(|p| match p {
+            Property::Default(value) => Some(value.as_str()),
+            _ => None,
+        })
+    }
+    pub fn enum_values(&self) -> Option<&[String]> {
+        self.list.iter().find_map(|p| match p {
+            Property::Enum(values) => Some(values.as_slice()),
+            _ => None,
+        })
+    }
+    /// the same properties without sorted
+    pub fn without_sorted(&self) -> ColumnProperties {
+        ColumnProperties {
+            list: self
+                .list
+                .iter()
+                .filter(|p| **p != Property::Sorted)
+                .cloned()
+                .collect(),
+        }
+    }
+}
+
+impl Column {
+    /// the enum values as the canonical text of the data type, like Value::to_string(),
+    /// so enum=1|2 matches the Float 1.0 and enum=+1 matches the Integer 1
+    pub fn canonical_enum_values(&self) -> Result<Option<Vec<String>>, Qvs20ErrorTable> {
+        match self.properties.enum_values() {
+            None => Ok(None),
+            Some(values) => values
+                .iter()
+                .map(|value| {
+                    Value::from_text(value.to_string(), &self.data_type).map(|v| v.to_string())
+                })
+                .collect::<Result<Vec<String>, _>>()
+                .map(Some),
+        }
+    }
+}
+
+impl Schema {
+    /// the properties must fit together and with the data type of the column
+    pub fn check_properties(&self) -> Result<(), Qvs20ErrorSchema> {
+        let error = |i: usize, column: &Column, msg: String| Qvs20ErrorSchema::ErrorInProperties {
+            field: i + 1,
+            column: column.name.clone(),
+            msg,
+            position: None,
+        };
+        let mut primary_key: Option<&str> = None;
+        for (i, column) in self.columns.iter().enumerate() {
+            let properties = &column.properties;
+            if properties.is_primary_key() {
+                if let Some(other) = primary_key {
+                    return Err(error(
+                        i,
+                        column,
+                        format!("second primary_key, the first is {}", other),
+                    ));
+                }
+                if properties.is_nullable() {
+                    return Err(error(
+                        i,
+                        column,
+                        "primary_key cannot be nullable".to_string(),
+                    ));
+                }
+                primary_key = Some(&column.name);
+            }
+            if let Some(values) = properties.enum_values() {
+                for value in values.iter() {
+                    if value.contains(char::is_whitespace) {
+                        return Err(error(
+                            i,
+                            column,
+                            format!("enum value {:?} has whitespace", value),
+                        ));
+                    }
+                }
+                if let Err(e) = column.canonical_enum_values() {
+                    return Err(error(i, column, format!("enum {}", e)));
+                }
+            }
+            if let Some(value) = properties.default_value() {
+                if properties.is_nullable() {
+                    return Err(error(
+                        i,
+                        column,
+                        "default cannot be nullable, the missing value gets the default"
+                            .to_string(),
+                    ));
+                }
+                if value.contains(char::is_whitespace) {
+                    return Err(error(
+                        i,
+                        column,
+                        format!("default {:?} has whitespace", value),
+                    ));
+                }
+                if let Err(e) = Value::from_text(value.to_string(), &column.data_type) {
+                    return Err(error(i, column, format!("default {}", e)));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// checks the properties of the values row by row
+/// for unique and sorted it remembers the previous rows
+pub struct RowChecker {
+    columns: Vec<Column>,
+    /// the nested tables must have a different row delimiter
+    row_delimiter: Vec<u8>,
+    /// canonical texts of the enum values, None for the columns without enum
+    enum_texts: Vec<Option<Vec<String>>>,
+    /// canonical texts of the unique columns
+    seen: Vec<HashSet<String>>,
+    /// the value of the previous row for the sorted columns
+    previous: Vec<Option<Value>>,
+}
+
+impl RowChecker {
+    /// constructor
+    pub fn new(schema: &Schema) -> RowChecker {
+        RowChecker {
+            columns: schema.columns.clone(),
+            row_delimiter: schema.row_delimiter.clone(),
+            // the wrong enum value is reported by check_properties(), here it stays as it is
+            enum_texts: schema
+                .columns
+                .iter()
+                .map(|column| {
+                    column
+                        .canonical_enum_values()
+                        .unwrap_or_else(|_| column.properties.enum_values().map(|v| v.to_vec()))
+                })
+                .collect(),
+            seen: vec![HashSet::new(); schema.columns.len()],
+            previous: vec![None; schema.columns.len()],
+        }
+    }
+
+    /// the values of one row, row_number is 1-based, returns the first violation
+    /// a row with a violation is not remembered for unique and sorted,
+    /// so the lenient parser can reject the row and continue with the next one
+    pub fn check_row(&mut self, row_number: usize, row: &Row) -> Result<(), Qvs20ErrorTable> {
+        match self.row_violations(row_number, row).into_iter().next() {
+            Some(violation) => Err(violation),
+            None => Ok(()),
+        }
+    }
+
+    /// all the violations of one row, in the order of the columns
+    /// like check_row() the row is remembered only if there is no violation
+    pub fn row_violations(&mut self, row_number: usize, row: &Row) -> Vec<Qvs20ErrorTable> {
+        let mut violations = vec![];
+        // remembered only after all the columns are checked
+        let mut seen_texts = vec![];
+        let mut previous_values = vec![];
+        for (i, (column, value)) in self.columns.iter().zip(row.values.iter()).enumerate() {
+            let properties = &column.properties;
+            let mut violation = |msg: String| {
+                violations.push(Qvs20ErrorTable::PropertyViolation {
+                    row_number,
+                    field: i + 1,
+                    column: column.name.clone(),
+                    msg,
+                    position: None,
+                })
+            };
+            // null is not checked for the other properties
+            if *value == Value::Null {
+                if !properties.is_nullable() {
+                    violation("null in a column that is not nullable".to_string());
+                }
+                continue;
+            }
+            if let Value::Table(t) = value {
+                let row_delimiter = &t.schema.row_delimiter[..];
+                if row_delimiter.len() != 1 && row_delimiter != CRLF {
+                    violation(format!(
+                        "the nested table has the row delimiter {}, it must be 1 byte or CRLF",
+                        ReaderForQvs20::row_delimiter_text(row_delimiter)
+                    ));
+                } else if t.schema.row_delimiter == self.row_delimiter {
+                    violation(format!(
+                        "the nested table has the same row delimiter {} as the parent",
+                        ReaderForQvs20::row_delimiter_text(&self.row_delimiter)
+                    ));
+                }
+            }
+            let needs_text = properties.max_len().is_some()
+                || properties.enum_values().is_some()
+                || properties.is_unique();
+            if needs_text {
+                let text = value.to_string();
+                if let Some(max_len) = properties.max_len() {
+                    let len = text.chars().count();
+                    if len > max_len {
+                        violation(format!("length {} is more than max_len={}", len, max_len));
+                    }
+                }
+                if let (Some(values), Some(texts)) = (properties.enum_values(), &self.enum_texts[i])
+                {
+                    if !texts.contains(&text) {
+                        violation(format!("{:?} is not in enum={}", text, values.join("|")));
+                    }
+                }
+                if properties.is_unique() {
+                    if self.seen[i].contains(&text) {
+                        violation(format!("duplicate value {:?}", text));
+                    } else {
+                        seen_texts.push((i, text));
+                    }
+                }
+            }
+            if properties.is_sorted() {
+                if let Some(previous) = &self.previous[i] {
+                    if compare_values(previous, value) == Some(Ordering::Greater) {
+                        violation(format!(
+                            "{:?} is smaller than {:?} in the previous row",
+                            value.to_string(),
+                            previous.to_string()
+                        ));
+                    }
+                }
+                previous_values.push((i, value.clone()));
+            }
+        }
+        if violations.is_empty() {
+            for (i, text) in seen_texts {
+                self.seen[i].insert(text);
+            }
+            for (i, value) in previous_values {
+                self.previous[i] = Some(value);
+            }
+        }
+        violations
+    }
+}
+
+/// the order of two values of the same data type, None for different data types
+/// the nested tables have no order
+pub fn compare_values(a: &Value, b: &Value) -> Option<Ordering> {
+    match (a, b) {
+        (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+        (Value::Integer(a), Value::Integer(b)) => Some(a.cmp(b)),
+        (Value::Decimal(a), Value::Decimal(b)) => Some(a.cmp(b)),
+        (Value::Float(a), Value::Float(b)) => a.partial_cmp(b),
+        (Value::Bool(a), Value::Bool(b)) => Some(a.cmp(b)),
+        (Value::Date(a), Value::Date(b)) => Some(a.cmp(b)),
+        (Value::Time(a), Value::Time(b)) => Some(a.cmp(b)),
+        (Value::DateTime(a), Value::DateTime(b)) => Some(a.cmp(b)),
+        (Value::Bytes(a), Value::Bytes(b)) => Some(a.cmp(b)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use unwrap::unwrap;
+    #[test]
+    pub fn test_01_parse_and_write() {
+        let text = "unique max_len=20 x-index=btree default=a enum=a|b|c sorted";
+        let properties = unwrap!(ColumnProperties::from_str(text));
+        assert!(properties.is_unique());
+        assert!(!properties.is_nullable());
+        assert_eq!(properties.max_len(), Some(20));
+        assert_eq!(properties.default_value(), Some("a"));
+        assert_eq!(unwrap!(properties.enum_values()).len(), 3);
+        // unknown properties are preserved verbatim
+        assert_eq!(
+            properties.list[2],
+            Property::Unknown("x-index=btree".to_string())
+        );
+        assert_eq!(properties.to_string(), text);
+        assert!(ColumnProperties::from_str("max_len=ten").is_err());
+        assert!(ColumnProperties::from_str("enum=").is_err());
+    }
+    #[test]
+    pub fn test_02_enforced_on_load_and_write() {
+        let schema = "[t]\n[Integer][String][String][Integer]\n[primary_key sorted][max_len=3][enum=a|b nullable][default=7]\n[id][short][kind][n]\n";
+        let table = unwrap!(Table::from_qvs20_with_schema(
+            format!("{}[1][abc][a][\\0]\n[2][][b][8]\n", schema).as_bytes()
+        ));
+        // the missing value gets the default value
+        assert_eq!(table.rows[0].values[3], Value::Integer(7));
+        // the properties are written back
+        assert!(unwrap!(String::from_utf8(unwrap!(table.to_qvs20_bytes()))).starts_with(schema));
+        let error = |data: &str| {
+            Table::from_qvs20_with_schema(format!("{}{}", schema, data).as_bytes())
+                .unwrap_err()
+                .to_string()
+        };
+        assert_eq!(
+            error("[1][abc][a][9]\n[1][abc][a][9]\n"),
+            r#"Error in data row 2, field 1, column id: duplicate value "1". (line 6, column 1, pos: 137, near "][a][9]\n[1][abc][a][9]\n")"#
+        );
+        assert_eq!(
+            error("[2][abc][a][9]\n[1][abc][a][9]\n"),
+            r#"Error in data row 2, field 1, column id: "1" is smaller than "2" in the previous row. (line 6, column 1, pos: 137, near "][a][9]\n[1][abc][a][9]\n")"#
+        );
+        assert_eq!(
+            error("[1][abcd][a][9]\n"),
+            r#"Error in data row 1, field 2, column short: length 4 is more than max_len=3. (line 5, column 4, pos: 125, near "][n]\n[1][abcd][a][9]\n")"#
+        );
+        assert_eq!(
+            error("[1][abc][c][9]\n"),
+            r#"Error in data row 1, field 3, column kind: "c" is not in enum=a|b. (line 5, column 9, pos: 130, near "[1][abc][c][9]\n")"#
+        );
+        // the writer does not write a table that breaks the properties
+        let mut wrong = table.clone();
+        wrong.rows[1].values[0] = Value::Integer(1);
+        assert_eq!(
+            wrong.to_qvs20_bytes().unwrap_err().to_string(),
+            r#"Error in data row 2, field 1, column id: duplicate value "1"."#
+        );
+        // the properties must fit together
+        let error = |third_row: &str| {
+            Schema::from_qvs20(
+                format!("[t]\n[Integer][Integer]\n{}\n[a][b]\n", third_row).as_bytes(),
+            )
+            .unwrap_err()
+            .to_string()
+        };
+        assert_eq!(
+            error("[primary_key][primary_key]"),
+            r#"Error in third row: field 2, column b: second primary_key, the first is a. (line 3, column 14, pos: 36, near "ary_key][primary_key]\n[a")"#
+        );
+        assert_eq!(
+            error("[primary_key nullable][]"),
+            r#"Error in third row: field 1, column a: primary_key cannot be nullable. (line 3, column 1, pos: 23, near "nteger]\n[primary_key nul")"#
+        );
+        assert_eq!(
+            error("[][default=x]"),
+            r#"Error in third row: field 2, column b: default Error: Integer "x" invalid digit found in string. (line 3, column 3, pos: 25, near "eger]\n[][default=x]\n[a][")"#
+        );
+        assert_eq!(
+            error("[max_len=x][]"),
+            r#"Error in third row: field 1, column a: max_len "x" is not a number. (line 3, column 1, pos: 23, near "nteger]\n[max_len=x][]\n[a")"#
+        );
+        assert_eq!(
+            error("[default=1 nullable][]"),
+            r#"Error in third row: field 1, column a: default cannot be nullable, the missing value gets the default. (line 3, column 1, pos: 23, near "nteger]\n[default=1 nulla")"#
+        );
+    }
+    #[test]
+    pub fn test_03_default_round_trip() {
+        let schema = "[t]\n[String][String]\n[default=x][]\n[a][b]\n";
+        let table = unwrap!(Table::from_qvs20_with_schema(
+            format!("{}[\\0][1]\n[][2]\n[y][3]\n", schema).as_bytes()
+        ));
+        // only the missing value gets the default, the empty text stays empty
+        let a: Vec<String> = table.rows.iter().map(|r| r.values[0].to_string()).collect();
+        assert_eq!(a, vec!["x", "", "y"]);
+        let bytes = unwrap!(table.to_qvs20_bytes());
+        assert_eq!(
+            unwrap!(String::from_utf8(bytes.clone())),
+            format!("{}[x][1]\n[][2]\n[y][3]\n", schema)
+        );
+        assert_eq!(unwrap!(Table::from_qvs20_with_schema(&bytes)), table);
+        // the values cannot have whitespace, because the properties are split on whitespace
+        let mut wrong = table.schema.clone();
+        wrong.columns[0].properties.list = vec![Property::Default("x y".to_string())];
+        assert!(wrong.to_qvs20_bytes().is_err());
+        wrong.columns[0].properties.list = vec![Property::Enum(vec!["x y".to_string()])];
+        assert!(wrong.to_qvs20_bytes().is_err());
+    }
+    #[test]
+    pub fn test_04_enum_canonical_text() {
+        // the enum values are compared as the canonical text of the data type
+        let table = unwrap!(Table::from_qvs20_with_schema(
+            b"[t]\n[Float][Integer]\n[enum=1|2.5][enum=+1|-2]\n[f][i]\n[1.0][1]\n[2.5][-2]\n[1][+1]\n"
+        ));
+        assert_eq!(table.rows.len(), 3);
+        assert_eq!(table.rows[0].values[0], Value::Float(1.0));
+        // and the table with the Values is written back
+        unwrap!(table.to_qvs20_bytes());
+        let err = unwrap!(Table::from_qvs20_with_schema(
+            b"[t]\n[Float][Integer]\n[enum=1|2.5][enum=+1|-2]\n[f][i]\n[2][1]\n"
+        )
+        .err());
+        assert!(err
+            .to_string()
+            .starts_with(r#"Error in data row 1, field 1, column f: "2.0" is not in enum=1|2.5."#));
+    }
+}
@@ -1,23 +1,166 @@
 // qvs20_reader_mod
 
+// The row delimiter is the byte after the last field of the row.
+// LF is the usual row delimiter, the nested tables use 1, 2, 3... for every level.
+// CRLF from Windows is the only row delimiter with 2 bytes.
+// Every error has the Position: byte position, line and column like in a text editor
+// and a short excerpt of the bytes around it. The lines are counted by LF only,
+// because the nested tables with the row delimiters 1, 2, 3... are usually in the same line.
+
+use std::fmt;
 use thiserror::Error;
 
+/// the usual row delimiter
+pub const LF: &[u8] = b"\n";
+/// the row delimiter of Windows
+pub const CRLF: &[u8] = b"\r\n";
+
+/// bytes of the excerpt before the position
+const EXCERPT_BEFORE: usize = 8;
+/// bytes of the excerpt from the position on
+const EXCERPT_AFTER: usize = 16;
+
+// Display is implemented below, because the reason is used also without the position
 #[derive(Error, Debug)]
 pub enum Qvs20ErrorReader {
     // reader
-    #[error("Error: The field must start with [. pos: {pos}")]
-    NoFieldStart { pos: usize },
-    #[error("Error: Last bracket is missing. pos: {pos}")]
-    NoFieldEnd { pos: usize },
-    #[error("Error: Last row delimiter is missing. pos: {pos}")]
-    NoLastRowDelimiter { pos: usize },
-    #[error("Error: The row delimiter has more than 1 byte. pos: {pos}")]
-    RowDelimiterMoreThan1Byte { pos: usize },
-    #[error("Error: Premature end of file. pos: {pos}")]
-    PrematureEndOfFile { pos: usize },
+    NoFieldStart { position: Position },
+    NoFieldEnd { position: Position },
+    NoLastRowDelimiter { position: Position },
+    RowDelimiterNotAllowed { position: Position },
+    PrematureEndOfFile { position: Position },
+}
+
+impl Qvs20ErrorReader {
+    /// the reason of the error without the position
+    /// the errors of the schema and data rows report the position on their own
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Qvs20ErrorReader::NoFieldStart { .. } => "The field must start with [",
+            Qvs20ErrorReader::NoFieldEnd { .. } => "Last bracket is missing",
+            Qvs20ErrorReader::NoLastRowDelimiter { .. } => "Last row delimiter is missing",
+            Qvs20ErrorReader::RowDelimiterNotAllowed { .. } => {
+                "The row delimiter must be 1 byte or CRLF"
+            }
+            Qvs20ErrorReader::PrematureEndOfFile { .. } => "Premature end of file",
+        }
+    }
+    /// where is the error in the input
+    pub fn position(&self) -> &Position {
+        match self {
+            Qvs20ErrorReader::NoFieldStart { position }
+            | Qvs20ErrorReader::NoFieldEnd { position }
+            | Qvs20ErrorReader::NoLastRowDelimiter { position }
+            | Qvs20ErrorReader::RowDelimiterNotAllowed { position }
+            | Qvs20ErrorReader::PrematureEndOfFile { position } => position,
+        }
+    }
+}
+
+impl fmt::Display for Qvs20ErrorReader {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Error: {} {}", self.reason(), self.position())
+    }
+}
+
+/// the place in the input for the error messages
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Position {
+    /// 0-based byte position
+    pub pos: usize,
+    /// 1-based line, only LF starts a new line
+    pub line: usize,
+    /// 1-based column in characters, not bytes
+    pub column: usize,
+    /// the bytes around the position as lossy utf8, empty for an origin
+    pub excerpt: String,
+}
+
+impl Default for Position {
+    /// the start of the input
+    fn default() -> Self {
+        Position {
+            pos: 0,
+            line: 1,
+            column: 1,
+            excerpt: String::new(),
+        }
+    }
+}
+
+/// the text of the optional position at the end of an error message, with a space before it
+pub(crate) fn optional_position_text(position: &Option<Position>) -> String {
+    match position {
+        Some(position) => format!(" {}", position),
+        None => String::new(),
+    }
+}
+
+impl Position {
+    /// the position of the byte pos in the input
+    pub fn new(input: &[u8], pos: usize) -> Position {
+        Position::default().position_in(input, pos)
+    }
+
+    /// self is the origin: the position of the first byte of the input in the whole file
+    /// the stream reader parses every row on its own, but reports the position in the file
+    pub fn position_in(&self, input: &[u8], pos: usize) -> Position {
+        let pos = pos.min(input.len());
+        let mut position = self.advance(&input[..pos]);
+        // the excerpt does not cut a utf8 character in half
+        let is_char_start = |i: usize| i >= input.len() || input[i] & 0b1100_0000 != 0b1000_0000;
+        let mut start = pos.saturating_sub(EXCERPT_BEFORE);
+        while !is_char_start(start) {
+            start += 1;
+        }
+        let mut end = (pos + EXCERPT_AFTER).min(input.len());
+        while !is_char_start(end) {
+            end -= 1;
+        }
+        position.excerpt = String::from_utf8_lossy(&input[start..end]).to_string();
+        //return
+        position
+    }
+
+    /// the position after the bytes, without excerpt
+    pub fn advance(&self, bytes: &[u8]) -> Position {
+        // characters are counted by the first byte, the utf8 continuation bytes are skipped
+        let count_chars = |bytes: &[u8]| {
+            bytes
+                .iter()
+                .filter(|&&b| b & 0b1100_0000 != 0b1000_0000)
+                .count()
+        };
+        let (line, column) = match bytes.iter().rposition(|&b| b == b'\n') {
+            Some(last_lf) => (
+                self.line + bytes.iter().filter(|&&b| b == b'\n').count(),
+                1 + count_chars(&bytes[last_lf + 1..]),
+            ),
+            None => (self.line, self.column + count_chars(bytes)),
+        };
+        //return
+        Position {
+            pos: self.pos + bytes.len(),
+            line,
+            column,
+            excerpt: String::new(),
+        }
+    }
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "(line {}, column {}, pos: {}, near {:?})",
+            self.line, self.column, self.pos, self.excerpt
+        )
+    }
 }
 
 /// ReaderForQvs20
+/// Clone is cheap, the clone can look ahead without moving the original cursor
+#[derive(Clone)]
 pub struct ReaderForQvs20<'a> {
     // All the fields are internal and not public.
     // The only way to interact is through methods.
@@ -27,9 +170,12 @@ pub struct ReaderForQvs20<'a> {
     cursor_state: CursorState,
     /// cursor position
     cursor_pos: usize,
+    /// the position of the first byte of the input in the whole file
+    origin: Position,
 }
 
 /// internal enum
+#[derive(Clone)]
 enum CursorState {
     /// start of file
     StartOfField,
@@ -48,33 +194,63 @@ enum CursorState {
 pub enum Token<'a> {
     /// field content - not unescaped  
     Field(&'a [u8]),
-    /// row delimiter  
-    RowDelimiter(u8),
+    /// row delimiter, 1 byte or CRLF
+    RowDelimiter(&'a [u8]),
 }
 
 impl<'a> ReaderForQvs20<'a> {
     /// Constructor. String (but in [u8] form) is immutably borrowed here. No allocation.  
-    pub fn new(input: &[u8]) -> ReaderForQvs20 {
+    pub fn new(input: &[u8]) -> ReaderForQvs20<'_> {
         ReaderForQvs20 {
             input,
             cursor_state: CursorState::StartOfField,
             cursor_pos: 0,
+            origin: Position::default(),
         }
     }
+    /// Constructor for a part of a file, the positions in the errors are in the whole file
+    pub fn with_origin(input: &[u8], origin: Position) -> ReaderForQvs20<'_> {
+        ReaderForQvs20 {
+            origin,
+            ..ReaderForQvs20::new(input)
+        }
+    }
+    /// byte position of the cursor, before next() it is the start of the next token
+    pub fn pos(&self) -> usize {
+        self.cursor_pos
+    }
+    /// line, column and excerpt of the byte position pos
+    pub fn position_at(&self, pos: usize) -> Position {
+        self.origin.position_in(self.input, pos)
+    }
+    /// line, column and excerpt of the 1-based field in the row that starts at row_pos
+    /// the row is read again from the start, only for the error messages
+    pub fn field_position(&self, row_pos: usize, field: usize) -> Position {
+        let mut rdr = ReaderForQvs20 {
+            input: self.input,
+            cursor_state: CursorState::StartOfField,
+            cursor_pos: row_pos,
+            origin: self.origin.clone(),
+        };
+        for _ in 1..field {
+            match rdr.next() {
+                Some(Ok(Token::Field(_))) => {}
+                _ => break,
+            }
+        }
+        rdr.position_at(rdr.pos())
+    }
+    /// the row delimiter as readable text for messages: "\n", "\r\n", "1"
+    pub fn row_delimiter_text(row_delimiter: &[u8]) -> String {
+        format!("{:?}", String::from_utf8_lossy(row_delimiter))
+    }
     /// low level - find u8 from pos_cursor
     pub fn find_u8_from(source_str: &[u8], pos_cursor: usize, find_u8: u8) -> Option<usize> {
         //print!("find_u8_from {}, {}, {}",unwrap!(String::from_utf8(source_str.to_vec())),pos_cursor,unwrap!(String::from_utf8(vec![find_u8])));
         let slice01 = &source_str[pos_cursor..];
         let opt_pos_found = slice01.iter().position(|&s| s == find_u8);
-        if let Some(pos_found) = opt_pos_found {
-            // return Option with usize
-            //println!("return {} {}",pos_cursor,pos_found);
-            Some(pos_cursor + pos_found)
-        } else {
-            //println!("return None");
-            // return
-            None
-        }
+        // return Option with usize
+        opt_pos_found.map(|pos_found| pos_cursor + pos_found)
     }
 }
 
@@ -94,47 +270,60 @@ impl<'a> Iterator for ReaderForQvs20<'a> {
                         self.cursor_pos += 1;
                         if self.cursor_pos >= self.input.len() {
                             return Some(Err(Qvs20ErrorReader::PrematureEndOfFile {
-                                pos: self.cursor_pos,
+                                position: self.position_at(self.cursor_pos),
                             }));
                         }
                     // continue loop
                     } else {
                         return Some(Err(Qvs20ErrorReader::NoFieldStart {
-                            pos: self.cursor_pos,
+                            position: self.position_at(self.cursor_pos),
                         }));
                     }
                 }
                 CursorState::InsideOfField => {
                     let start_pos = self.cursor_pos;
-                    while let Some(pos) = Self::find_u8_from(self.input, self.cursor_pos, b']') {
-                        if self.input[pos - 1] == b'\\' {
-                            // if before the delimiter is \ (escaped), then find the next
-                            self.cursor_pos += 1;
-                            if self.cursor_pos >= self.input.len() {
+                    // The escape sequence is always exactly 2 bytes: \ and the escaped byte.
+                    // Jump over the whole sequence, so the ] after an escaped backslash \\
+                    // is the end of the field, but the ] in \] is not.
+                    let mut opt_end_pos = None;
+                    let mut pos = start_pos;
+                    while let Some(found) = self.input[pos..]
+                        .iter()
+                        .position(|&b| b == b']' || b == b'\\')
+                    {
+                        let found = pos + found;
+                        if self.input[found] == b']' {
+                            opt_end_pos = Some(found);
+                            break;
+                        }
+                        pos = found + 2;
+                        if pos >= self.input.len() {
+                            break;
+                        }
+                    }
+                    let end_pos = match opt_end_pos {
+                        Some(p) => p,
+                        None => {
+                            // only escaped brackets or a lonely backslash at the end
+                            if self.input[start_pos..].contains(&b']') {
                                 return Some(Err(Qvs20ErrorReader::PrematureEndOfFile {
-                                    pos: self.cursor_pos,
+                                    position: self.position_at(self.input.len()),
+                                }));
+                            } else {
+                                return Some(Err(Qvs20ErrorReader::NoFieldEnd {
+                                    position: self.position_at(start_pos),
                                 }));
                             }
-                        //continue while
-                        } else {
-                            self.cursor_pos = pos;
-                            break;
                         }
-                    }
-                    if self.input[self.cursor_pos] != b']' {
-                        return Some(Err(Qvs20ErrorReader::NoFieldEnd {
-                            pos: self.cursor_pos,
-                        }));
-                    }
-                    let end_pos = self.cursor_pos;
-                    self.cursor_pos += 1;
+                    };
+                    self.cursor_pos = end_pos + 1;
                     self.cursor_state = CursorState::OutsideOfField;
                     return Some(Ok(Token::Field(&self.input[start_pos..end_pos])));
                 }
                 CursorState::OutsideOfField => {
                     if self.cursor_pos >= self.input.len() {
                         return Some(Err(Qvs20ErrorReader::NoLastRowDelimiter {
-                            pos: self.cursor_pos,
+                            position: self.position_at(self.cursor_pos),
                         }));
                     } else if self.input[self.cursor_pos] == b'[' {
                         self.cursor_state = CursorState::StartOfField;
@@ -143,21 +332,25 @@ impl<'a> Iterator for ReaderForQvs20<'a> {
                     }
                 }
                 CursorState::InsideRowDelimiter => {
-                    // row_delimiter must be only one byte 1,2,3..a,b,c..
+                    // row_delimiter is one byte 1,2,3..a,b,c.. or CRLF
                     // the hierarchy will never be very deep. Probably till 3.
-                    if self.cursor_pos + 1 >= self.input.len() {
+                    let start_pos = self.cursor_pos;
+                    let end_pos = if self.input[start_pos..].starts_with(CRLF) {
+                        start_pos + 2
+                    } else {
+                        start_pos + 1
+                    };
+                    if end_pos >= self.input.len() {
                         self.cursor_state = CursorState::EndOfFile;
-                        return Some(Ok(Token::RowDelimiter(self.input[self.cursor_pos])));
-                    } else if self.input[self.cursor_pos + 1] == b'[' {
+                    } else if self.input[end_pos] == b'[' {
                         self.cursor_state = CursorState::StartOfField;
-                        let start_pos = self.cursor_pos;
-                        self.cursor_pos += 1;
-                        return Some(Ok(Token::RowDelimiter(self.input[start_pos])));
                     } else {
-                        return Some(Err(Qvs20ErrorReader::RowDelimiterMoreThan1Byte {
-                            pos: self.cursor_pos,
+                        return Some(Err(Qvs20ErrorReader::RowDelimiterNotAllowed {
+                            position: self.position_at(start_pos),
                         }));
                     }
+                    self.cursor_pos = end_pos;
+                    return Some(Ok(Token::RowDelimiter(&self.input[start_pos..end_pos])));
                 }
                 CursorState::EndOfFile => {
                     // stop the iter() with None
@@ -192,13 +385,13 @@ mod test {
         assert_eq!(token, Token::Field(r"1\\2\]3\[4\n5\r6\t".as_bytes()));
         // row_delimiter only one byte
         let token = unwrap!(unwrap!(rdr.next()));
-        assert_eq!(token, Token::RowDelimiter(b'\n'));
+        assert_eq!(token, Token::RowDelimiter(LF));
         // fourth field
         let token = unwrap!(unwrap!(rdr.next()));
         assert_eq!(token, Token::Field(r"four".as_bytes()));
         // row_delimiter only one byte. Must end with row delimiter
         let token = unwrap!(unwrap!(rdr.next()));
-        assert_eq!(token, Token::RowDelimiter(b'\n'));
+        assert_eq!(token, Token::RowDelimiter(LF));
         // None is returned to signal the end for iter()
         let next = rdr.next();
         assert!(next.is_none());
@@ -209,10 +402,9 @@ mod test {
         let mut rdr = ReaderForQvs20::new("this is not a field".as_bytes());
         // first field
         let result = unwrap!(rdr.next());
-        assert_eq!(
-            result.err().unwrap().to_string(),
-            Qvs20ErrorReader::NoFieldStart { pos: 0 }.to_string()
-        );
+        let err = result.unwrap_err();
+        assert_eq!(err.reason(), "The field must start with [");
+        assert_eq!(err.position().pos, 0);
     }
     
     #[test]
@@ -230,7 +422,7 @@ mod test {
         let result = unwrap!(rdr.next());
         assert_eq!(
             result.err().unwrap().to_string(),
-            "Error: Premature end of file. pos: 1");
+            r#"Error: Premature end of file (line 1, column 2, pos: 1, near "[")"#);
     }
 
     #[test]
@@ -238,10 +430,9 @@ mod test {
         let mut rdr = ReaderForQvs20::new("[no last bracket".as_bytes());
         // first field
         let result = unwrap!(rdr.next());
-        assert_eq!(
-            result.err().unwrap().to_string(),
-            Qvs20ErrorReader::NoFieldEnd { pos: 1 }.to_string()
-        );
+        let err = result.unwrap_err();
+        assert_eq!(err.reason(), "Last bracket is missing");
+        assert_eq!(err.position().pos, 1);
     }
     
     #[test]
@@ -252,10 +443,9 @@ mod test {
         assert_eq!(token, Token::Field("one".as_bytes()));
         // second field
         let result = unwrap!(rdr.next());
-        assert_eq!(
-            result.err().unwrap().to_string(),
-            Qvs20ErrorReader::NoFieldEnd { pos: 6 }.to_string()
-        );
+        let err = result.unwrap_err();
+        assert_eq!(err.reason(), "Last bracket is missing");
+        assert_eq!(err.position().pos, 6);
     }
     #[test]
     pub fn test_07_premature_end() {
@@ -263,7 +453,7 @@ mod test {
         let result = unwrap!(rdr.next());
         assert_eq!(
             result.err().unwrap().to_string(),
-            "Error: Premature end of file. pos: 11"
+            r#"Error: Premature end of file (line 1, column 12, pos: 11, near "caped \\]")"#
         );
     }
     
@@ -275,24 +465,131 @@ mod test {
         assert_eq!(token, Token::Field("one".as_bytes()));
         // second field
         let result = unwrap!(rdr.next());
-        assert_eq!(
-            result.err().unwrap().to_string(),
-            Qvs20ErrorReader::NoLastRowDelimiter { pos: 5 }.to_string()
-        );
+        let err = result.unwrap_err();
+        assert_eq!(err.reason(), "Last row delimiter is missing");
+        assert_eq!(err.position().pos, 5);
     }
     
     #[test]
     pub fn test_09_row_delimiter_too_big() {
-        let mut rdr = ReaderForQvs20::new("[one]\n\n".as_bytes());
+        let mut rdr = ReaderForQvs20::new("[one]\n\n[two]\n".as_bytes());
         // first field
         let token = unwrap!(unwrap!(rdr.next()));
         assert_eq!(token, Token::Field("one".as_bytes()));
         // second field
         let result = unwrap!(rdr.next());
+        let err = result.unwrap_err();
+        assert_eq!(err.reason(), "The row delimiter must be 1 byte or CRLF");
+        assert_eq!(err.position().pos, 5);
+        // LF after CRLF is not a bigger row delimiter
+        let mut rdr = ReaderForQvs20::new("[one]\r\n\n".as_bytes());
+        unwrap!(unwrap!(rdr.next()));
+        assert!(unwrap!(rdr.next()).is_err());
+    }
+
+    #[test]
+    pub fn test_10_escape_at_field_boundaries() {
+        // (input, expected fields) every field is followed by \n
+        let matrix: Vec<(&str, Vec<&str>)> = vec![
+            // escaped backslash at the end of the field
+            (r"[abc\\][next]", vec![r"abc\\", "next"]),
+            (r"[\\][next]", vec![r"\\", "next"]),
+            (r"[\\\\][next]", vec![r"\\\\", "next"]),
+            // escaped bracket at the end of the field
+            (r"[abc\]][next]", vec![r"abc\]", "next"]),
+            (r"[\]][next]", vec![r"\]", "next"]),
+            // escaped backslash and then escaped bracket
+            (r"[\\\]][next]", vec![r"\\\]", "next"]),
+            (r"[\\\\\]][next]", vec![r"\\\\\]", "next"]),
+            // escaped brackets at the start of the field
+            (r"[\[abc][next]", vec![r"\[abc", "next"]),
+            (r"[\]abc][next]", vec![r"\]abc", "next"]),
+            // escaped backslash before [ inside of the field
+            (r"[\\[abc][next]", vec![r"\\[abc", "next"]),
+            // other escape sequences at the boundaries
+            (r"[\n][\r][\t]", vec![r"\n", r"\r", r"\t"]),
+            (r"[\nabc\t][\rabc\n]", vec![r"\nabc\t", r"\rabc\n"]),
+            // empty fields
+            (r"[][\\]", vec!["", r"\\"]),
+        ];
+        for (input, fields) in matrix.iter() {
+            let input = format!("{}\n", input);
+            let mut rdr = ReaderForQvs20::new(input.as_bytes());
+            for field in fields.iter() {
+                let token = unwrap!(unwrap!(rdr.next()), "{}", input);
+                assert_eq!(token, Token::Field(field.as_bytes()), "{}", input);
+            }
+            let token = unwrap!(unwrap!(rdr.next()));
+            assert_eq!(token, Token::RowDelimiter(LF), "{}", input);
+            assert!(rdr.next().is_none(), "{}", input);
+        }
+    }
+
+    #[test]
+    pub fn test_11_escape_errors() {
+        // only escaped brackets
+        let mut rdr = ReaderForQvs20::new(r"[abc\][next\]".as_bytes());
+        let result = unwrap!(rdr.next());
+        let err = result.unwrap_err();
+        assert_eq!(err.reason(), "Premature end of file");
+        assert_eq!(err.position().pos, 13);
+        // lonely backslash at the end of the input
+        let mut rdr = ReaderForQvs20::new(r"[abc\".as_bytes());
+        let result = unwrap!(rdr.next());
+        let err = result.unwrap_err();
+        assert_eq!(err.reason(), "Last bracket is missing");
+        assert_eq!(err.position().pos, 1);
+    }
+
+    #[test]
+    pub fn test_12_crlf_and_digit_row_delimiters() {
+        let mut rdr = ReaderForQvs20::new("[one]\r\n[two]1[three]\r\n".as_bytes());
+        let tokens: Vec<Token> = rdr.by_ref().map(|t| unwrap!(t)).collect();
         assert_eq!(
-            result.err().unwrap().to_string(),
-            Qvs20ErrorReader::RowDelimiterMoreThan1Byte { pos: 5 }.to_string()
+            tokens,
+            vec![
+                Token::Field(b"one"),
+                Token::RowDelimiter(CRLF),
+                Token::Field(b"two"),
+                Token::RowDelimiter(b"1"),
+                Token::Field(b"three"),
+                Token::RowDelimiter(CRLF),
+            ]
         );
+        assert_eq!(rdr.pos(), 22);
+        assert_eq!(ReaderForQvs20::row_delimiter_text(CRLF), r#""\r\n""#);
+    }
+
+    #[test]
+    pub fn test_13_position() {
+        let input = "[one]\n[čćš]\n[three\n";
+        let mut rdr = ReaderForQvs20::new(input.as_bytes());
+        let err = rdr.find_map(|t| t.err()).unwrap();
+        // the column counts characters, the excerpt has whole characters
+        assert_eq!(
+            err.position(),
+            &Position {
+                pos: 16,
+                line: 3,
+                column: 2,
+                excerpt: "ćš]\n[three\n".to_string(),
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            r#"Error: Last bracket is missing (line 3, column 2, pos: 16, near "ćš]\n[three\n")"#
+        );
+        // the origin is the position of the first byte in the whole file
+        let origin = Position::new(input.as_bytes(), 15);
+        let mut rdr = ReaderForQvs20::with_origin(b"[three\n", origin);
+        let err = unwrap!(rdr.next()).unwrap_err();
+        let position = err.position();
+        assert_eq!((position.pos, position.line, position.column), (16, 3, 2));
+        // the field of a row that is already read
+        let mut rdr = ReaderForQvs20::new(b"[a]\n[b][cc][d]\n");
+        rdr.by_ref().for_each(drop);
+        let position = rdr.field_position(4, 3);
+        assert_eq!((position.pos, position.line, position.column), (11, 2, 8));
+        assert_eq!(rdr.field_position(4, 1).pos, 4);
     }
-    
 }
@@ -1,20 +1,82 @@
-//! schema_mod
+// qvs20_schema_mod
 
+// The schema is the first 4 rows of a qvs20 file:
+// [table_name] and the row delimiter
+// [String][Integer]    data types
+// [add_prop][add_prop] additional properties
+// [Country][Population] column names
+// The Table owns the Schema. The Schema can be parsed and written on its own,
+// a schema-only file has exactly these 4 rows and no data rows.
+
+use crate::qvs20_properties_mod::ColumnProperties;
+use crate::qvs20_reader_mod::*;
+use crate::qvs20_table_mod::Table;
+
+use std::str::FromStr;
 use strum_macros::{Display, EnumString};
-// AsRefStr, EnumString
-//use unwrap::unwrap;
-//use anyhow::anyhow;
-//use std::borrow::Cow;
+use thiserror::Error;
 
-#[derive(Clone, Debug)]
-pub struct Schema {
-    // first row is table_name and row_delimiter
-    pub table_name: String,
-    // first row is table_name and row_delimiter
-    pub row_delimiter: String,
+#[derive(Error, Debug)]
+pub enum Qvs20ErrorSchema {
+    #[error("Error: {msg}")]
+    Error { msg: String },
+    #[error("Error in first row: table name: {msg} {position}")]
+    ErrorInFirstRowTableName {
+        msg: String,
+        position: Position,
+        #[source]
+        source: Option<Box<dyn std::error::Error + Send + Sync>>,
+    },
+    #[error("Error in second row: data types, field {field}: {msg} {position}")]
+    ErrorInSecondRowDataTypes {
+        /// 1-based
+        field: usize,
+        msg: String,
+        position: Position,
+        #[source]
+        source: Option<Box<dyn std::error::Error + Send + Sync>>,
+    },
+    #[error("Error in third row: additional properties, field {field}: {msg} {position}")]
+    ErrorInThirdRowAdditionalProperties {
+        /// 1-based
+        field: usize,
+        msg: String,
+        position: Position,
+        #[source]
+        source: Option<Box<dyn std::error::Error + Send + Sync>>,
+    },
+    #[error("Error in fourth row: column names, field {field}: {msg} {position}")]
+    ErrorInFourthRowColumnNames {
+        /// 1-based
+        field: usize,
+        msg: String,
+        position: Position,
+        #[source]
+        source: Option<Box<dyn std::error::Error + Send + Sync>>,
+    },
+    #[error("Error in third row: field {field}, column {column}: {msg}.{}", optional_position_text(.position))]
+    ErrorInProperties {
+        /// 1-based
+        field: usize,
+        column: String,
+        msg: String,
+        /// None if the schema is not parsed from qvs20, like in the writer
+        position: Option<Position>,
+    },
+}
 
-    // private field
-    cursor_pos: usize,
+impl Qvs20ErrorSchema {
+    /// the position of the error in the input, None for the errors without position
+    pub fn position(&self) -> Option<&Position> {
+        match self {
+            Qvs20ErrorSchema::Error { .. } => None,
+            Qvs20ErrorSchema::ErrorInFirstRowTableName { position, .. }
+            | Qvs20ErrorSchema::ErrorInSecondRowDataTypes { position, .. }
+            | Qvs20ErrorSchema::ErrorInThirdRowAdditionalProperties { position, .. }
+            | Qvs20ErrorSchema::ErrorInFourthRowColumnNames { position, .. } => Some(position),
+            Qvs20ErrorSchema::ErrorInProperties { position, .. } => position.as_ref(),
+        }
+    }
 }
 
 #[derive(Clone, Debug, Display, EnumString, Eq, PartialEq)]
@@ -30,152 +92,456 @@ pub enum DataType {
     Table,
 }
 
-/*
-impl Schema {
-    /// crate schema from string
-    /// Schema contains 4 rows.
-    pub fn new_from_str(schema_str: &str) -> anyhow::Result<Schema> {
-        // [table_name]row_delimiter
-        // [String][Integer]
-        // [add_prop][add_prop]
-        // [Country][Population]
-
-        if schema_str.is_empty(){
-            return Err(anyhow!("The schema must not be empty."));
-        }
-
-        // Parsing will get vectors of strings for one row.
-        // Parsing is simple because there is only Strings in this data.
-        // This code looks similar to parsing qvs20 data, but it is not the same.
-        // It is much simplified and specialized.
-
-        cursor_pos=0;
-
-        get_next_field(schema_str, cursor_pos)
-
+/// one column of the schema: one field in each of the rows 2, 3 and 4
+#[derive(Clone, Debug, PartialEq)]
+pub struct Column {
+    pub name: String,
+    pub data_type: DataType,
+    /// additional properties from the third row
+    pub properties: ColumnProperties,
+}
 
-// row delimiter can be LF, 1, 2, 3, ...
-        // we find it quickly after the table_name
-        // We could just look at the space between fields.
-        // That is between end and start ][ delimiters.
-        // Only the row delimiter is allowed there.
-        // It means the row finishes after the delimiter.
-        // it can be the end of the file, or a new row starting with [
-        let (data_types, cursor_pos) = Self::get_vec_of_strings(schema_str, 0);
-        // the returned cursor_pos is the new position
-        let (additional_properties, cursor_pos) = Self::get_vec_of_strings(schema_str, cursor_pos);
-        let (column_names, _cursor_pos) = Self::get_vec_of_strings(schema_str, cursor_pos);
+impl Column {
+    /// column without additional properties
+    pub fn new(name: &str, data_type: DataType) -> Column {
+        Column {
+            name: name.to_string(),
+            data_type,
+            properties: ColumnProperties::default(),
+        }
+    }
+}
 
-        // this vec of string need to be transformed to vec od DataType enum
-        let data_types = data_types
-            .iter()
-            .map(|x| unwrap!(DataType::from_str(x)))
-            .collect();
+#[derive(Clone, Debug, PartialEq)]
+pub struct Schema {
+    // first row is table_name and row_delimiter
+    pub table_name: String,
+    /// 1 byte or CRLF
+    pub row_delimiter: Vec<u8>,
+    pub columns: Vec<Column>,
+}
 
-        //return
+impl Default for Schema {
+    fn default() -> Self {
         Schema {
-            data_types,
-            additional_properties,
-            column_names,
+            table_name: String::new(),
+            // LF is the usual row delimiter
+            row_delimiter: LF.to_vec(),
+            columns: vec![],
+        }
+    }
+}
+
+impl Schema {
+    /// parse a schema-only qvs20: the 4 schema rows and no data rows
+    pub fn from_qvs20(input: &[u8]) -> Result<Schema, Qvs20ErrorSchema> {
+        let mut rdr = ReaderForQvs20::new(input);
+        let schema = Self::read_schema(&mut rdr)?;
+        if rdr.next().is_some() {
+            return Err(Qvs20ErrorSchema::Error {
+                msg: "schema-only file has data rows.".to_string(),
+            });
         }
+        //return
+        Ok(schema)
     }
-    /// schema to string
-    pub fn to_string(&self, row_delimiter: &str) -> String {
-        let mut schema_str = String::with_capacity(1000);
-        for data_type in &self.data_types {
-            schema_str.push('[');
-            schema_str.push_str(&data_type.to_string());
-            schema_str.push(']');
+
+    /// parse the 4 schema rows, the reader stays at the start of the data rows
+    pub(crate) fn read_schema(rdr: &mut ReaderForQvs20) -> Result<Schema, Qvs20ErrorSchema> {
+        let mut schema = Schema::default();
+        // first row: table name and row delimiter
+        schema.first_row_table_name(rdr)?;
+        // second row: data types
+        let second_row_pos = rdr.pos();
+        let mut data_types = vec![];
+        while let Some(result) = schema.second_row_data_types(rdr, data_types.len() + 1) {
+            // if Err then propagate
+            data_types.push(result?);
+        }
+        // must have at least one column
+        if data_types.is_empty() {
+            return Err(Qvs20ErrorSchema::ErrorInSecondRowDataTypes {
+                field: 1,
+                msg: "data types is empty".to_string(),
+                position: rdr.position_at(second_row_pos),
+                source: None,
+            });
         }
-        schema_str.push_str(row_delimiter);
-        for property in &self.additional_properties {
-            schema_str.push('[');
-            schema_str.push_str(&property.to_string());
-            schema_str.push(']');
+        // third row - additional properties
+        // third rows must have same number of columns as data_types
+        let third_row_pos = rdr.pos();
+        let mut additional_properties = vec![];
+        while let Some(result) = schema.third_row_additional_properties(
+            rdr,
+            additional_properties.len() + 1,
+            data_types.len(),
+        ) {
+            // if Err then propagate
+            additional_properties.push(result?);
         }
-        schema_str.push_str(row_delimiter);
-        for column_name in &self.column_names {
-            schema_str.push('[');
-            schema_str.push_str(&column_name);
-            schema_str.push(']');
+        // fourth row - column names, unique and the same number of columns as data_types
+        let mut column_names: Vec<String> = vec![];
+        while let Some(result) =
+            schema.fourth_row_column_names(rdr, &column_names, data_types.len())
+        {
+            // if Err then propagate
+            column_names.push(result?);
         }
-        schema_str.push_str(row_delimiter);
+        for (i, ((data_type, properties), name)) in data_types
+            .into_iter()
+            .zip(additional_properties)
+            .zip(column_names)
+            .enumerate()
+        {
+            let properties = properties.parse::<ColumnProperties>().map_err(|msg| {
+                Qvs20ErrorSchema::ErrorInProperties {
+                    field: i + 1,
+                    column: name.clone(),
+                    msg,
+                    position: Some(rdr.field_position(third_row_pos, i + 1)),
+                }
+            })?;
+            schema.columns.push(Column {
+                name,
+                data_type,
+                properties,
+            });
+        }
+        schema.check_properties().map_err(|e| match e {
+            Qvs20ErrorSchema::ErrorInProperties {
+                field,
+                column,
+                msg,
+                position: None,
+            } => Qvs20ErrorSchema::ErrorInProperties {
+                field,
+                column,
+                msg,
+                position: Some(rdr.field_position(third_row_pos, field)),
+            },
+            e => e,
+        })?;
         //return
-        schema_str
+        Ok(schema)
     }
 
-    pub fn get_next_field(schema_str: &str, cursor_pos: usize)-> anyhow::Result<(Cow<String>, usize)> {
-        if unwrap!(schema_str.bytes().nth(cursor_pos)) != b'['{
-            return Err(anyhow!("The field at pos {} must start with [.", cursor_pos));
-        }
+    /// first row: table name and row_delimiter are mandatory
+    fn first_row_table_name(&mut self, rdr: &mut ReaderForQvs20) -> Result<(), Qvs20ErrorSchema> {
+        let error = |msg: &str, position: Position, source| {
+            Err(Qvs20ErrorSchema::ErrorInFirstRowTableName {
+                msg: msg.to_string(),
+                position,
+                source,
+            })
+        };
+        let pos = rdr.pos();
+        let table_name = match rdr.next() {
+            Some(Ok(Token::Field(f))) => f,
+            Some(Ok(Token::RowDelimiter(_))) => {
+                return error(
+                    "expected Field found Row delimiter",
+                    rdr.position_at(pos),
+                    None,
+                )
+            }
+            Some(Err(e)) => return error(e.reason(), e.position().clone(), Some(e.into())),
+            None => return error("first row is empty", rdr.position_at(pos), None),
+        };
+        self.table_name = match Table::unescape(table_name) {
+            Ok(table_name) => table_name,
+            Err(e) => return error(&e.to_string(), rdr.position_at(pos), Some(e.into())),
+        };
+        // row delimiter is mandatory
+        let pos = rdr.pos();
+        self.row_delimiter = match rdr.next() {
+            Some(Ok(Token::RowDelimiter(r))) => r.to_vec(),
+            Some(Ok(Token::Field(_))) => {
+                return error(
+                    "expected Row delimiter found Field",
+                    rdr.position_at(pos),
+                    None,
+                )
+            }
+            Some(Err(e)) => return error(e.reason(), e.position().clone(), Some(e.into())),
+            None => return error("missing row delimiter", rdr.position_at(pos), None),
+        };
 
+        Ok(())
     }
 
-    // get vec of string for data_types, additional_properties and column_names
-    // returns the cursor_pos of the new position
-    pub fn get_vec_of_strings(schema_str: &str, cursor_pos: usize) -> anyhow::Result<(Vec<String>, usize)> {
-        // string slices are positioned by bytes(), not by char()
-        // Working with Vec<Byte> it faster than working with String.
-        // because of the utf-8 crazy rules. We just ignore them.
-        // it is safe because at the end we transform it in Strings.
-        // that is the moment when Rust checks that the utf8 is correct.
-        // And it should be. We disturb only the delimiters and leave the rest alone.
-        // If is a string then we want to unescape the special characters.
+    /// second row: data types
+    /// field is 1-based
+    /// Option::None means end of the row
+    fn second_row_data_types(
+        &self,
+        rdr: &mut ReaderForQvs20,
+        field: usize,
+    ) -> Option<Result<DataType, Qvs20ErrorSchema>> {
+        let error = |msg: String, position: Position, source| {
+            Some(Err(Qvs20ErrorSchema::ErrorInSecondRowDataTypes {
+                field,
+                msg,
+                position,
+                source,
+            }))
+        };
+        let pos = rdr.pos();
+        let token = match rdr.next() {
+            Some(Ok(token)) => token,
+            Some(Err(e)) => {
+                return error(e.reason().to_string(), e.position().clone(), Some(e.into()))
+            }
+            None => return error("missing row".to_string(), rdr.position_at(pos), None),
+        };
+        // could be field or row_delimiter
+        let data_type = match token {
+            Token::Field(f) => f,
+            Token::RowDelimiter(r) => {
+                // row delimiter must be the same
+                if r != &self.row_delimiter[..] {
+                    return error(self.wrong_row_delimiter(r), rdr.position_at(pos), None);
+                }
+                //end of row
+                return None;
+            }
+        };
+        let data_type = match String::from_utf8(data_type.to_vec()) {
+            Ok(p) => p,
+            Err(e) => return error(e.to_string(), rdr.position_at(pos), Some(e.into())),
+        };
+        let data_type = match DataType::from_str(&data_type) {
+            Ok(p) => p,
+            Err(e) => {
+                return error(
+                    format!("{} {:?}", e, data_type),
+                    rdr.position_at(pos),
+                    Some(e.into()),
+                )
+            }
+        };
+        // return
+        Some(Ok(data_type))
+    }
 
-        if unwrap!(schema_str.bytes().nth(cursor_pos)) != b'['{
-            return Err(anyhow!("The schema row at pos {} must start with [.", cursor_pos));
+    /// third row additional properties
+    /// field is 1-based, column_count is the number of data types
+    /// Option::None means end of the row
+    fn third_row_additional_properties(
+        &self,
+        rdr: &mut ReaderForQvs20,
+        field: usize,
+        column_count: usize,
+    ) -> Option<Result<String, Qvs20ErrorSchema>> {
+        let error = |msg: String, position: Position, source| {
+            Some(Err(Qvs20ErrorSchema::ErrorInThirdRowAdditionalProperties {
+                field,
+                msg,
+                position,
+                source,
+            }))
+        };
+        let pos = rdr.pos();
+        let token = match rdr.next() {
+            Some(Ok(token)) => token,
+            Some(Err(e)) => {
+                return error(e.reason().to_string(), e.position().clone(), Some(e.into()))
+            }
+            None => return error("missing row".to_string(), rdr.position_at(pos), None),
+        };
+        match token {
+            Token::RowDelimiter(r) => {
+                if let Some(msg) = self.row_end_error(r, field - 1, column_count) {
+                    return error(msg, rdr.position_at(pos), None);
+                }
+                //end of row
+                None
+            }
+            Token::Field(f) => {
+                if field > column_count {
+                    return error(
+                        format!("more than {} fields", column_count),
+                        rdr.position_at(pos),
+                        None,
+                    );
+                }
+                // return
+                match Table::unescape(f) {
+                    Ok(text) => Some(Ok(text)),
+                    Err(e) => error(e.to_string(), rdr.position_at(pos), Some(e.into())),
+                }
+            }
         }
-        let mut vec_of_strings: Vec<String> = vec![];
-        let mut found_row_end_delimiter = false;
-        let mut between_end_and_start = false;
-        // shadowing !
-        let mut cursor_pos: usize = cursor_pos;
-        let mut is_escaped = false;
-        let mut utf8_text = vec![];
-
-        for xu8 in schema_str[cursor_pos..].as_bytes() {
-            if is_escaped == true {
-                //escaped sequence is always exact 2 bytes
-                utf8_text.push(*xu8);
-                is_escaped = false;
-            } else {
-                if xu8 == &b'\\' {
-                    //always start of the escaped sequence. There is always exactly 2 bytes.
-                    is_escaped = true;
-                } else if xu8 == &b'[' {
-                    if found_row_end_delimiter == true {
-                        // end of the row delimiter and end of the row
-                        // in case of the last row, the end of file comes first
-                        // return the cursor position -> for the next row
-                        break;
-                    } else {
-                        // start of new utf8_text
-                        between_end_and_start = false;
-                    }
-                } else if xu8 == &b']' {
-                    // end of utf8_text
-                    let text = unwrap!(std::str::from_utf8(&utf8_text));
-                    vec_of_strings.push(text.to_string());
-                    utf8_text = vec![];
-                    between_end_and_start = true;
-                } else if between_end_and_start == true {
-                    // in this space between ] and [ is allowed only the row_delimiter
-                    // it can be various bytes long LF, 1, 2, 3,...
-                    // here we don't need to do anything with it. Simplified.
-                    found_row_end_delimiter = true;
-                } else {
-                    // fill the result vector with bytes, one by one
-                    // the sequence to be escaped are already solved and don't come to this point
-                    utf8_text.push(*xu8);
+    }
+
+    /// fourth row column names
+    /// column_names are the names before this field, column_count is the number of data types
+    /// Option::None means end of the row
+    fn fourth_row_column_names(
+        &self,
+        rdr: &mut ReaderForQvs20,
+        column_names: &[String],
+        column_count: usize,
+    ) -> Option<Result<String, Qvs20ErrorSchema>> {
+        let field = column_names.len() + 1;
+        let error = |msg: String, position: Position, source| {
+            Some(Err(Qvs20ErrorSchema::ErrorInFourthRowColumnNames {
+                field,
+                msg,
+                position,
+                source,
+            }))
+        };
+        let pos = rdr.pos();
+        let token = match rdr.next() {
+            Some(Ok(token)) => token,
+            Some(Err(e)) => {
+                return error(e.reason().to_string(), e.position().clone(), Some(e.into()))
+            }
+            None => return error("missing row".to_string(), rdr.position_at(pos), None),
+        };
+        match token {
+            Token::RowDelimiter(r) => {
+                if let Some(msg) = self.row_end_error(r, column_names.len(), column_count) {
+                    return error(msg, rdr.position_at(pos), None);
+                }
+                //end of row
+                None
+            }
+            Token::Field(f) => {
+                if field > column_count {
+                    return error(
+                        format!("more than {} fields", column_count),
+                        rdr.position_at(pos),
+                        None,
+                    );
+                }
+                let column_name = match Table::unescape(f) {
+                    Ok(column_name) => column_name,
+                    Err(e) => return error(e.to_string(), rdr.position_at(pos), Some(e.into())),
+                };
+                // names of columns must be unique
+                if column_names.contains(&column_name) {
+                    return error(
+                        format!("duplicate column name {:?}", column_name),
+                        rdr.position_at(pos),
+                        None,
+                    );
                 }
+                // return
+                Some(Ok(column_name))
             }
-            cursor_pos += 1;
         }
-        if found_row_end_delimiter == false{
-            return Err(anyhow!("The row does not end with row_delimiter pos {}.", cursor_pos));
+    }
+
+    /// the row delimiter must be the same as in the first row
+    /// and the row must have a field for every data type
+    fn row_end_error(
+        &self,
+        row_delimiter: &[u8],
+        field_count: usize,
+        column_count: usize,
+    ) -> Option<String> {
+        if row_delimiter != &self.row_delimiter[..] {
+            Some(self.wrong_row_delimiter(row_delimiter))
+        } else if field_count != column_count {
+            Some(format!(
+                "{} fields instead of {}",
+                field_count, column_count
+            ))
+        } else {
+            None
         }
-        //return
-        Ok((vec_of_strings, cursor_pos))
+    }
+
+    fn wrong_row_delimiter(&self, row_delimiter: &[u8]) -> String {
+        format!(
+            "wrong row delimiter {} instead of {}",
+            ReaderForQvs20::row_delimiter_text(row_delimiter),
+            ReaderForQvs20::row_delimiter_text(&self.row_delimiter)
+        )
+    }
+
+    /// the position of the column by name
+    pub fn column_position(&self, column_name: &str) -> Option<usize> {
+        self.columns.iter().position(|c| c.name == column_name)
+    }
+
+    /// the names of all columns in order
+    pub fn column_names(&self) -> Vec<String> {
+        self.columns.iter().map(|c| c.name.clone()).collect()
+    }
+
+    /// the data types of all columns in order
+    pub fn data_types(&self) -> Vec<DataType> {
+        self.columns.iter().map(|c| c.data_type.clone()).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use unwrap::unwrap;
+    #[test]
+    pub fn test_01_schema_only() {
+        let s = "[crates]\n[String][Integer]\n[][unique]\n[name][id]\n";
+        let schema = unwrap!(Schema::from_qvs20(s.as_bytes()));
+        assert_eq!(schema.table_name, "crates");
+        assert_eq!(
+            schema.columns,
+            vec![
+                Column::new("name", DataType::String),
+                Column {
+                    name: "id".to_string(),
+                    data_type: DataType::Integer,
+                    properties: unwrap!("unique".parse()),
+                },
+            ]
+        );
+        assert_eq!(schema.column_position("id"), Some(1));
+        assert_eq!(schema.column_position("no_column"), None);
+        assert_eq!(unwrap!(schema.to_qvs20_bytes()), s.as_bytes());
+        // the schema-only file must not have data rows
+        let s = format!("{}[serde][463]\n", s);
+        let err_msg = Schema::from_qvs20(s.as_bytes()).unwrap_err();
+        assert_eq!(
+            err_msg.to_string(),
+            "Error: schema-only file has data rows."
+        );
+    }
+    #[test]
+    pub fn test_02_error_position() {
+        // the reader error is the source, the field and the position are in the schema error
+        let s = "[t]\n[String][Integer]\n[][prop\n";
+        let err = Schema::from_qvs20(s.as_bytes()).unwrap_err();
+        match &err {
+            Qvs20ErrorSchema::ErrorInThirdRowAdditionalProperties {
+                field,
+                position,
+                source: Some(source),
+                ..
+            } => {
+                assert!(matches!(
+                    source.downcast_ref::<Qvs20ErrorReader>(),
+                    Some(Qvs20ErrorReader::NoFieldEnd { .. })
+                ));
+                assert_eq!(*field, 2);
+                assert_eq!((position.pos, position.line, position.column), (25, 3, 4));
+            }
+            e => panic!(
+                "expected ErrorInThirdRowAdditionalProperties, found {:?}",
+                e
+            ),
+        }
+        let source = unwrap!(std::error::Error::source(&err));
+        assert!(source
+            .to_string()
+            .starts_with("Error: Last bracket is missing (line 3"));
+        // column names must be unique
+        let s = "[t]\n[String][Integer]\n[][]\n[id][id]\n";
+        let err = Schema::from_qvs20(s.as_bytes()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            r#"Error in fourth row: column names, field 2: duplicate column name "id" (line 4, column 5, pos: 31, near "][]\n[id][id]\n")"#
+        );
     }
 }
-*/
@@ -0,0 +1,1052 @@
+// qvs20_serde_mod
+
+// Serde data format for qvs20.
+// A Rust struct is one row. The struct fields are the columns.
+// Serialize: the schema (column names and data types) is derived from the struct fields.
+// Without rows schema_of() traces the Deserialize of the struct to get the same schema.
+// Deserialize: the struct fields are found by column name, the order is not important.
+// Only flat structs with simple fields are supported, no nested structs, seq or maps.
+// Option fields are nullable columns, None is Null.
+
+use crate::qvs20_properties_mod::Property;
+use crate::qvs20_schema_mod::*;
+use crate::qvs20_table_mod::*;
+use crate::qvs20_writer_mod::Writer;
+
+use serde::de::{self, DeserializeOwned, IntoDeserializer, MapAccess, Visitor};
+use serde::ser::{self, Impossible, Serialize};
+use std::convert::TryFrom;
+use std::fmt;
+use std::io::Write;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Qvs20ErrorSerde {
+    #[error("Error: {msg}")]
+    Message { msg: String },
+    #[error("Error: {what} is not supported in qvs20 serde.")]
+    Unsupported { what: String },
+    #[error("Error in row {row_number}: {msg}")]
+    ErrorInRow { row_number: usize, msg: String },
+    #[error("Error: {source}")]
+    ErrorIo { source: std::io::Error },
+}
+
+impl ser::Error for Qvs20ErrorSerde {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Qvs20ErrorSerde::Message {
+            msg: msg.to_string(),
+        }
+    }
+}
+
+impl de::Error for Qvs20ErrorSerde {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        Qvs20ErrorSerde::Message {
+            msg: msg.to_string(),
+        }
+    }
+}
+
+/// shortcut for unsupported types
+fn unsupported<T>(what: &str) -> Result<T, Qvs20ErrorSerde> {
+    Err(Qvs20ErrorSerde::Unsupported {
+        what: what.to_string(),
+    })
+}
+
+// region: serialize
+
+/// the schema is derived from the first struct, all the structs become rows
+/// None is Null, the data type of the column is from the first value that is not None
+/// and the columns with None get the property nullable
+/// without rows there is no struct to derive the schema from, use schema_of()
+pub fn to_table<T: Serialize>(table_name: &str, items: &[T]) -> Result<Table, Qvs20ErrorSerde> {
+    let mut table = Table {
+        schema: Schema {
+            table_name: table_name.to_string(),
+            ..Default::default()
+        },
+        rows: vec![],
+    };
+    let mut column_names = vec![];
+    // None until the first value that is not Null
+    let mut data_types: Vec<Option<DataType>> = vec![];
+    let mut nullable = vec![];
+    for (i, item) in items.iter().enumerate() {
+        let mut row_serializer = RowSerializer {
+            column_names: vec![],
+            values: vec![],
+        };
+        item.serialize(&mut row_serializer)
+            .map_err(|e| Qvs20ErrorSerde::ErrorInRow {
+                row_number: i + 1,
+                msg: e.to_string(),
+            })?;
+        let different = || Qvs20ErrorSerde::ErrorInRow {
+            row_number: i + 1,
+            msg: "the columns are different than in the first row".to_string(),
+        };
+        if i == 0 {
+            // schema from the first row
+            column_names = row_serializer.column_names;
+            data_types = vec![None; column_names.len()];
+            nullable = vec![false; column_names.len()];
+        } else if row_serializer.column_names != column_names {
+            return Err(different());
+        }
+        for (j, value) in row_serializer.values.iter().enumerate() {
+            match (data_type_of(value), &data_types[j]) {
+                (None, _) => nullable[j] = true,
+                (Some(data_type), None) => data_types[j] = Some(data_type),
+                (Some(data_type), Some(first)) if data_type != *first => return Err(different()),
+                _ => (),
+            }
+        }
+        table.rows.push(Row {
+            values: row_serializer.values,
+        });
+    }
+    if column_names.is_empty() {
+        return Err(Qvs20ErrorSerde::Message {
+            msg: "the schema cannot be derived without rows, use schema_of().".to_string(),
+        });
+    }
+    table.schema.columns = column_names
+        .iter()
+        .zip(data_types)
+        .zip(nullable)
+        .map(|((name, data_type), nullable)| {
+            // a column with only Null is String
+            let mut column = Column::new(name, data_type.unwrap_or(DataType::String));
+            if nullable {
+                column.properties.list.push(Property::Nullable);
+            }
+            column
+        })
+        .collect();
+    //return
+    Ok(table)
+}
+
+/// serialize the structs to qvs20 in any std::io::Write
+pub fn to_writer<W: Write, T: Serialize>(
+    wtr: W,
+    table_name: &str,
+    items: &[T],
+) -> Result<(), Qvs20ErrorSerde> {
+    let table = to_table(table_name, items)?;
+    let mut wtr = Writer::new(wtr);
+    wtr.write_table(&table)
+        .map_err(|e| Qvs20ErrorSerde::ErrorIo { source: e })
+}
+
+/// the data type of the column from the serialized value, None for Null
+fn data_type_of(value: &Value) -> Option<DataType> {
+    let data_type = match value {
+        Value::String(_) | Value::Bytes(_) => DataType::String,
+        Value::Integer(_) => DataType::Integer,
+        Value::Decimal(_) => DataType::Decimal,
+        Value::Float(_) => DataType::Float,
+        Value::Bool(_) => DataType::Bool,
+        Value::Date(_) => DataType::Date,
+        Value::Time(_) => DataType::Time,
+        Value::DateTime(_) => DataType::DateTime,
+        Value::Table(_) => DataType::Table,
+        Value::Null => return None,
+    };
+    //return
+    Some(data_type)
+}
+
+/// serializes one struct into column names and values
+struct RowSerializer {
+    column_names: Vec<String>,
+    values: Vec<Value>,
+}
+
+impl ser::Serializer for &mut RowSerializer {
+    type Ok = ();
+    type Error = Qvs20ErrorSerde;
+    type SerializeSeq = Impossible<(), Qvs20ErrorSerde>;
+    type SerializeTuple = Impossible<(), Qvs20ErrorSerde>;
+    type SerializeTupleStruct = Impossible<(), Qvs20ErrorSerde>;
+    type SerializeTupleVariant = Impossible<(), Qvs20ErrorSerde>;
+    type SerializeMap = Impossible<(), Qvs20ErrorSerde>;
+    type SerializeStruct = Self;
+    type SerializeStructVariant = Impossible<(), Qvs20ErrorSerde>;
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStruct, Self::Error> {
+        Ok(self)
+    }
+    fn serialize_bool(self, _v: bool) -> Result<(), Self::Error> {
+        unsupported("row as bool")
+    }
+    fn serialize_i8(self, _v: i8) -> Result<(), Self::Error> {
+        unsupported("row as i8")
+    }
+    fn serialize_i16(self, _v: i16) -> Result<(), Self::Error> {
+        unsupported("row as i16")
+    }
+    fn serialize_i32(self, _v: i32) -> Result<(), Self::Error> {
+        unsupported("row as i32")
+    }
+    fn serialize_i64(self, _v: i64) -> Result<(), Self::Error> {
+        unsupported("row as i64")
+    }
+    fn serialize_u8(self, _v: u8) -> Result<(), Self::Error> {
+        unsupported("row as u8")
+    }
+    fn serialize_u16(self, _v: u16) -> Result<(), Self::Error> {
+        unsupported("row as u16")
+    }
+    fn serialize_u32(self, _v: u32) -> Result<(), Self::Error> {
+        unsupported("row as u32")
+    }
+    fn serialize_u64(self, _v: u64) -> Result<(), Self::Error> {
+        unsupported("row as u64")
+    }
+    fn serialize_f32(self, _v: f32) -> Result<(), Self::Error> {
+        unsupported("row as f32")
+    }
+    fn serialize_f64(self, _v: f64) -> Result<(), Self::Error> {
+        unsupported("row as f64")
+    }
+    fn serialize_char(self, _v: char) -> Result<(), Self::Error> {
+        unsupported("row as char")
+    }
+    fn serialize_str(self, _v: &str) -> Result<(), Self::Error> {
+        unsupported("row as str")
+    }
+    fn serialize_bytes(self, _v: &[u8]) -> Result<(), Self::Error> {
+        unsupported("row as bytes")
+    }
+    fn serialize_none(self) -> Result<(), Self::Error> {
+        unsupported("row as None")
+    }
+    fn serialize_some<T: ?Sized + Serialize>(self, value: &T) -> Result<(), Self::Error> {
+        value.serialize(self)
+    }
+    fn serialize_unit(self) -> Result<(), Self::Error> {
+        unsupported("row as unit")
+    }
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<(), Self::Error> {
+        unsupported("row as unit struct")
+    }
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+    ) -> Result<(), Self::Error> {
+        unsupported("row as enum")
+    }
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<(), Self::Error> {
+        value.serialize(self)
+    }
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<(), Self::Error> {
+        unsupported("row as enum")
+    }
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, Self::Error> {
+        unsupported("row as seq")
+    }
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, Self::Error> {
+        unsupported("row as tuple")
+    }
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct, Self::Error> {
+        unsupported("row as tuple struct")
+    }
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, Self::Error> {
+        unsupported("row as enum")
+    }
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, Self::Error> {
+        unsupported("row as map")
+    }
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, Self::Error> {
+        unsupported("row as enum")
+    }
+}
+
+impl ser::SerializeStruct for &mut RowSerializer {
+    type Ok = ();
+    type Error = Qvs20ErrorSerde;
+
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), Self::Error> {
+        let value = value
+            .serialize(ValueSerializer)
+            .map_err(|e| Qvs20ErrorSerde::Message {
+                msg: format!("field {}: {}", key, e),
+            })?;
+        self.column_names.push(key.to_string());
+        self.values.push(value);
+        Ok(())
+    }
+    fn end(self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// serializes one struct field into a Value
+struct ValueSerializer;
+
+impl ser::Serializer for ValueSerializer {
+    type Ok = Value;
+    type Error = Qvs20ErrorSerde;
+    type SerializeSeq = Impossible<Value, Qvs20ErrorSerde>;
+    type SerializeTuple = Impossible<Value, Qvs20ErrorSerde>;
+    type SerializeTupleStruct = Impossible<Value, Qvs20ErrorSerde>;
+    type SerializeTupleVariant = Impossible<Value, Qvs20ErrorSerde>;
+    type SerializeMap = Impossible<Value, Qvs20ErrorSerde>;
+    type SerializeStruct = Impossible<Value, Qvs20ErrorSerde>;
+    type SerializeStructVariant = Impossible<Value, Qvs20ErrorSerde>;
+
+    fn serialize_bool(self, v: bool) -> Result<Value, Self::Error> {
+        Ok(Value::Bool(v))
+    }
+    fn serialize_i8(self, v: i8) -> Result<Value, Self::Error> {
+        Ok(Value::Integer(i64::from(v)))
+    }
+    fn serialize_i16(self, v: i16) -> Result<Value, Self::Error> {
+        Ok(Value::Integer(i64::from(v)))
+    }
+    fn serialize_i32(self, v: i32) -> Result<Value, Self::Error> {
+        Ok(Value::Integer(i64::from(v)))
+    }
+    fn serialize_i64(self, v: i64) -> Result<Value, Self::Error> {
+        Ok(Value::Integer(v))
+    }
+    fn serialize_u8(self, v: u8) -> Result<Value, Self::Error> {
+        Ok(Value::Integer(i64::from(v)))
+    }
+    fn serialize_u16(self, v: u16) -> Result<Value, Self::Error> {
+        Ok(Value::Integer(i64::from(v)))
+    }
+    fn serialize_u32(self, v: u32) -> Result<Value, Self::Error> {
+        Ok(Value::Integer(i64::from(v)))
+    }
+    fn serialize_u64(self, v: u64) -> Result<Value, Self::Error> {
+        match i64::try_from(v) {
+            Ok(p) => Ok(Value::Integer(p)),
+            Err(_) => unsupported("u64 bigger than i64::MAX"),
+        }
+    }
+    fn serialize_f32(self, v: f32) -> Result<Value, Self::Error> {
+        Ok(Value::Float(f64::from(v)))
+    }
+    fn serialize_f64(self, v: f64) -> Result<Value, Self::Error> {
+        Ok(Value::Float(v))
+    }
+    fn serialize_char(self, v: char) -> Result<Value, Self::Error> {
+        Ok(Value::String(v.to_string()))
+    }
+    fn serialize_str(self, v: &str) -> Result<Value, Self::Error> {
+        Ok(Value::String(v.to_string()))
+    }
+    fn serialize_bytes(self, v: &[u8]) -> Result<Value, Self::Error> {
+        Ok(Value::Bytes(v.to_vec()))
+    }
+    fn serialize_none(self) -> Result<Value, Self::Error> {
+        Ok(Value::Null)
+    }
+    fn serialize_some<T: ?Sized + Serialize>(self, value: &T) -> Result<Value, Self::Error> {
+        value.serialize(self)
+    }
+    fn serialize_unit(self) -> Result<Value, Self::Error> {
+        unsupported("unit")
+    }
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Value, Self::Error> {
+        unsupported("unit struct")
+    }
+    /// enum without data is the name of the variant
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<Value, Self::Error> {
+        Ok(Value::String(variant.to_string()))
+    }
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<Value, Self::Error> {
+        value.serialize(self)
+    }
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<Value, Self::Error> {
+        unsupported("enum with data")
+    }
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, Self::Error> {
+        unsupported("seq")
+    }
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, Self::Error> {
+        unsupported("tuple")
+    }
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct, Self::Error> {
+        unsupported("tuple struct")
+    }
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, Self::Error> {
+        unsupported("enum with data")
+    }
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, Self::Error> {
+        unsupported("map")
+    }
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStruct, Self::Error> {
+        unsupported("nested struct")
+    }
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, Self::Error> {
+        unsupported("enum with data")
+    }
+}
+
+// endregion: serialize
+
+// region: deserialize
+
+/// deserialize all the rows into structs, the fields are found by column name
+pub fn from_table<T: DeserializeOwned>(table: &Table) -> Result<Vec<T>, Qvs20ErrorSerde> {
+    let mut items = Vec::with_capacity(table.rows.len());
+    let column_names = table.schema.column_names();
+    for (i, row) in table.rows.iter().enumerate() {
+        let item = from_row(&column_names, row).map_err(|e| Qvs20ErrorSerde::ErrorInRow {
+            row_number: i + 1,
+            msg: e.to_string(),
+        })?;
+        items.push(item);
+    }
+    //return
+    Ok(items)
+}
+
+/// deserialize one row into a struct, the fields are found by column name
+pub fn from_row<T: DeserializeOwned>(
+    column_names: &[String],
+    row: &Row,
+) -> Result<T, Qvs20ErrorSerde> {
+    T::deserialize(RowDeserializer { column_names, row })
+}
+
+/// the row is a map: column name -> value
+struct RowDeserializer<'a> {
+    column_names: &'a [String],
+    row: &'a Row,
+}
+
+impl<'de, 'a> de::Deserializer<'de> for RowDeserializer<'a> {
+    type Error = Qvs20ErrorSerde;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_map(RowMapAccess {
+            column_names: self.column_names,
+            row: self.row,
+            pos: 0,
+        })
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map struct enum identifier ignored_any
+    }
+}
+
+/// iterates the columns of the row
+struct RowMapAccess<'a> {
+    column_names: &'a [String],
+    row: &'a Row,
+    pos: usize,
+}
+
+impl<'de, 'a> MapAccess<'de> for RowMapAccess<'a> {
+    type Error = Qvs20ErrorSerde;
+
+    fn next_key_seed<K: de::DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Self::Error> {
+        match self.column_names.get(self.pos) {
+            Some(column_name) => seed
+                .deserialize(column_name.as_str().into_deserializer())
+                .map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: de::DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, Self::Error> {
+        let value = match self.row.values.get(self.pos) {
+            Some(p) => p,
+            None => {
+                return Err(Qvs20ErrorSerde::Message {
+                    msg: format!("missing value for column {}", self.column_names[self.pos]),
+                })
+            }
+        };
+        let column_name = &self.column_names[self.pos];
+        self.pos += 1;
+        seed.deserialize(ValueDeserializer { value })
+            .map_err(|e| Qvs20ErrorSerde::Message {
+                msg: format!("column {}: {}", column_name, e),
+            })
+    }
+}
+
+/// one Value into a struct field
+struct ValueDeserializer<'a> {
+    value: &'a Value,
+}
+
+impl<'de, 'a> de::Deserializer<'de> for ValueDeserializer<'a> {
+    type Error = Qvs20ErrorSerde;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.value {
+            Value::String(s) => visitor.visit_str(s),
+            Value::Integer(i) => visitor.visit_i64(*i),
+            Value::Float(f) => visitor.visit_f64(*f),
+            Value::Bool(b) => visitor.visit_bool(*b),
+            Value::Bytes(b) => visitor.visit_bytes(b),
+            Value::Null => visitor.visit_none(),
+            // the other types are deserialized from their canonical text
+            _ => visitor.visit_string(self.value.to_string()),
+        }
+    }
+    /// any value can be deserialized as text, except Null
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.value {
+            Value::String(s) => visitor.visit_str(s),
+            Value::Null => visitor.visit_none(),
+            _ => visitor.visit_string(self.value.to_string()),
+        }
+    }
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserialize_str(visitor)
+    }
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.value {
+            Value::Null => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+    /// enum without data from the name of the variant
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_enum(self.value.to_string().into_deserializer())
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char
+        bytes byte_buf unit unit_struct seq tuple
+        tuple_struct map struct identifier ignored_any
+    }
+}
+
+/// the schema from the struct fields, without an instance of the struct
+/// T::deserialize is traced: the field names and the data types it asks for are recorded
+/// Option fields are nullable columns
+pub fn schema_of<T: DeserializeOwned>(table_name: &str) -> Result<Schema, Qvs20ErrorSerde> {
+    let mut columns = vec![];
+    T::deserialize(SchemaDeserializer {
+        columns: &mut columns,
+    })?;
+    if columns.is_empty() {
+        return Err(Qvs20ErrorSerde::Message {
+            msg: "the struct has no fields.".to_string(),
+        });
+    }
+    //return
+    Ok(Schema {
+        table_name: table_name.to_string(),
+        columns,
+        ..Default::default()
+    })
+}
+
+/// records the struct fields as columns
+struct SchemaDeserializer<'a> {
+    columns: &'a mut Vec<Column>,
+}
+
+impl<'de, 'a> de::Deserializer<'de> for SchemaDeserializer<'a> {
+    type Error = Qvs20ErrorSerde;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
+        unsupported("row that is not a struct")
+    }
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_map(SchemaMapAccess {
+            fields,
+            columns: self.columns,
+            pos: 0,
+        })
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map enum identifier ignored_any
+    }
+}
+
+/// iterates the struct fields
+struct SchemaMapAccess<'a> {
+    fields: &'static [&'static str],
+    columns: &'a mut Vec<Column>,
+    pos: usize,
+}
+
+impl<'de, 'a> MapAccess<'de> for SchemaMapAccess<'a> {
+    type Error = Qvs20ErrorSerde;
+
+    fn next_key_seed<K: de::DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Self::Error> {
+        match self.fields.get(self.pos) {
+            Some(field) => seed.deserialize(field.into_deserializer()).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: de::DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, Self::Error> {
+        let field = self.fields[self.pos];
+        self.pos += 1;
+        let mut trace = FieldTrace {
+            data_type: None,
+            nullable: false,
+        };
+        let value = seed
+            .deserialize(&mut trace)
+            .map_err(|e| Qvs20ErrorSerde::Message {
+                msg: format!("field {}: {}", field, e),
+            })?;
+        let mut column = Column::new(field, trace.data_type.unwrap_or(DataType::String));
+        if trace.nullable {
+            column.properties.list.push(Property::Nullable);
+        }
+        self.columns.push(column);
+        Ok(value)
+    }
+}
+
+/// records the data type that the struct field asks for and returns an empty value
+struct FieldTrace {
+    data_type: Option<DataType>,
+    nullable: bool,
+}
+
+impl FieldTrace {
+    fn set(&mut self, data_type: DataType) {
+        self.data_type = Some(data_type);
+    }
+}
+
+impl<'de> de::Deserializer<'de> for &mut FieldTrace {
+    type Error = Qvs20ErrorSerde;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
+        unsupported("field without a known data type")
+    }
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.set(DataType::Bool);
+        visitor.visit_bool(false)
+    }
+    fn deserialize_i8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserialize_i64(visitor)
+    }
+    fn deserialize_i16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserialize_i64(visitor)
+    }
+    fn deserialize_i32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserialize_i64(visitor)
+    }
+    fn deserialize_i64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.set(DataType::Integer);
+        visitor.visit_i64(0)
+    }
+    fn deserialize_u8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserialize_i64(visitor)
+    }
+    fn deserialize_u16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserialize_i64(visitor)
+    }
+    fn deserialize_u32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserialize_i64(visitor)
+    }
+    fn deserialize_u64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserialize_i64(visitor)
+    }
+    fn deserialize_f32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserialize_f64(visitor)
+    }
+    fn deserialize_f64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.set(DataType::Float);
+        visitor.visit_f64(0.0)
+    }
+    fn deserialize_char<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.set(DataType::String);
+        visitor.visit_char(' ')
+    }
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.set(DataType::String);
+        visitor.visit_str("")
+    }
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserialize_str(visitor)
+    }
+    fn deserialize_bytes<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.set(DataType::String);
+        visitor.visit_bytes(&[])
+    }
+    fn deserialize_byte_buf<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.deserialize_bytes(visitor)
+    }
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        self.nullable = true;
+        visitor.visit_some(self)
+    }
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+    /// enum without data is the name of the variant
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.set(DataType::String);
+        match variants.first() {
+            Some(variant) => visitor.visit_enum(variant.into_deserializer()),
+            None => unsupported("enum without variants"),
+        }
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
+        unsupported("unit")
+    }
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        unsupported("unit struct")
+    }
+    fn deserialize_seq<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
+        unsupported("seq")
+    }
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        _visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        unsupported("tuple")
+    }
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        _visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        unsupported("tuple struct")
+    }
+    fn deserialize_map<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
+        unsupported("map")
+    }
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _fields: &'static [&'static str],
+        _visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        unsupported("nested struct")
+    }
+
+    serde::forward_to_deserialize_any! {
+        i128 u128 identifier ignored_any
+    }
+}
+
+// endregion: deserialize
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_derive::{Deserialize, Serialize};
+    use unwrap::unwrap;
+
+    #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+    struct CrateData {
+        name: String,
+        id: i64,
+        downloads: u32,
+        score: f64,
+        is_yanked: bool,
+        kind: Kind,
+    }
+
+    #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+    enum Kind {
+        Normal,
+        Dev,
+    }
+
+    fn sample() -> Vec<CrateData> {
+        vec![
+            CrateData {
+                name: "name_1 [escaped]".to_string(),
+                id: 1601,
+                downloads: 100,
+                score: 0.5,
+                is_yanked: false,
+                kind: Kind::Normal,
+            },
+            CrateData {
+                name: "name_2".to_string(),
+                id: -1,
+                downloads: 0,
+                score: 1e300,
+                is_yanked: true,
+                kind: Kind::Dev,
+            },
+        ]
+    }
+
+    #[test]
+    pub fn test_01_round_trip() {
+        let mut bytes = vec![];
+        unwrap!(to_writer(&mut bytes, "crates", &sample()));
+        assert!(bytes.starts_with(
+            b"[crates]\n[String][Integer][Integer][Float][Bool][String]\n[][][][][][]\n[name][id][downloads][score][is_yanked][kind]\n[name_1 \\[escaped\\]][1601][100][0.5][false][Normal]\n"
+        ));
+        let table = unwrap!(Table::from_qvs20_with_schema(&bytes));
+        let items: Vec<CrateData> = unwrap!(from_table(&table));
+        assert_eq!(items, sample());
+    }
+
+    #[test]
+    pub fn test_02_by_column_name() {
+        // different order and more columns than fields
+        #[derive(Debug, Deserialize, PartialEq)]
+        struct Partial {
+            id: String,
+            name: String,
+        }
+        let table = unwrap!(Table::from_qvs20_with_schema(
+            "[t]\n[String][Integer][String]\n[][][]\n[name][id][extra]\n[one][1][x]\n".as_bytes()
+        ));
+        let items: Vec<Partial> = unwrap!(from_table(&table));
+        assert_eq!(
+            items,
+            vec![Partial {
+                id: "1".to_string(),
+                name: "one".to_string()
+            }]
+        );
+        // missing column
+        #[derive(Debug, Deserialize)]
+        struct Missing {
+            #[allow(dead_code)]
+            other: String,
+        }
+        let err = from_table::<Missing>(&table).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Error in row 1: Error: missing field `other`"
+        );
+    }
+
+    #[test]
+    pub fn test_03_errors() {
+        let empty: Vec<CrateData> = vec![];
+        assert!(to_table("t", &empty).is_err());
+        #[derive(Serialize)]
+        struct Nested {
+            inner: Vec<u8>,
+            list: Vec<String>,
+        }
+        let err = to_table(
+            "t",
+            &[Nested {
+                inner: vec![],
+                list: vec![],
+            }],
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Error in row 1: Error: field inner: Error: seq is not supported in qvs20 serde."
+        );
+    }
+
+    #[test]
+    pub fn test_04_option() {
+        #[derive(Debug, Deserialize, PartialEq, Serialize)]
+        struct Optional {
+            name: String,
+            last_version: Option<String>,
+            downloads: Option<i64>,
+        }
+        let items = vec![
+            Optional {
+                name: "one".to_string(),
+                last_version: None,
+                downloads: Some(1),
+            },
+            Optional {
+                name: "two".to_string(),
+                last_version: Some("0.1.0".to_string()),
+                downloads: Some(2),
+            },
+        ];
+        let mut bytes = vec![];
+        unwrap!(to_writer(&mut bytes, "t", &items));
+        // the data type is from the first value that is not None
+        assert_eq!(
+            unwrap!(String::from_utf8(bytes.clone())),
+            "[t]\n[String][String][Integer]\n[][nullable][]\n[name][last_version][downloads]\n\
+             [one][\\0][1]\n[two][0.1.0][2]\n"
+        );
+        let table = unwrap!(Table::from_qvs20_with_schema(&bytes));
+        let items_2: Vec<Optional> = unwrap!(from_table(&table));
+        assert_eq!(items_2, items);
+        // null cannot be deserialized into a field that is not Option
+        #[derive(Debug, Deserialize)]
+        struct NotOptional {
+            #[allow(dead_code)]
+            last_version: String,
+        }
+        assert!(from_table::<NotOptional>(&table).is_err());
+    }
+
+    #[test]
+    pub fn test_05_schema_of() {
+        // the same schema as from the rows, but without an instance
+        let schema = unwrap!(schema_of::<CrateData>("t"));
+        assert_eq!(schema, unwrap!(to_table("t", &sample())).schema);
+        assert_eq!(
+            schema.column_names(),
+            vec!["name", "id", "downloads", "score", "is_yanked", "kind"]
+        );
+        // the empty table is written with the schema and no rows
+        let table = Table {
+            schema,
+            rows: vec![],
+        };
+        let bytes = unwrap!(table.to_qvs20_bytes());
+        assert_eq!(
+            unwrap!(String::from_utf8(bytes.clone())),
+            "[t]\n[String][Integer][Integer][Float][Bool][String]\n[][][][][][]\n\
+             [name][id][downloads][score][is_yanked][kind]\n"
+        );
+        let items: Vec<CrateData> =
+            unwrap!(from_table(&unwrap!(Table::from_qvs20_with_schema(&bytes))));
+        assert!(items.is_empty());
+        // Option fields are nullable, also when all the values would be None
+        #[derive(Deserialize)]
+        #[allow(dead_code)]
+        struct Optional {
+            name: String,
+            downloads: Option<i64>,
+        }
+        let schema = unwrap!(schema_of::<Optional>("t"));
+        assert_eq!(schema.columns[1].data_type, DataType::Integer);
+        assert_eq!(schema.columns[1].properties.list, vec![Property::Nullable]);
+        assert!(schema.columns[0].properties.list.is_empty());
+        // nested fields are not supported
+        #[derive(Deserialize)]
+        #[allow(dead_code)]
+        struct Nested {
+            list: Vec<String>,
+        }
+        assert_eq!(
+            unwrap!(schema_of::<Nested>("t").map(|_| ()).err()).to_string(),
+            "Error: field list: Error: seq is not supported in qvs20 serde."
+        );
+    }
+}
@@ -0,0 +1,278 @@
+// qvs20_stream_mod
+
+// Streaming for big files that don't fit in memory.
+// The schema is parsed once, then the rows are returned one by one.
+// Only the bytes of one row are in memory at a time.
+// The bytes of one row are split from the BufRead and parsed with ReaderForQvs20,
+// the same token state machine that parses the whole file in memory.
+// The reader of the row knows where the row starts, so the errors have the position in the file.
+
+use crate::qvs20_properties_mod::RowChecker;
+use crate::qvs20_reader_mod::*;
+use crate::qvs20_schema_mod::*;
+use crate::qvs20_table_mod::*;
+
+use std::io::BufRead;
+
+/// the schema has 4 rows: table name, data types, additional properties, column names
+const SCHEMA_ROWS: usize = 4;
+
+/// StreamReaderForQvs20 iterates the rows from any BufRead
+pub struct StreamReaderForQvs20<R: BufRead> {
+    // All the fields are internal and not public.
+    // The only way to interact is through methods.
+    /// the source of bytes
+    rdr: R,
+    /// the 4 schema rows
+    schema: Schema,
+    /// the properties of the columns, unique remembers the values of the previous rows
+    checker: RowChecker,
+    /// bytes of one row, the allocation is reused for every row
+    row_buffer: Vec<u8>,
+    /// where is the splitter now, it survives the refill of the BufRead buffer
+    split_state: SplitState,
+    /// 1-based data row number
+    row_number: usize,
+    /// the position of the first byte of the row in the file
+    row_origin: Position,
+    /// after end of file or error the iterator returns None
+    finished: bool,
+}
+
+/// internal enum
+enum SplitState {
+    /// start of row, expecting [
+    StartOfRow,
+    /// inside of field
+    InsideOfField,
+    /// after \ inside of field, the next byte is escaped
+    AfterEscape,
+    /// after ] expecting [ or the row delimiter
+    AfterField,
+    /// after \r, the row delimiter can be CRLF
+    AfterCarriageReturn,
+}
+
+impl<R: BufRead> StreamReaderForQvs20<R> {
+    /// Constructor. Reads and parses the 4 schema rows.
+    pub fn new(rdr: R) -> Result<Self, Qvs20ErrorTable> {
+        let mut stream = StreamReaderForQvs20 {
+            rdr,
+            schema: Schema::default(),
+            checker: RowChecker::new(&Schema::default()),
+            row_buffer: Vec::with_capacity(1000),
+            split_state: SplitState::StartOfRow,
+            row_number: 1,
+            row_origin: Position::default(),
+            finished: false,
+        };
+        let mut schema_bytes = vec![];
+        for _ in 0..SCHEMA_ROWS {
+            if !stream.read_row_bytes()? {
+                break;
+            }
+            schema_bytes.extend_from_slice(&stream.row_buffer);
+        }
+        stream.schema = Schema::from_qvs20(&schema_bytes)?;
+        stream.row_origin = stream.row_origin.advance(&schema_bytes);
+        stream.checker = RowChecker::new(&stream.schema);
+        // return
+        Ok(stream)
+    }
+
+    /// the 4 schema rows
+    pub fn schema(&self) -> &Schema {
+        &self.schema
+    }
+
+    /// read the bytes of the next row in row_buffer, including the row delimiter
+    /// returns false on end of file
+    fn read_row_bytes(&mut self) -> Result<bool, Qvs20ErrorTable> {
+        self.row_buffer.clear();
+        loop {
+            let available = match self.rdr.fill_buf() {
+                Ok(p) => p,
+                Err(e) => return Err(Qvs20ErrorTable::ErrorIo { source: e }),
+            };
+            if available.is_empty() {
+                // end of file, maybe with an unfinished row
+                return Ok(!self.row_buffer.is_empty());
+            }
+            let mut consumed = available.len();
+            let mut end_of_row = false;
+            for (i, byte) in available.iter().enumerate() {
+                match self.split_state {
+                    SplitState::StartOfRow => {
+                        if byte == &b'[' {
+                            self.split_state = SplitState::InsideOfField;
+                        } else {
+                            // malformed, the parser will return the error
+                            end_of_row = true;
+                        }
+                    }
+                    SplitState::InsideOfField => {
+                        if byte == &b'\\' {
+                            self.split_state = SplitState::AfterEscape;
+                        } else if byte == &b']' {
+                            self.split_state = SplitState::AfterField;
+                        }
+                    }
+                    SplitState::AfterEscape => {
+                        // escape sequence is always exactly 2 bytes
+                        self.split_state = SplitState::InsideOfField;
+                    }
+                    SplitState::AfterField => {
+                        if byte == &b'[' {
+                            self.split_state = SplitState::InsideOfField;
+                        } else if byte == &b'\r' {
+                            self.split_state = SplitState::AfterCarriageReturn;
+                        } else {
+                            // the row delimiter is the last byte of the row
+                            end_of_row = true;
+                        }
+                    }
+                    SplitState::AfterCarriageReturn => {
+                        if byte == &b'\n' {
+                            // CRLF
+                            end_of_row = true;
+                        } else {
+                            // the row delimiter was only \r, this byte is the next row
+                            end_of_row = true;
+                            consumed = i;
+                            break;
+                        }
+                    }
+                }
+                if end_of_row {
+                    consumed = i + 1;
+                    break;
+                }
+            }
+            self.row_buffer.extend_from_slice(&available[..consumed]);
+            self.rdr.consume(consumed);
+            if end_of_row {
+                self.split_state = SplitState::StartOfRow;
+                return Ok(true);
+            }
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for StreamReaderForQvs20<R> {
+    type Item = Result<Row, Qvs20ErrorTable>;
+    /// Reads the next row. Return None when EndOfFile. After an error returns None.
+    fn next(&mut self) -> Option<Result<Row, Qvs20ErrorTable>> {
+        if self.finished {
+            return None;
+        }
+        let result = match self.read_row_bytes() {
+            Ok(true) => {
+                let mut rdr =
+                    ReaderForQvs20::with_origin(&self.row_buffer, self.row_origin.clone());
+                let result = match self.schema.data_row(&mut rdr, self.row_number) {
+                    Some(Ok(row)) => self
+                        .checker
+                        .check_row(self.row_number, &row)
+                        .map(|_| row)
+                        .map_err(|e| e.with_field_position(&rdr, 0)),
+                    Some(Err(e)) => Err(e),
+                    None => Err(Qvs20ErrorTable::ErrorInDataRow {
+                        row_number: self.row_number,
+                        msg: "empty row".to_string(),
+                        position: rdr.position_at(0),
+                        source: None,
+                    }),
+                };
+                self.row_origin = self.row_origin.advance(&self.row_buffer);
+                result
+            }
+            Ok(false) => {
+                // normal end of file
+                self.finished = true;
+                return None;
+            }
+            Err(e) => Err(e),
+        };
+        if result.is_err() {
+            self.finished = true;
+        }
+        self.row_number += 1;
+        Some(result)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::BufReader;
+    use unwrap::unwrap;
+    #[test]
+    pub fn test_01_same_as_in_memory() {
+        let s = r"[table\[name\]]
+[String][Integer][Date]
+[][][]
+[name][id][date]
+[name_1][1][2020-01-01]
+[escaped \\][2][2020-01-02]
+[\]\[\n][3][2020-01-03]
+";
+        let table = unwrap!(Table::from_qvs20_with_schema(s.as_bytes()));
+        // very small buffer, so the refill happens inside of the fields
+        for capacity in 1..8 {
+            let rdr = BufReader::with_capacity(capacity, s.as_bytes());
+            let mut stream = unwrap!(StreamReaderForQvs20::new(rdr));
+            assert_eq!(stream.schema().table_name, "table[name]");
+            assert_eq!(stream.schema(), &table.schema);
+            let rows: Vec<Row> = unwrap!((&mut stream).collect::<Result<_, _>>());
+            assert_eq!(rows.len(), 3);
+            for (row, row2) in rows.iter().zip(table.rows.iter()) {
+                assert_eq!(row.values, row2.values);
+            }
+        }
+    }
+    #[test]
+    pub fn test_02_errors() {
+        // wrong schema
+        let result = StreamReaderForQvs20::new("[t]\n[Strin]\n".as_bytes());
+        assert!(result.is_err());
+        // only schema, no rows
+        let mut stream = unwrap!(StreamReaderForQvs20::new(
+            "[t]\n[String]\n[]\n[name]\n".as_bytes()
+        ));
+        assert!(stream.next().is_none());
+        // error in the second row and then the iterator stops
+        let mut stream = unwrap!(StreamReaderForQvs20::new(
+            "[t]\n[String]\n[]\n[name]\n[one]\n[two][too many]\n[three]\n".as_bytes()
+        ));
+        assert!(unwrap!(stream.next()).is_ok());
+        assert_eq!(
+            unwrap!(stream.next()).unwrap_err().to_string(),
+            r#"Error in data row 2: more than 1 fields (line 6, column 6, pos: 34, near "[two][too many]\n")"#
+        );
+        assert!(stream.next().is_none());
+        // missing last row delimiter
+        let mut stream = unwrap!(StreamReaderForQvs20::new(
+            "[t]\n[String]\n[]\n[name]\n[one]".as_bytes()
+        ));
+        assert_eq!(
+            unwrap!(stream.next()).unwrap_err().to_string(),
+            r#"Error in data row 1: Last row delimiter is missing (line 5, column 6, pos: 28, near "[one]")"#
+        );
+    }
+    #[test]
+    pub fn test_03_crlf_and_cr() {
+        let lf = "[t]\n[String][Integer]\n[][]\n[name][id]\n[name_1][1]\n[\\r\\n][2]\n";
+        let table = unwrap!(Table::from_qvs20_with_schema(lf.as_bytes()));
+        for row_delimiter in ["\r\n", "\r"].iter() {
+            let s = lf.replace('\n', row_delimiter);
+            // the refill can split the CRLF
+            for capacity in 1..8 {
+                let rdr = BufReader::with_capacity(capacity, s.as_bytes());
+                let mut stream = unwrap!(StreamReaderForQvs20::new(rdr));
+                assert_eq!(stream.schema().row_delimiter, row_delimiter.as_bytes());
+                let rows: Vec<Row> = unwrap!((&mut stream).collect::<Result<_, _>>());
+                assert_eq!(rows, table.rows);
+            }
+        }
+    }
+}
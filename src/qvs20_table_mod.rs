@@ -4,61 +4,123 @@
 // A lot of times the table is used just as an intermediary,
 // and don't need a fixed Rust struct in compile time.
 // It means that sometimes a change in the table does not dictate change in source code and compiling.
-
-use crate::*;
-
+// A column of data type Table has a nested table in every field:
+// the full qvs20 text of the sub-table with its own schema, escaped like any other text.
+// The row delimiter of the nested table must be different from the parent,
+// by convention 1 for the first level of nesting, 2 for the second...
+// [crates]
+// [String][Table]
+// [][]
+// [name][versions]
+// [serde][\[versions\]1\[String\]1\[\]1\[num\]1\[1.0.0\]1\[1.0.1\]1]
+
+use crate::qvs20_properties_mod::RowChecker;
 use crate::qvs20_reader_mod::*;
+use crate::qvs20_schema_mod::*;
 
 //use strum;
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
+use rust_decimal::Decimal;
+use std::fmt;
 use std::str::FromStr;
-use strum_macros::EnumString;
 use thiserror::Error;
-use unwrap::unwrap;
-
-#[macro_export]
-macro_rules! unwrap_field_or_error(
-    ($token:expr, $err:expr) => (
-        match $token{
-            crate::qvs20_reader_mod::Token::Field(p) => p,
-            crate::qvs20_reader_mod::Token::RowDelimiter(_r) =>  return $err,
-        };
-    );
-);
-#[macro_export]
-macro_rules! unwrap_row_delimiter_or_error(
-    ($token:expr, $err:expr) => (
-        match $token{
-            crate::qvs20_reader_mod::Token::Field(_p) =>  return $err,
-            crate::qvs20_reader_mod::Token::RowDelimiter(r) => r,
-        };
-    );
-);
 
 #[derive(Error, Debug)]
 pub enum Qvs20ErrorTable {
     // table
     #[error("Error: {msg}")]
     Error { msg: String },
+    /// the value cannot be parsed, the source is the error of the parser
+    /// or the Qvs20ErrorTable of the nested table
     #[error("Error: {msg} {source}")]
     ErrorWithSource {
-        source: Qvs20ErrorReader,
         msg: String,
+        #[source]
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+    #[error(transparent)]
+    ErrorSchema {
+        #[from]
+        source: Qvs20ErrorSchema,
+    },
+    #[error("Error in data row {row_number}: {msg} {position}")]
+    ErrorInDataRow {
+        /// 1-based
+        row_number: usize,
+        msg: String,
+        position: Position,
+        #[source]
+        source: Option<Box<Qvs20ErrorReader>>,
+    },
+    #[error("Error in data row {row_number}, field {field}, column {column}: {source} {position}")]
+    ErrorInDataField {
+        /// 1-based
+        row_number: usize,
+        /// 1-based
+        field: usize,
+        column: String,
+        position: Position,
+        source: Box<Qvs20ErrorTable>,
+    },
+    #[error("Error in data row {row_number}, field {field}, column {column}: {msg}.{}", optional_position_text(.position))]
+    PropertyViolation {
+        /// 1-based
+        row_number: usize,
+        /// 1-based
+        field: usize,
+        column: String,
+        msg: String,
+        /// None if the row is not parsed from qvs20, like in the writer
+        position: Option<Position>,
     },
-    #[error("Error in third row: additional properties.")]
-    ErrorInThirdRowAdditionalProperties,
-    #[error("Error in fourth row: column names.")]
-    ErrorInFourthRowColumnNames,
-    #[error("Error in data row.")]
-    ErrorInDataRow { row_number: usize },
+    #[error("Error: {source}")]
+    ErrorIo { source: std::io::Error },
     //#[error("unknown error")]
     //Unknown,
 }
 
-#[derive(Clone, Debug)]
+impl Qvs20ErrorTable {
+    /// the property violation of a parsed row gets the position of its field
+    /// rdr has read the row that starts at row_pos
+    pub(crate) fn with_field_position(self, rdr: &ReaderForQvs20, row_pos: usize) -> Self {
+        match self {
+            Qvs20ErrorTable::PropertyViolation {
+                row_number,
+                field,
+                column,
+                msg,
+                position: None,
+            } => Qvs20ErrorTable::PropertyViolation {
+                row_number,
+                field,
+                column,
+                msg,
+                position: Some(rdr.field_position(row_pos, field)),
+            },
+            e => e,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub enum Value {
     String(String),
     Integer(i64),
+    /// exact decimal number, no rounding like float
+    Decimal(Decimal),
+    Float(f64),
+    Bool(bool),
+    /// calendar date 2020-12-31
+    Date(NaiveDate),
+    /// time of day 23:59:59.999
+    Time(NaiveTime),
+    /// timestamp 2020-12-31T23:59:59.999
+    DateTime(NaiveDateTime),
+    /// nested table with its own schema, parsed recursively
+    Table(Box<Table>),
     Bytes(Vec<u8>),
+    /// missing value, only in nullable columns
+    Null,
 }
 
 impl Default for Value {
@@ -67,270 +129,275 @@ impl Default for Value {
     }
 }
 
-#[derive(Clone, Debug, Default)]
+/// canonical text for Date
+const DATE_FORMAT: &str = "%Y-%m-%d";
+/// canonical text for Time
+const TIME_FORMAT: &str = "%H:%M:%S%.f";
+/// canonical text for DateTime
+const DATE_TIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.f";
+/// DateTime is accepted also with space instead of T, like in the crates.io dump
+const DATE_TIME_FORMAT_WITH_SPACE: &str = "%Y-%m-%d %H:%M:%S%.f";
+
+/// the reserved raw field for Null: [\0]
+/// the escape \0 is not used for any character, so it cannot be confused with text
+pub const NULL_FIELD: &[u8] = b"\\0";
+
+impl Value {
+    /// parse the raw field (not unescaped) into the Value of the column data type
+    pub fn from_qvs20_field(field: &[u8], data_type: &DataType) -> Result<Value, Qvs20ErrorTable> {
+        let text = Table::unescape(field)?;
+        Self::from_text(text, data_type)
+    }
+    /// parse the unescaped text into the Value of the column data type
+    pub fn from_text(text: String, data_type: &DataType) -> Result<Value, Qvs20ErrorTable> {
+        let value: Result<Value, Box<dyn std::error::Error + Send + Sync>> = match data_type {
+            DataType::String => return Ok(Value::String(text)),
+            DataType::Integer => text.parse::<i64>().map(Value::Integer).map_err(Into::into),
+            DataType::Decimal => Decimal::from_str(&text)
+                .map(Value::Decimal)
+                .map_err(Into::into),
+            DataType::Float => text.parse::<f64>().map(Value::Float).map_err(Into::into),
+            DataType::Bool => match text.as_str() {
+                "true" => Ok(Value::Bool(true)),
+                "false" => Ok(Value::Bool(false)),
+                _ => Err("must be true or false".into()),
+            },
+            DataType::Date => NaiveDate::parse_from_str(&text, DATE_FORMAT)
+                .map(Value::Date)
+                .map_err(Into::into),
+            DataType::Time => NaiveTime::parse_from_str(&text, TIME_FORMAT)
+                .map(Value::Time)
+                .map_err(Into::into),
+            DataType::DateTime => NaiveDateTime::parse_from_str(&text, DATE_TIME_FORMAT)
+                .or_else(|_| NaiveDateTime::parse_from_str(&text, DATE_TIME_FORMAT_WITH_SPACE))
+                .map(Value::DateTime)
+                .map_err(Into::into),
+            // the error of the nested table stays a Qvs20ErrorTable
+            DataType::Table => Table::from_qvs20_with_schema(text.as_bytes())
+                .map(|t| Value::Table(Box::new(t)))
+                .map_err(Into::into),
+        };
+        match value {
+            Ok(p) => Ok(p),
+            Err(source) => Err(Qvs20ErrorTable::ErrorWithSource {
+                msg: format!("{} {:?}", data_type, text),
+                source,
+            }),
+        }
+    }
+    /// the canonical text, None for Null, so Null is not the same as the empty String
+    pub fn to_text(&self) -> Option<String> {
+        match self {
+            Value::Null => None,
+            value => Some(value.to_string()),
+        }
+    }
+}
+
+/// canonical textual form (not escaped) that from_text() parses back to the same Value
+/// Null has no text, the writer encodes it as the reserved field [\0]
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::String(s) => write!(f, "{}", s),
+            Value::Integer(i) => write!(f, "{}", i),
+            Value::Decimal(d) => write!(f, "{}", d),
+            // Debug has the short exponent form for big and small numbers
+            Value::Float(x) => write!(f, "{:?}", x),
+            Value::Bool(b) => write!(f, "{}", b),
+            Value::Date(d) => write!(f, "{}", d.format(DATE_FORMAT)),
+            Value::Time(t) => write!(f, "{}", t.format(TIME_FORMAT)),
+            Value::DateTime(dt) => write!(f, "{}", dt.format(DATE_TIME_FORMAT)),
+            // the qvs20 text of the nested table, the RowChecker checks the nested table
+            Value::Table(t) => write!(
+                f,
+                "{}",
+                String::from_utf8_lossy(&t.to_qvs20_bytes_unchecked())
+            ),
+            Value::Bytes(b) => write!(f, "{}", String::from_utf8_lossy(b)),
+            Value::Null => Ok(()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
 pub struct Row {
     pub values: Vec<Value>,
 }
 
-#[derive(Clone, Debug, EnumString, Eq, PartialEq)]
-pub enum DataType {
-    String,
-    Integer,
-    Decimal,
-    Float,
-    Bool,
-    Date,
-    Time,
-    DateTime,
-    Table,
-}
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug, Default, PartialEq)]
 pub struct Table {
-    pub table_name: String,
-    pub row_delimiter: u8,
-    // second row is data types
-    pub data_types: Vec<DataType>,
-    // third row is additional properties as strings
-    pub additional_properties: Vec<String>,
-    // fourth roe is column names, the last schema row
-    pub column_names: Vec<String>,
+    // the 4 schema rows
+    pub schema: Schema,
     pub rows: Vec<Row>,
 }
 
-impl Table {
-    /// first row: table name and row_delimiter are mandatory
-    fn first_row_table_name(&mut self, rdr: &mut ReaderForQvs20) -> Result<(), Qvs20ErrorTable> {
-        let result = match rdr.next() {
-            Some(p) => p,
-            None => {
-                return Err(Qvs20ErrorTable::Error {
-                    msg: "first row is empty.".to_string(),
-                })
-            }
-        };
-        let token = match result {
-            Ok(p) => p,
-            Err(e) => {
-                return Err(Qvs20ErrorTable::ErrorWithSource {
-                    source: e,
-                    msg: "first row table name.".to_string(),
-                })
-            }
-        };
-        let table_name = match token {
-            Token::Field(f) => f,
-            Token::RowDelimiter(r) => {
-                return Err(Qvs20ErrorTable::Error {
-                    msg: format!(
-                        "first row, expected Field found Row delimiter: {:?}.",
-                        r
-                    ),
-                })
-            }
-        };
-        self.table_name = Self::unescape(table_name);
-        // row delimiter is mandatory
-        let result = match rdr.next() {
-            Some(p) => p,
-            None => {
-                return Err(Qvs20ErrorTable::Error {
-                    msg: "first row missing row delimiter.".to_string(),
-                })
-            }
-        };
-        let token = match result {
-            Ok(p) => p,
-            Err(e) => {
-                return Err(Qvs20ErrorTable::ErrorWithSource {
-                    source: e,
-                    msg: "first row ".to_string(),
-                })
-            }
-        };
-        let row_delimiter = match token {
-            Token::Field(f) => {
-                return Err(Qvs20ErrorTable::Error {
-                    msg: "first row expected Row delimiter found Field.".to_string(),
-                })
-            }
-            Token::RowDelimiter(r) => r,
-        };
-        self.row_delimiter = row_delimiter;
-
-        Ok(())
+impl Column {
+    /// parse the raw field (not unescaped)
+    /// the reserved field [\0] is the missing value: the default value if the column has one,
+    /// else Null and the RowChecker rejects it if the column is not nullable
+    /// the empty field [] is the empty text, never the default
+    pub fn value_from_field(&self, field: &[u8]) -> Result<Value, Qvs20ErrorTable> {
+        if field == NULL_FIELD {
+            return match self.properties.default_value() {
+                Some(default) => Value::from_text(default.to_string(), &self.data_type),
+                None => Ok(Value::Null),
+            };
+        }
+        Value::from_qvs20_field(field, &self.data_type)
     }
+}
 
-    /// second row: data types
-    /// Option::None means end of the row
-    fn second_row_data_types(
-        &mut self,
+impl Schema {
+    /// data row: every field is converted to Value of the column data type
+    /// row_number is 1-based and counts only data rows
+    /// Option::None means end of file
+    pub(crate) fn data_row(
+        &self,
         rdr: &mut ReaderForQvs20,
-    ) -> Option<Result<(), Qvs20ErrorTable>> {
-        let result = unwrap_option_or_error!(
-            rdr.next(),
-            Some(Err(Qvs20ErrorTable::Error{
-                msg: "Missing mandatory second row - data types.".to_string(),
-            }))
-        );
-        let token = match result {
-            Ok(p) => p,
-            Err(e) => {
-                return Some(Err(Qvs20ErrorTable::ErrorWithSource {
-                    source: e,
-                    msg: "second row ".to_string(),
-                }))
-            }
-        };
-        // could be field or row_delimiter
-        if let Token::RowDelimiter(r) = token {
-            // row delimiter must be the same
-            if r != self.row_delimiter {
-                return Some(Err(Qvs20ErrorTable::Error{
-                    msg: format!("second row wrong row delimiter:{:?} instead of {:?}",r, self.row_delimiter),
-                }));
-            }
-            //end of row
-            return None;
-        }
-        let data_type = match token {
-            Token::Field(f) => f,
-            Token::RowDelimiter(r) => {
-                return Some(Err(Qvs20ErrorTable::Error {
-                    msg: format!(
-                        "second row, expected Field found Row delimiter: {:?}.",
-                        r
-                    ),
-                }))
-            }
-        };
-        let data_type = match String::from_utf8(data_type.to_vec()) {
-            Ok(p) => p,
-            Err(e) => {
-                return Some(Err(Qvs20ErrorTable::Error {
-                    msg: format!("second row {}",e),
-                }))
-            }
+        row_number: usize,
+    ) -> Option<Result<Row, Qvs20ErrorTable>> {
+        let mut row = Row {
+            values: Vec::with_capacity(self.columns.len()),
         };
-        let data_type = match DataType::from_str(&data_type) {
-            Ok(p) => p,
-            Err(e) => {
-                return Some(Err(Qvs20ErrorTable::Error {
-                    msg: format!("second row {}", e),
-                }))
-            }
+        let error = |msg: String, position: Position, source| {
+            Some(Err(Qvs20ErrorTable::ErrorInDataRow {
+                row_number,
+                msg,
+                position,
+                source,
+            }))
         };
-        self.data_types.push(data_type);
-        // return
-        Some(Ok(()))
-    }
-    /// third row additional properties
-    /// Option::None means end of the row
-    fn third_row_additional_properties(
-        &mut self,
-        rdr: &mut ReaderForQvs20,
-    ) -> Option<Result<(), Qvs20ErrorTable>> {
-        let result = unwrap_option_or_error!(
-            rdr.next(),
-            Some(Err(Qvs20ErrorTable::ErrorInThirdRowAdditionalProperties))
-        );
-        let token = unwrap_result_or_error!(
-            result,
-            Some(Err(Qvs20ErrorTable::ErrorInThirdRowAdditionalProperties))
-        );
-        // could be field or row_delimiter
-        if let Token::RowDelimiter(r) = token {
-            // row delimiter must be the same
-            if r != self.row_delimiter {
-                return Some(Err(Qvs20ErrorTable::ErrorInThirdRowAdditionalProperties));
+        loop {
+            let pos = rdr.pos();
+            let token = match rdr.next() {
+                Some(Ok(token)) => token,
+                Some(Err(e)) => {
+                    return error(
+                        e.reason().to_string(),
+                        e.position().clone(),
+                        Some(Box::new(e)),
+                    )
+                }
+                None => {
+                    if row.values.is_empty() {
+                        // normal end of file after the last row delimiter
+                        return None;
+                    } else {
+                        return error(
+                            "missing row delimiter".to_string(),
+                            rdr.position_at(pos),
+                            None,
+                        );
+                    }
+                }
+            };
+            match token {
+                Token::Field(f) => {
+                    // more fields than columns
+                    let column = match self.columns.get(row.values.len()) {
+                        Some(column) => column,
+                        None => {
+                            return error(
+                                format!("more than {} fields", self.columns.len()),
+                                rdr.position_at(pos),
+                                None,
+                            )
+                        }
+                    };
+                    let value = match column.value_from_field(f) {
+                        Ok(value) => value,
+                        Err(e) => {
+                            return Some(Err(Qvs20ErrorTable::ErrorInDataField {
+                                row_number,
+                                field: row.values.len() + 1,
+                                column: column.name.clone(),
+                                position: rdr.position_at(pos),
+                                source: Box::new(e),
+                            }))
+                        }
+                    };
+                    row.values.push(value);
+                }
+                Token::RowDelimiter(r) => {
+                    // row delimiter must be the same and the row must be complete
+                    if r != &self.row_delimiter[..] {
+                        return error(
+                            format!(
+                                "wrong row delimiter {} instead of {}",
+                                ReaderForQvs20::row_delimiter_text(r),
+                                ReaderForQvs20::row_delimiter_text(&self.row_delimiter)
+                            ),
+                            rdr.position_at(pos),
+                            None,
+                        );
+                    }
+                    if row.values.len() != self.columns.len() {
+                        return error(
+                            format!(
+                                "{} fields instead of {}",
+                                row.values.len(),
+                                self.columns.len()
+                            ),
+                            rdr.position_at(pos),
+                            None,
+                        );
+                    }
+                    // return
+                    return Some(Ok(row));
+                }
             }
-            //end of row
-            return None;
         }
-        let additional_property = unwrap_field_or_error!(
-            token,
-            Some(Err(Qvs20ErrorTable::ErrorInThirdRowAdditionalProperties))
-        );
-        self.additional_properties
-            .push(Self::unescape(additional_property));
-        // return
-        Some(Ok(()))
-    }
-    /// fourth row column names
-    /// Option::None means end of the row
-    fn fourth_row_column_names(
-        &mut self,
-        rdr: &mut ReaderForQvs20,
-    ) -> Option<Result<(), Qvs20ErrorTable>> {
-        let result = unwrap_option_or_error!(
-            rdr.next(),
-            Some(Err(Qvs20ErrorTable::ErrorInFourthRowColumnNames))
-        );
-        let token = unwrap_result_or_error!(
-            result,
-            Some(Err(Qvs20ErrorTable::ErrorInFourthRowColumnNames))
-        );
-        // could be field or row_delimiter
-        if let Token::RowDelimiter(r) = token {
-            // row delimiter must be the same
-            if r != self.row_delimiter {
-                return Some(Err(Qvs20ErrorTable::ErrorInFourthRowColumnNames));
-            }
-            //end of row
-            return None;
-        }
-        let column_name = unwrap_field_or_error!(
-            token,
-            Some(Err(Qvs20ErrorTable::ErrorInFourthRowColumnNames))
-        );
-        let column_name = Self::unescape(column_name);
-        // names of columns must be unique
-        for name in self.column_names.iter() {
-            if name == &column_name {
-                return Some(Err(Qvs20ErrorTable::ErrorInFourthRowColumnNames));
-            }
-        }
-        self.column_names.push(column_name);
-        // return
-        Some(Ok(()))
     }
+}
+
+impl Table {
     /// create an object in memory from a qvs20 string in [u8] format
     pub fn from_qvs20_with_schema(input: &[u8]) -> Result<Table, Qvs20ErrorTable> {
-        let mut table = Table::default();
         let mut rdr = ReaderForQvs20::new(input);
+        Self::read_table(&mut rdr, None)
+    }
 
-        // first row: table name and row delimiter
-        table.first_row_table_name(&mut rdr)?;
-        // second row: data types
-        while let Some(result) = table.second_row_data_types(&mut rdr) {
-            // if Err then propagate
-            result?;
-        }
-        // must have at least one column
-        if table.data_types.is_empty() {
-            return Err(Qvs20ErrorTable::Error{
-                msg:"second row, data types is empty.".to_string(),
-            });
-        }
-        // third row - additional properties
-        while let Some(result) = table.third_row_additional_properties(&mut rdr) {
-            // if Err then propagate
-            result?;
-        }
-        // third rows must have same number of columns as data_types
-        if table.additional_properties.len() != table.data_types.len() {
-            return Err(Qvs20ErrorTable::ErrorInThirdRowAdditionalProperties);
-        }
-        // fourth row - column names
-        while let Some(result) = table.fourth_row_column_names(&mut rdr) {
-            // if Err then propagate
-            result?;
+    /// read the 4 schema rows and the data rows
+    /// row_count None reads till the end of file, Some(n) reads exactly n data rows
+    /// and the reader stays at the start of the next table in the bundle
+    pub(crate) fn read_table(
+        rdr: &mut ReaderForQvs20,
+        row_count: Option<usize>,
+    ) -> Result<Table, Qvs20ErrorTable> {
+        // the 4 schema rows
+        let mut table = Table {
+            schema: Schema::read_schema(rdr)?,
+            rows: vec![],
+        };
+        // rows of data
+        let mut checker = RowChecker::new(&table.schema);
+        let mut row_number = 1;
+        while row_number <= row_count.unwrap_or(usize::MAX) {
+            let row_pos = rdr.pos();
+            let row = match table.schema.data_row(rdr, row_number) {
+                // if Err then propagate
+                Some(result) => result?,
+                None => break,
+            };
+            checker
+                .check_row(row_number, &row)
+                .map_err(|e| e.with_field_position(rdr, row_pos))?;
+            table.rows.push(row);
+            row_number += 1;
         }
-        // every row must have same number of columns as data_types
-        if table.column_names.len() != table.data_types.len() {
-            return Err(Qvs20ErrorTable::ErrorInFourthRowColumnNames);
+        if let Some(n) = row_count {
+            if table.rows.len() != n {
+                return Err(Qvs20ErrorTable::Error {
+                    msg: format!(
+                        "table {} has {} data rows instead of {}.",
+                        table.schema.table_name,
+                        table.rows.len(),
+                        n
+                    ),
+                });
+            }
         }
-        // rows of data
-
         //return
         Ok(table)
     }
@@ -342,53 +409,78 @@ impl Table {
     /// \n New line
     /// \r Carriage return
     /// \t Tab
-    pub fn unescape(field_value: &[u8]) -> String {
-        //println!("unescape: {}", unwrap!(String::from_utf8(field_value.to_vec())));
+    /// The whole field \0 is Null and is not unescaped, see NULL_FIELD.
+    /// The field must be UTF-8, the error has the byte position inside of the field.
+    pub fn unescape(field_value: &[u8]) -> Result<String, Qvs20ErrorTable> {
+        let field_value = match std::str::from_utf8(field_value) {
+            Ok(s) => s,
+            Err(e) => {
+                return Err(Qvs20ErrorTable::Error {
+                    msg: format!("invalid UTF-8 at byte {} of the field", e.valid_up_to()),
+                })
+            }
+        };
+        let bytes = field_value.as_bytes();
         let mut ret = String::with_capacity(field_value.len());
         let mut start_pos = 0;
-        while let Some(pos) = ReaderForQvs20::find_u8_from(field_value, start_pos, b'\\') {
+        while let Some(pos) = ReaderForQvs20::find_u8_from(bytes, start_pos, b'\\') {
             let end_pos = pos;
             // push the normal characters
-            let str_value = unwrap!(String::from_utf8(field_value[start_pos..end_pos].to_vec()));
-            ret.push_str(&str_value);
+            ret.push_str(&field_value[start_pos..end_pos]);
             // unescape the special character
-            match field_value[end_pos + 1] {
-                b'\\' => ret.push('\\'),
-                b'[' => ret.push('['),
-                b']' => ret.push(']'),
-                b'n' => ret.push('\n'),
-                b'r' => ret.push('\r'),
-                b't' => ret.push('\t'),
+            let escaped = match field_value[end_pos + 1..].chars().next() {
+                Some(c) => c,
+                None => {
+                    return Err(Qvs20ErrorTable::Error {
+                        msg: format!("backslash at the end of the field at byte {}", end_pos),
+                    })
+                }
+            };
+            match escaped {
+                '\\' => ret.push('\\'),
+                '[' => ret.push('['),
+                ']' => ret.push(']'),
+                'n' => ret.push('\n'),
+                'r' => ret.push('\r'),
+                't' => ret.push('\t'),
                 _ => ret.push('?'),
             }
-            start_pos = end_pos + 2;
+            start_pos = end_pos + 1 + escaped.len_utf8();
         }
         // push the last part of normal characters
-        let end_pos = field_value.len();
-        let str_value = unwrap!(String::from_utf8(field_value[start_pos..end_pos].to_vec()));
-        ret.push_str(&str_value);
+        ret.push_str(&field_value[start_pos..]);
 
         // return
-        ret
+        Ok(ret)
     }
 }
 #[cfg(test)]
 mod test {
     use super::*;
-    //use unwrap::unwrap;
+    use unwrap::unwrap;
     #[test]
     pub fn test_01_unescape() {
         // one byte characters
-        let unescaped = Table::unescape(b"one");
+        let unescaped = unwrap!(Table::unescape(b"one"));
         assert_eq!(unescaped, "one");
         // non one byte characters
-        let unescaped = Table::unescape("čćšß€".as_bytes());
+        let unescaped = unwrap!(Table::unescape("čćšß€".as_bytes()));
         assert_eq!(unescaped, "čćšß€");
         // qvs20 escape sequences
-        let unescaped = Table::unescape(r"1\[2\]3\\4\r5\n6\t".as_bytes());
+        let unescaped = unwrap!(Table::unescape(r"1\[2\]3\\4\r5\n6\t".as_bytes()));
         assert_eq!(unescaped, "1[2]3\\4\r5\n6\t");
+        // unknown escape of a non one byte character
+        let unescaped = unwrap!(Table::unescape(r"a\čb".as_bytes()));
+        assert_eq!(unescaped, "a?b");
+        // the field must be UTF-8
+        let err = Table::unescape(b"ab\xff").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Error: invalid UTF-8 at byte 2 of the field"
+        );
+        assert!(Table::unescape(b"a\\").is_err());
     }
-    //#[test]
+    #[test]
     pub fn test_02() {
         let s = r"[table-name]
 [String][String]
@@ -400,11 +492,14 @@ mod test {
 ";
         let table = unwrap!(Table::from_qvs20_with_schema(s.as_bytes()));
         let table2 = Table {
-            table_name: "table-name".to_string(),
-            row_delimiter: b'\n',
-            data_types: vec![DataType::String, DataType::String],
-            additional_properties: vec![String::new(), String::new()],
-            column_names: vec!["name".to_string(), "description".to_string()],
+            schema: Schema {
+                table_name: "table-name".to_string(),
+                row_delimiter: LF.to_vec(),
+                columns: vec![
+                    Column::new("name", DataType::String),
+                    Column::new("description", DataType::String),
+                ],
+            },
             rows: vec![
                 Row {
                     values: vec![
@@ -439,121 +534,318 @@ NewLine"
         // error in first row - table_name
         let s = r"";
         let err_msg = Table::from_qvs20_with_schema(s.as_bytes()).unwrap_err();
-        assert_eq!(err_msg.to_string(), "Error: first row is empty.");
+        assert_eq!(err_msg.to_string(), r#"Error in first row: table name: first row is empty (line 1, column 1, pos: 0, near "")"#);
 
         let s = r"bad-formed text";
         let err_msg = Table::from_qvs20_with_schema(s.as_bytes()).unwrap_err();
-        assert_eq!(err_msg.to_string(), "Error: first row table name. Error: The field must start with [. pos: 0");
+        assert_eq!(err_msg.to_string(), r#"Error in first row: table name: The field must start with [ (line 1, column 1, pos: 0, near "bad-formed text")"#);
 
         let s = r"[bad-formed text";
         let err_msg = Table::from_qvs20_with_schema(s.as_bytes()).unwrap_err();
-        assert_eq!(err_msg.to_string(), "Error: first row table name. Error: Last bracket is missing. pos: 1");
+        assert_eq!(err_msg.to_string(), r#"Error in first row: table name: Last bracket is missing (line 1, column 2, pos: 1, near "[bad-formed text")"#);
 
         let s = r"[no row delimiter]";
         let err_msg = Table::from_qvs20_with_schema(s.as_bytes()).unwrap_err();
-        assert_eq!(err_msg.to_string(), "Error: first row  Error: Last row delimiter is missing. pos: 18");
+        assert_eq!(err_msg.to_string(), r#"Error in first row: table name: Last row delimiter is missing (line 1, column 19, pos: 18, near "limiter]")"#);
 
         let s = "[row delimiter too big]\n\n";
         let err_msg = Table::from_qvs20_with_schema(s.as_bytes()).unwrap_err();
-        assert_eq!(err_msg.to_string(), "Error: first row  Error: The row delimiter has more than 1 byte. pos: 23");
+        assert_eq!(err_msg.to_string(), r#"Error in first row: table name: The row delimiter must be 1 byte or CRLF (line 1, column 24, pos: 23, near "too big]\n\n")"#);
 
         // error in second row - data types
         // good table_name, but no 2nd row
         let s = "[table name]\n";
         let err_msg = Table::from_qvs20_with_schema(s.as_bytes()).unwrap_err();
-        assert_eq!(err_msg.to_string(), "Error: Missing mandatory second row - data types.");
+        assert_eq!(err_msg.to_string(), r#"Error in second row: data types, field 1: missing row (line 2, column 1, pos: 13, near "e name]\n")"#);
 
         let s = "[table name]\n[String";
         let err_msg = Table::from_qvs20_with_schema(s.as_bytes()).unwrap_err();
-        assert_eq!(err_msg.to_string(), "Error: second row  Error: Last bracket is missing. pos: 14");
+        assert_eq!(err_msg.to_string(), r#"Error in second row: data types, field 1: Last bracket is missing (line 2, column 2, pos: 14, near " name]\n[String")"#);
 
         let s = "[table name]\n[String]";
         let err_msg = Table::from_qvs20_with_schema(s.as_bytes()).unwrap_err();
-        assert_eq!(err_msg.to_string(), "Error: second row  Error: Last row delimiter is missing. pos: 21");
+        assert_eq!(err_msg.to_string(), r#"Error in second row: data types, field 2: Last row delimiter is missing (line 2, column 9, pos: 21, near "[String]")"#);
 
         let s = "[table name]\n[String][Integer][Decimal]";
         let err_msg = Table::from_qvs20_with_schema(s.as_bytes()).unwrap_err();
-        assert_eq!(err_msg.to_string(), "Error: second row  Error: Last row delimiter is missing. pos: 39");
+        assert_eq!(err_msg.to_string(), r#"Error in second row: data types, field 4: Last row delimiter is missing (line 2, column 27, pos: 39, near "Decimal]")"#);
 
         // wrong row delimiter
         let s = "[table name]\n[String][Integer][Decimal]1";
         let err_msg = Table::from_qvs20_with_schema(s.as_bytes()).unwrap_err();
-        assert_eq!(err_msg.to_string(), "Error: second row wrong row delimiter:49 instead of 10");
+        assert_eq!(err_msg.to_string(), r#"Error in second row: data types, field 4: wrong row delimiter "1" instead of "\n" (line 2, column 27, pos: 39, near "Decimal]1")"#);
 
         let s = "[table name]\n[String][Integer][Decimal]\n\n";
         let err_msg = Table::from_qvs20_with_schema(s.as_bytes()).unwrap_err();
-        assert_eq!(err_msg.to_string(), "Error: second row  Error: The row delimiter has more than 1 byte. pos: 39");
+        assert_eq!(err_msg.to_string(), r#"Error in second row: data types, field 4: The row delimiter must be 1 byte or CRLF (line 2, column 27, pos: 39, near "Decimal]\n\n")"#);
 
         // error in third row - additional properties
         let s = "[table name]\n[String][Integer][Decimal]\n";
         let err_msg = Table::from_qvs20_with_schema(s.as_bytes()).unwrap_err();
-        assert_eq!(err_msg.to_string(),"");
-/*
+        assert_eq!(err_msg.to_string(), r#"Error in third row: additional properties, field 1: missing row (line 3, column 1, pos: 40, near "ecimal]\n")"#);
+
         let s = "[table name]\n[String][Integer][Decimal]\n[prop";
         let err_msg = Table::from_qvs20_with_schema(s.as_bytes()).unwrap_err();
-        assert_eq!(
-            err_msg,
-            Qvs20ErrorTable::ErrorInThirdRowAdditionalProperties
-        );
+        assert_eq!(err_msg.to_string(), r#"Error in third row: additional properties, field 1: Last bracket is missing (line 3, column 2, pos: 41, near "cimal]\n[prop")"#);
 
         let s = "[table name]\n[String][Integer][Decimal]\n[prop]";
         let err_msg = Table::from_qvs20_with_schema(s.as_bytes()).unwrap_err();
-        assert_eq!(
-            err_msg,
-            Qvs20ErrorTable::ErrorInThirdRowAdditionalProperties
-        );
+        assert_eq!(err_msg.to_string(), r#"Error in third row: additional properties, field 2: Last row delimiter is missing (line 3, column 7, pos: 46, near "]\n[prop]")"#);
 
         let s = "[table name]\n[String][Integer][Decimal]\n[prop][prop]\n";
         let err_msg = Table::from_qvs20_with_schema(s.as_bytes()).unwrap_err();
-        assert_eq!(
-            err_msg,
-            Qvs20ErrorTable::ErrorInThirdRowAdditionalProperties
-        );
+        assert_eq!(err_msg.to_string(), r#"Error in third row: additional properties, field 3: 2 fields instead of 3 (line 3, column 13, pos: 52, near "p][prop]\n")"#);
         // wrong row delimiter
         let s = "[table name]\n[String][Integer][Decimal]\n[prop1][prop2][prop3]1";
         let err_msg = Table::from_qvs20_with_schema(s.as_bytes()).unwrap_err();
-        assert_eq!(
-            err_msg,
-            Qvs20ErrorTable::ErrorInThirdRowAdditionalProperties
-        );
+        assert_eq!(err_msg.to_string(), r#"Error in third row: additional properties, field 4: wrong row delimiter "1" instead of "\n" (line 3, column 22, pos: 61, near "][prop3]1")"#);
         // row delimiter must be only 1 byte
         let s = "[table name]\n[String][Integer][Decimal]\n[prop1][prop2][prop3]\n\n";
         let err_msg = Table::from_qvs20_with_schema(s.as_bytes()).unwrap_err();
-        assert_eq!(
-            err_msg,
-            Qvs20ErrorTable::ErrorInThirdRowAdditionalProperties
-        );
+        assert_eq!(err_msg.to_string(), r#"Error in third row: additional properties, field 4: The row delimiter must be 1 byte or CRLF (line 3, column 22, pos: 61, near "][prop3]\n\n")"#);
 
         // fourth row: column names
         let s = "[table name]\n[String][Integer][Decimal]\n[prop1][prop2][prop3]\n";
         let err_msg = Table::from_qvs20_with_schema(s.as_bytes()).unwrap_err();
-        assert_eq!(err_msg, Qvs20ErrorTable::ErrorInFourthRowColumnNames);
+        assert_eq!(err_msg.to_string(), r#"Error in fourth row: column names, field 1: missing row (line 4, column 1, pos: 62, near "[prop3]\n")"#);
 
         let s = "[table name]\n[String][Integer][Decimal]\n[prop1][prop2][prop3]\n[name";
         let err_msg = Table::from_qvs20_with_schema(s.as_bytes()).unwrap_err();
-        assert_eq!(err_msg, Qvs20ErrorTable::ErrorInFourthRowColumnNames);
+        assert_eq!(err_msg.to_string(), r#"Error in fourth row: column names, field 1: Last bracket is missing (line 4, column 2, pos: 63, near "prop3]\n[name")"#);
 
         let s = "[table name]\n[String][Integer][Decimal]\n[prop1][prop2][prop3]\n[name]";
         let err_msg = Table::from_qvs20_with_schema(s.as_bytes()).unwrap_err();
-        assert_eq!(err_msg, Qvs20ErrorTable::ErrorInFourthRowColumnNames);
+        assert_eq!(err_msg.to_string(), r#"Error in fourth row: column names, field 2: Last row delimiter is missing (line 4, column 7, pos: 68, near "]\n[name]")"#);
 
         let s = "[table name]\n[String][Integer][Decimal]\n[prop1][prop2][prop3]\n[name1][name2]\n";
         let err_msg = Table::from_qvs20_with_schema(s.as_bytes()).unwrap_err();
-        assert_eq!(err_msg, Qvs20ErrorTable::ErrorInFourthRowColumnNames);
+        assert_eq!(err_msg.to_string(), r#"Error in fourth row: column names, field 3: 2 fields instead of 3 (line 4, column 15, pos: 76, near "][name2]\n")"#);
         // column names must be unique
         let s = "[table name]\n[String][Integer][Decimal]\n[prop1][prop2][prop3]\n[name1][name1][name1]\n";
         let err_msg = Table::from_qvs20_with_schema(s.as_bytes()).unwrap_err();
-        assert_eq!(err_msg, Qvs20ErrorTable::ErrorInFourthRowColumnNames);
+        assert_eq!(err_msg.to_string(), r#"Error in fourth row: column names, field 2: duplicate column name "name1" (line 4, column 8, pos: 69, near "\n[name1][name1][name1]\n")"#);
         // wrong row delimiter
         let s = "[table name]\n[String][Integer][Decimal]\n[prop1][prop2][prop3]\n[name1][name2][name3]1";
         let err_msg = Table::from_qvs20_with_schema(s.as_bytes()).unwrap_err();
-        assert_eq!(err_msg, Qvs20ErrorTable::ErrorInFourthRowColumnNames);
+        assert_eq!(err_msg.to_string(), r#"Error in fourth row: column names, field 4: wrong row delimiter "1" instead of "\n" (line 4, column 22, pos: 83, near "][name3]1")"#);
 
         let s = "[table name]\n[String][Integer][Decimal]\n[prop1][prop2][prop3]\n[name1][name2][name3]\n\n";
         let err_msg = Table::from_qvs20_with_schema(s.as_bytes()).unwrap_err();
-        assert_eq!(err_msg, Qvs20ErrorTable::ErrorInFourthRowColumnNames);
-
-        // rows of data
-        */
+        assert_eq!(err_msg.to_string(), r#"Error in fourth row: column names, field 4: The row delimiter must be 1 byte or CRLF (line 4, column 22, pos: 83, near "][name3]\n\n")"#);
+    }
+    #[test]
+    pub fn test_04_all_wrong_in_data_rows() {
+        let schema = "[table name]\n[String][Integer]\n[][]\n[name][id]\n";
+        // schema without rows is a valid empty table
+        let table = unwrap!(Table::from_qvs20_with_schema(schema.as_bytes()));
+        assert!(table.rows.is_empty());
+
+        // too few fields
+        let s = format!("{}[name_1][1]\n[name_2]\n", schema);
+        let err_msg = Table::from_qvs20_with_schema(s.as_bytes()).unwrap_err();
+        assert_eq!(err_msg.to_string(), r#"Error in data row 2: 1 fields instead of 2 (line 6, column 9, pos: 67, near "[name_2]\n")"#);
+        // too many fields
+        let s = format!("{}[name_1][1][one too many]\n", schema);
+        let err_msg = Table::from_qvs20_with_schema(s.as_bytes()).unwrap_err();
+        assert_eq!(err_msg.to_string(), r#"Error in data row 1: more than 2 fields (line 5, column 12, pos: 58, near "me_1][1][one too many]\n")"#);
+        // wrong row delimiter
+        let s = format!("{}[name_1][1]1", schema);
+        let err_msg = Table::from_qvs20_with_schema(s.as_bytes()).unwrap_err();
+        assert_eq!(err_msg.to_string(), r#"Error in data row 1: wrong row delimiter "1" instead of "\n" (line 5, column 12, pos: 58, near "me_1][1]1")"#);
+        // Integer that does not parse
+        let s = format!("{}[name_1][1]\n[name_2][two]\n", schema);
+        let err_msg = Table::from_qvs20_with_schema(s.as_bytes()).unwrap_err();
+        assert_eq!(err_msg.to_string(), r#"Error in data row 2, field 2, column id: Error: Integer "two" invalid digit found in string (line 6, column 9, pos: 67, near "[name_2][two]\n")"#);
+        // missing last row delimiter
+        let s = format!("{}[name_1][1]", schema);
+        let err_msg = Table::from_qvs20_with_schema(s.as_bytes()).unwrap_err();
+        assert_eq!(err_msg.to_string(), r#"Error in data row 1: Last row delimiter is missing (line 5, column 12, pos: 58, near "me_1][1]")"#);
+        // the error of the value is the source, the position is in the whole file
+        let s = format!("{}[name_1][1]\n[name_2][two]\n", schema);
+        match Table::from_qvs20_with_schema(s.as_bytes()).unwrap_err() {
+            Qvs20ErrorTable::ErrorInDataField {
+                row_number,
+                field,
+                column,
+                position,
+                source,
+            } => {
+                assert_eq!((row_number, field, column.as_str()), (2, 2, "id"));
+                assert_eq!((position.line, position.column), (6, 9));
+                // the error of the parser is the source of the source
+                match *source {
+                    Qvs20ErrorTable::ErrorWithSource { source, .. } => {
+                        assert!(source.is::<std::num::ParseIntError>())
+                    }
+                    e => panic!("expected ErrorWithSource, found {:?}", e),
+                }
+            }
+            e => panic!("expected ErrorInDataField, found {:?}", e),
+        }
+        // the error of the nested table stays a Qvs20ErrorTable
+        match Value::from_text("[t]1[Strin]1".to_string(), &DataType::Table).unwrap_err() {
+            Qvs20ErrorTable::ErrorWithSource { source, .. } => {
+                let nested = source.downcast_ref::<Qvs20ErrorTable>();
+                assert!(matches!(nested, Some(Qvs20ErrorTable::ErrorSchema { .. })));
+            }
+            e => panic!("expected ErrorWithSource, found {:?}", e),
+        }
+    }
+    #[test]
+    pub fn test_05_sample_data() {
+        let s = unwrap!(std::fs::read("sample_data/crates.qvs20"));
+        let table = unwrap!(Table::from_qvs20_with_schema(&s));
+        assert_eq!(table.schema.table_name, "crates table");
+        assert_eq!(table.schema.columns.len(), 5);
+        assert_eq!(table.rows.len(), 5);
+        match &table.rows[4].values[3] {
+            Value::Integer(i) => assert_eq!(*i, 1605),
+            v => panic!("expected Integer, found {:?}", v),
+        }
+    }
+    #[test]
+    pub fn test_06_data_types() {
+        let s = r"[all types]
+[String][Integer][Decimal][Float][Bool][Date][Time][DateTime][Table]
+[][][][][][][][][]
+[s][i][d][f][b][date][time][date_time][t]
+[text][-42][12345678901234567890.0123][0.1][true][2020-02-29][23:59:59.5][2020-02-29T23:59:59][\[t\]1\[String\]1\[\]1\[s\]1\[a\]1]
+[][0][-0.5][-1e300][false][0001-01-01][00:00:00][2019-01-01 12:30:00.123456][\[t\]1\[String\]1\[\]1\[s\]1]
+";
+        let table = unwrap!(Table::from_qvs20_with_schema(s.as_bytes()));
+        let values = &table.rows[0].values;
+        assert_eq!(values[0], Value::String("text".to_string()));
+        assert_eq!(values[1], Value::Integer(-42));
+        assert_eq!(
+            values[2],
+            Value::Decimal(unwrap!(Decimal::from_str("12345678901234567890.0123")))
+        );
+        assert_eq!(values[3], Value::Float(0.1));
+        assert_eq!(values[4], Value::Bool(true));
+        assert_eq!(values[5], Value::Date(unwrap!(NaiveDate::from_ymd_opt(2020, 2, 29))));
+        assert_eq!(
+            values[6],
+            Value::Time(unwrap!(NaiveTime::from_hms_milli_opt(23, 59, 59, 500)))
+        );
+        assert_eq!(values[7].to_string(), "2020-02-29T23:59:59");
+        assert_eq!(values[8].to_string(), "[t]1[String]1[]1[s]1[a]1");
+        // DateTime with space is accepted, but the canonical text has T
+        assert_eq!(
+            table.rows[1].values[7].to_string(),
+            "2019-01-01T12:30:00.123456"
+        );
+        // canonical text parses back to the same value
+        for row in table.rows.iter() {
+            for (value, column) in row.values.iter().zip(table.schema.columns.iter()) {
+                let text = value.to_string();
+                assert_eq!(&unwrap!(Value::from_text(text, &column.data_type)), value);
+            }
+        }
+        // wrong text for the data type
+        let err_msg = Value::from_text("yes".to_string(), &DataType::Bool).unwrap_err();
+        assert_eq!(err_msg.to_string(), r#"Error: Bool "yes" must be true or false"#);
+        assert!(Value::from_text("2020-02-30".to_string(), &DataType::Date).is_err());
+        assert!(Value::from_text("1.2.3".to_string(), &DataType::Decimal).is_err());
+        assert!(Value::from_text("25:00:00".to_string(), &DataType::Time).is_err());
+    }
+    #[test]
+    pub fn test_07_null() {
+        let s = r"[t]
+[String][Integer]
+[nullable][nullable]
+[description][downloads]
+[][\0]
+[\0][7]
+";
+        let table = unwrap!(Table::from_qvs20_with_schema(s.as_bytes()));
+        // the empty string is not null
+        assert_eq!(table.rows[0].values[0], Value::String("".to_string()));
+        assert_eq!(table.rows[0].values[1], Value::Null);
+        assert_eq!(table.rows[1].values[0], Value::Null);
+        // the writer encodes null with the reserved field
+        let bytes = unwrap!(table.to_qvs20_bytes());
+        assert_eq!(unwrap!(String::from_utf8(bytes)), s);
+        // null in a column that is not nullable
+        let s = "[t]\n[String]\n[]\n[name]\n[\\0]\n";
+        let err_msg = Table::from_qvs20_with_schema(s.as_bytes()).unwrap_err();
+        assert_eq!(
+            err_msg.to_string(),
+            r#"Error in data row 1, field 1, column name: null in a column that is not nullable. (line 5, column 1, pos: 23, near "\n[name]\n[\\0]\n")"#
+        );
+        // the escaped backslash followed by 0 is text
+        let s = "[t]\n[String]\n[]\n[name]\n[\\\\0]\n";
+        let table = unwrap!(Table::from_qvs20_with_schema(s.as_bytes()));
+        assert_eq!(table.rows[0].values[0], Value::String("\\0".to_string()));
+    }
+    #[test]
+    pub fn test_08_nested_table() {
+        let s = r"[crates]
+[String][Table]
+[][]
+[name][versions]
+[serde][\[versions\]1\[String\]\[Table\]1\[\]\[\]1\[num\]\[deps\]1\[1.0.0\]\[\\\[deps\\\]2\\\[String\\\]2\\\[\\\]2\\\[name\\\]2\\\[rand\\\]2\]1]
+";
+        let table = unwrap!(Table::from_qvs20_with_schema(s.as_bytes()));
+        let versions = match &table.rows[0].values[1] {
+            Value::Table(t) => t,
+            v => panic!("expected Table, found {:?}", v),
+        };
+        assert_eq!(versions.schema.table_name, "versions");
+        assert_eq!(versions.schema.row_delimiter, b"1");
+        assert_eq!(versions.rows[0].values[0], Value::String("1.0.0".to_string()));
+        // the second level of nesting
+        match &versions.rows[0].values[1] {
+            Value::Table(t) => {
+                assert_eq!(t.schema.row_delimiter, b"2");
+                assert_eq!(t.rows[0].values[0], Value::String("rand".to_string()));
+            }
+            v => panic!("expected Table, found {:?}", v),
+        }
+        // the writer escapes the nested tables recursively
+        let bytes = unwrap!(table.to_qvs20_bytes());
+        assert_eq!(unwrap!(String::from_utf8(bytes)), s);
+        // the nested table must have a different row delimiter
+        let s = "[t]\n[Table]\n[]\n[t]\n[\\[n\\]\n\\[String\\]\n\\[\\]\n\\[s\\]\n]\n";
+        let err_msg = Table::from_qvs20_with_schema(s.as_bytes()).unwrap_err();
+        assert_eq!(
+            err_msg.to_string(),
+            r#"Error in data row 1, field 1, column t: the nested table has the same row delimiter "\n" as the parent. (line 5, column 1, pos: 19, near "\n[]\n[t]\n[\\[n\\]\n\\[String\\")"#
+        );
+        // the text of a wrong nested table never fails, the RowChecker rejects it
+        let mut wrong = versions.as_ref().clone();
+        wrong.schema.row_delimiter = b"12".to_vec();
+        let value = Value::Table(Box::new(wrong));
+        assert!(value.to_string().starts_with("[versions]12[String][Table]12"));
+        let mut parent = table.clone();
+        parent.rows[0].values[1] = value;
+        assert_eq!(
+            parent.to_qvs20_bytes().unwrap_err().to_string(),
+            r#"Error in data row 1, field 2, column versions: the nested table has the row delimiter "12", it must be 1 byte or CRLF."#
+        );
+    }
+    #[test]
+    pub fn test_09_crlf() {
+        // a file saved on Windows
+        let s = "[t]\r\n[String][Integer]\r\n[][]\r\n[name][id]\r\n[name_1][1]\r\n";
+        let mut table = unwrap!(Table::from_qvs20_with_schema(s.as_bytes()));
+        assert_eq!(table.schema.row_delimiter, CRLF);
+        assert_eq!(table.rows[0].values[1], Value::Integer(1));
+        // the writer uses the row delimiter of the schema
+        assert_eq!(unwrap!(table.to_qvs20_bytes()), s.as_bytes());
+        table.schema.row_delimiter = LF.to_vec();
+        assert_eq!(
+            unwrap!(table.to_qvs20_bytes()),
+            s.replace("\r\n", "\n").as_bytes()
+        );
+        // all the rows must have the same row delimiter
+        let s = "[t]\r\n[String]\r\n[]\r\n[name]\r\n[name_1]\n";
+        let err_msg = Table::from_qvs20_with_schema(s.as_bytes()).unwrap_err();
+        assert_eq!(
+            err_msg.to_string(),
+            r#"Error in data row 1: wrong row delimiter "\n" instead of "\r\n" (line 5, column 9, pos: 35, near "[name_1]\n")"#
+        );
+        // the writer refuses other row delimiters
+        table.schema.row_delimiter = b"||".to_vec();
+        assert!(table.to_qvs20_bytes().is_err());
     }
 }
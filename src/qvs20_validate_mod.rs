@@ -0,0 +1,350 @@
+// qvs20_validate_mod
+
+// Validate a data file against a separate canonical schema.
+// The schema rows of the file must be the same as the canonical schema:
+// table name, row delimiter, column names, data types and additional properties.
+// Every value of the data rows must parse as the data type of the canonical column
+// and respect the additional properties of the canonical column.
+// All the violations are collected, the validation does not stop at the first one.
+// Only a malformed file (missing bracket, long row delimiter) stops the validation,
+// because after that the fields cannot be found reliably.
+
+use crate::qvs20_properties_mod::RowChecker;
+use crate::qvs20_reader_mod::*;
+use crate::qvs20_schema_mod::*;
+use crate::qvs20_table_mod::*;
+
+use std::fmt;
+
+/// the schema has 4 rows: table name, data types, additional properties, column names
+const SCHEMA_ROWS: usize = 4;
+
+/// one difference from the canonical schema or one value that does not parse
+#[derive(Clone, Debug, PartialEq)]
+pub struct Violation {
+    /// 1-based data row number, 0 is for the schema rows
+    pub row_number: usize,
+    /// column name, empty if the violation is for the whole row
+    pub column: String,
+    /// line, column and excerpt in the file
+    pub position: Position,
+    pub msg: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.row_number == 0 {
+            write!(f, "schema")?;
+        } else {
+            write!(f, "data row {}", self.row_number)?;
+        }
+        if !self.column.is_empty() {
+            write!(f, ", column {}", self.column)?;
+        }
+        write!(f, ": {} {}", self.msg, self.position)
+    }
+}
+
+/// all the violations of the qvs20 file against the canonical schema
+/// an empty vector means the file is valid
+pub fn validate_with_schema(expected: &Schema, input: &[u8]) -> Vec<Violation> {
+    let mut violations = vec![];
+    let mut push = |row_number: usize, column: &str, pos: usize, msg: String| {
+        violations.push(Violation {
+            row_number,
+            column: column.to_string(),
+            position: Position::new(input, pos),
+            msg,
+        })
+    };
+    let mut rdr = ReaderForQvs20::new(input);
+    let actual = match Schema::read_schema(&mut rdr) {
+        Ok(schema) => schema,
+        Err(e) => {
+            let position = e
+                .position()
+                .cloned()
+                .unwrap_or_else(|| rdr.position_at(rdr.pos()));
+            // the position is shown once, by the violation
+            let msg = e.to_string();
+            let msg = msg.strip_suffix(&format!(" {}", position)).unwrap_or(&msg);
+            push(0, "", position.pos, msg.to_string());
+            return violations;
+        }
+    };
+    // the schema is well-formed, so the positions of the fields can be collected
+    let positions = schema_field_positions(input);
+    if actual.table_name != expected.table_name {
+        push(
+            0,
+            "",
+            positions[0][0],
+            format!(
+                "table name {:?} instead of {:?}",
+                actual.table_name, expected.table_name
+            ),
+        );
+    }
+    if actual.row_delimiter != expected.row_delimiter {
+        push(
+            0,
+            "",
+            positions[0][0],
+            format!(
+                "row delimiter {} instead of {}",
+                ReaderForQvs20::row_delimiter_text(&actual.row_delimiter),
+                ReaderForQvs20::row_delimiter_text(&expected.row_delimiter)
+            ),
+        );
+    }
+    for (i, column) in expected.columns.iter().enumerate() {
+        let actual_column = match actual.columns.get(i) {
+            Some(actual_column) => actual_column,
+            None => {
+                push(
+                    0,
+                    &column.name,
+                    positions[3].last().copied().unwrap_or(0),
+                    format!("missing column {}", i + 1),
+                );
+                continue;
+            }
+        };
+        if actual_column.data_type != column.data_type {
+            push(
+                0,
+                &column.name,
+                positions[1][i],
+                format!(
+                    "data type {} instead of {}",
+                    actual_column.data_type, column.data_type
+                ),
+            );
+        }
+        if actual_column.properties != column.properties {
+            push(
+                0,
+                &column.name,
+                positions[2][i],
+                format!(
+                    "additional properties {:?} instead of {:?}",
+                    actual_column.properties.to_string(),
+                    column.properties.to_string()
+                ),
+            );
+        }
+        if actual_column.name != column.name {
+            push(
+                0,
+                &column.name,
+                positions[3][i],
+                format!(
+                    "column name {:?} instead of {:?}",
+                    actual_column.name, column.name
+                ),
+            );
+        }
+    }
+    for (i, column) in actual
+        .columns
+        .iter()
+        .enumerate()
+        .skip(expected.columns.len())
+    {
+        push(
+            0,
+            &column.name,
+            positions[3][i],
+            format!("unexpected column {}", i + 1),
+        );
+    }
+    // data rows: the values are checked against the canonical data types
+    let mut checker = RowChecker::new(expected);
+    let mut row_number = 1;
+    // the positions of the fields of the row
+    let mut field_positions = vec![];
+    let mut field_count = 0;
+    // the values of the row, None if a field is wrong
+    let mut values = Some(vec![]);
+    loop {
+        let pos = rdr.pos();
+        let token = match rdr.next() {
+            None => break,
+            Some(Ok(token)) => token,
+            Some(Err(e)) => {
+                push(
+                    row_number,
+                    "",
+                    e.position().pos,
+                    format!("malformed: {}", e.reason()),
+                );
+                break;
+            }
+        };
+        match token {
+            Token::Field(f) => {
+                field_positions.push(pos);
+                match expected.columns.get(field_count) {
+                    Some(column) => match column.value_from_field(f) {
+                        Ok(value) => {
+                            if let Some(values) = values.as_mut() {
+                                values.push(value);
+                            }
+                        }
+                        Err(e) => {
+                            push(row_number, &column.name, pos, e.to_string());
+                            values = None;
+                        }
+                    },
+                    None => {
+                        // report only the first field that is too many
+                        if field_count == expected.columns.len() {
+                            push(
+                                row_number,
+                                "",
+                                pos,
+                                format!("more than {} fields", expected.columns.len()),
+                            );
+                        }
+                        values = None;
+                    }
+                }
+                field_count += 1;
+            }
+            Token::RowDelimiter(r) => {
+                // the properties are checked only for the rows with all the values
+                if let Some(values) = values.take() {
+                    if values.len() == expected.columns.len() {
+                        for violation in checker.row_violations(row_number, &Row { values }) {
+                            if let Qvs20ErrorTable::PropertyViolation {
+                                field, column, msg, ..
+                            } = violation
+                            {
+                                push(row_number, &column, field_positions[field - 1], msg);
+                            }
+                        }
+                    }
+                }
+                if field_count < expected.columns.len() {
+                    push(
+                        row_number,
+                        "",
+                        pos,
+                        format!(
+                            "{} fields instead of {}",
+                            field_count,
+                            expected.columns.len()
+                        ),
+                    );
+                }
+                if r != &actual.row_delimiter[..] {
+                    push(
+                        row_number,
+                        "",
+                        pos,
+                        format!(
+                            "row delimiter {} instead of {}",
+                            ReaderForQvs20::row_delimiter_text(r),
+                            ReaderForQvs20::row_delimiter_text(&actual.row_delimiter)
+                        ),
+                    );
+                }
+                row_number += 1;
+                field_count = 0;
+                field_positions.clear();
+                values = Some(vec![]);
+            }
+        }
+    }
+    //return
+    violations
+}
+
+/// the byte positions of the fields in the 4 schema rows
+/// the schema must be already checked as well-formed
+fn schema_field_positions(input: &[u8]) -> Vec<Vec<usize>> {
+    let mut positions = vec![vec![]; SCHEMA_ROWS];
+    let mut row = 0;
+    let mut rdr = ReaderForQvs20::new(input);
+    while row < SCHEMA_ROWS {
+        let pos = rdr.pos();
+        match rdr.next() {
+            Some(Ok(Token::Field(_))) => positions[row].push(pos),
+            Some(Ok(Token::RowDelimiter(_))) => row += 1,
+            _ => break,
+        }
+    }
+    //return
+    positions
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use unwrap::unwrap;
+    #[test]
+    pub fn test_01_validate() {
+        let expected = unwrap!(Schema::from_qvs20(
+            b"[crates]\n[String][Integer][Date]\n[][unique][]\n[name][id][updated]\n"
+        ));
+        let s = "[crates]\n[String][Integer][Date]\n[][unique][]\n[name][id][updated]\n[serde][1][2020-01-01]\n";
+        assert!(validate_with_schema(&expected, s.as_bytes()).is_empty());
+        // the properties of the canonical schema
+        let s = format!("{}[rand][1][2020-01-02]\n", s);
+        let violations = validate_with_schema(&expected, s.as_bytes());
+        assert_eq!(
+            violations[0].to_string(),
+            r#"data row 2, column id: duplicate value "1" (line 6, column 7, pos: 95, near "]\n[rand][1][2020-01-02]\n")"#
+        );
+        // all the violations are reported
+        let s = "[crates]\n[String][Decimal][Date]\n[][][]\n[name][id][date]\n\
+                 [serde][one][2020-01-01]\n[rand][2][2020-13-01]\n[x][3]\n[y][4][2020-01-01][too many]\n";
+        let violations: Vec<String> = validate_with_schema(&expected, s.as_bytes())
+            .iter()
+            .map(|v| v.to_string())
+            .collect();
+        assert_eq!(
+            violations,
+            vec![
+                r#"schema, column id: data type Decimal instead of Integer (line 2, column 9, pos: 17, near "[String][Decimal][Date]\n")"#,
+                r#"schema, column id: additional properties "" instead of "unique" (line 3, column 3, pos: 35, near "Date]\n[][][]\n[name][id][")"#,
+                r#"schema, column updated: column name "date" instead of "updated" (line 4, column 11, pos: 50, near "ame][id][date]\n[serde][o")"#,
+                r#"data row 1, column id: Error: Integer "one" invalid digit found in string (line 5, column 8, pos: 64, near "\n[serde][one][2020-01-01")"#,
+                r#"data row 2, column updated: Error: Date "2020-13-01" input is out of range (line 6, column 10, pos: 91, near "rand][2][2020-13-01]\n[x]")"#,
+                r#"data row 3: 2 fields instead of 3 (line 7, column 7, pos: 110, near "]\n[x][3]\n[y][4][2020-01-")"#,
+                r#"data row 4: more than 3 fields (line 8, column 19, pos: 129, near "0-01-01][too many]\n")"#,
+            ]
+        );
+        // all the property violations of one row
+        let properties = unwrap!(Schema::from_qvs20(
+            b"[t]\n[String][String]\n[max_len=3][enum=a|b]\n[name][kind]\n"
+        ));
+        let violations: Vec<String> = validate_with_schema(
+            &properties,
+            b"[t]\n[String][String]\n[max_len=3][enum=a|b]\n[name][kind]\n[long][c]\n",
+        )
+        .iter()
+        .map(|v| v.msg.clone())
+        .collect();
+        assert_eq!(
+            violations,
+            vec![
+                "length 4 is more than max_len=3",
+                r#""c" is not in enum=a|b"#
+            ]
+        );
+        // a malformed file stops the validation
+        let s =
+            "[crates]\n[String][Integer][Date]\n[][unique][]\n[name][id][updated]\n[serde][1][2020";
+        let violations = validate_with_schema(&expected, s.as_bytes());
+        assert_eq!(violations.len(), 1);
+        assert_eq!(violations[0].row_number, 1);
+        assert_eq!(violations[0].position.line, 5);
+        // a malformed schema
+        let violations = validate_with_schema(&expected, b"[crates]\n[Strin]\n");
+        assert_eq!(
+            violations[0].to_string(),
+            r#"schema: Error in second row: data types, field 1: Matching variant not found "Strin" (line 2, column 1, pos: 9, near "crates]\n[Strin]\n")"#
+        );
+    }
+}
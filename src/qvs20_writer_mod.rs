@@ -1,45 +1,153 @@
 // qvs20_writer_mod
 
-// TODO: write to bytes instead of String should be faster
-// because there is no checking that the bytes are well-formed utf8
+// The writer writes directly to any std::io::Write (file, buffer, stdout...).
+// There is no intermediary String, the bytes go out as they are made.
+// Wrap the file in a BufWriter for performance.
 
-//use unwrap::unwrap;
+use crate::qvs20_properties_mod::RowChecker;
+use crate::qvs20_reader_mod::{ReaderForQvs20, CRLF};
+use crate::qvs20_schema_mod::*;
+use crate::qvs20_table_mod::*;
 
-/*
-pub struct Writer {
-    s: String,
-    schema: qvs20_schema_mod::Schema,
-    cursor_pos: usize,
-    row_delimiter: char,
+use std::io::{self, Write};
+
+/// Writer for qvs20
+pub struct Writer<W: Write> {
+    // All the fields are internal and not public.
+    // The only way to interact is through methods.
+    /// the destination of the bytes
+    wtr: W,
+    /// the allocation for escaping is reused for all the fields
+    escaped_buffer: Vec<u8>,
+    /// write as it is, without the checks, only for Display
+    unchecked: bool,
 }
 
-impl Writer {
-    // constructor
-    pub fn new(schema: qvs20_schema_mod::Schema) -> Self {
+impl<W: Write> Writer<W> {
+    /// constructor
+    pub fn new(wtr: W) -> Self {
         //return
         Writer {
-            s: String::with_capacity(1000),
-            schema,
-            cursor_pos: 0,
-            row_delimiter: '\n',
+            wtr,
+            escaped_buffer: Vec::with_capacity(1000),
+            unchecked: false,
         }
     }
-    /// push a field of type String
-    pub fn push_string(&mut self, data: &str) {
-        assert!(self.schema.data_types[self.cursor_pos] == qvs20_schema_mod::DataType::String);
-        self.s.push('[');
-        let bytes = data.as_bytes();
-        // TODO: this is bad place for allocation, it should be in the constructor.
-        let mut escaped_buffer: Vec<u8> = vec![];
-        self.s.push_str(&unwrap!(String::from_utf8(
-            Self::escape_qvs20_str(bytes, &mut escaped_buffer).to_vec()
-        )));
-        self.s.push(']');
-        self.cursor_pos += 1;
-        if self.cursor_pos >= self.schema.column_names.len() {
-            self.cursor_pos = 0;
-            self.s.push(self.row_delimiter);
+
+    /// write the 4 schema rows and all the data rows
+    pub fn write_table(&mut self, table: &Table) -> io::Result<()> {
+        let schema = &table.schema;
+        self.write_schema_rows(schema)?;
+        // rows of data
+        let mut checker = RowChecker::new(schema);
+        for (i, row) in table.rows.iter().enumerate() {
+            if self.unchecked {
+                self.write_values(row, &schema.row_delimiter)?;
+                continue;
+            }
+            check_data_types(i + 1, row, schema)?;
+            checker
+                .check_row(i + 1, row)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            self.write_values(row, &schema.row_delimiter)?;
         }
+        self.wtr.flush()
+    }
+
+    /// write only the 4 schema rows, a schema-only file
+    pub fn write_schema(&mut self, schema: &Schema) -> io::Result<()> {
+        self.write_schema_rows(schema)?;
+        self.wtr.flush()
+    }
+
+    fn write_schema_rows(&mut self, schema: &Schema) -> io::Result<()> {
+        if !self.unchecked {
+            schema
+                .check_properties()
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        }
+        let row_delimiter = &schema.row_delimiter[..];
+        // first row: table name and row delimiter
+        self.write_field(schema.table_name.as_bytes())?;
+        self.write_row_delimiter(row_delimiter)?;
+        // second row: data types
+        for column in schema.columns.iter() {
+            self.write_field(column.data_type.to_string().as_bytes())?;
+        }
+        self.write_row_delimiter(row_delimiter)?;
+        // third row: additional properties
+        for column in schema.columns.iter() {
+            self.write_field(column.properties.to_string().as_bytes())?;
+        }
+        self.write_row_delimiter(row_delimiter)?;
+        // fourth row: column names
+        for column in schema.columns.iter() {
+            self.write_field(column.name.as_bytes())?;
+        }
+        self.write_row_delimiter(row_delimiter)
+    }
+
+    /// write one data row with the canonical text of the values.
+    /// The values must match the data types of the columns,
+    /// the properties are checked by the RowChecker before.
+    pub fn write_row(&mut self, row_number: usize, row: &Row, schema: &Schema) -> io::Result<()> {
+        if !self.unchecked {
+            check_data_types(row_number, row, schema)?;
+        }
+        self.write_values(row, &schema.row_delimiter)
+    }
+
+    fn write_values(&mut self, row: &Row, row_delimiter: &[u8]) -> io::Result<()> {
+        for value in row.values.iter() {
+            match value {
+                // no need to allocate a new String
+                Value::String(s) => self.write_field(s.as_bytes())?,
+                Value::Bytes(b) => self.write_field(b)?,
+                // the nested table is written recursively and escaped as one field
+                Value::Table(t) if self.unchecked => {
+                    self.write_field(&t.to_qvs20_bytes_unchecked())?
+                }
+                Value::Table(t) => self.write_field(&t.to_qvs20_bytes()?)?,
+                // the reserved field is not escaped
+                Value::Null => self.write_raw_field(NULL_FIELD)?,
+                _ => self.write_field(value.to_string().as_bytes())?,
+            }
+        }
+        self.write_row_delimiter(row_delimiter)
+    }
+
+    /// write one field: escape the content and enclose in brackets
+    pub fn write_field(&mut self, data: &[u8]) -> io::Result<()> {
+        self.wtr.write_all(b"[")?;
+        let escaped = Self::escape_qvs20_str(data, &mut self.escaped_buffer);
+        self.wtr.write_all(escaped)?;
+        self.wtr.write_all(b"]")
+    }
+
+    /// write one field that is already escaped
+    fn write_raw_field(&mut self, escaped: &[u8]) -> io::Result<()> {
+        self.wtr.write_all(b"[")?;
+        self.wtr.write_all(escaped)?;
+        self.wtr.write_all(b"]")
+    }
+
+    /// write the row delimiter at the end of the row: 1 byte or CRLF
+    pub fn write_row_delimiter(&mut self, row_delimiter: &[u8]) -> io::Result<()> {
+        if !self.unchecked && row_delimiter.len() != 1 && row_delimiter != CRLF {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "the row delimiter {} must be 1 byte or CRLF",
+                    ReaderForQvs20::row_delimiter_text(row_delimiter)
+                ),
+            ));
+        }
+        self.wtr.write_all(row_delimiter)
+    }
+
+    /// unwrap the underlying writer
+    pub fn into_inner(self) -> W {
+        self.wtr
     }
 
     /// escape the 6 special characters
@@ -63,7 +171,7 @@ impl Writer {
             {
                 if inserted == 0 {
                     //lazy, only if needed
-                    escaped_buffer.extend_from_slice(&text.to_vec());
+                    escaped_buffer.extend_from_slice(text);
                 }
                 // for \t \n \r must replace this byte with a different byte
                 if item == &b'\n' {
@@ -81,15 +189,207 @@ impl Writer {
         }
         //return
         if inserted > 0 {
-            //println!("escape is_inserted: {}", unwrap!(str::from_utf8(escaped)));
             &escaped_buffer[..]
         } else {
             text
         }
     }
-    pub fn bytes_for_file(&self) -> &[u8] {
+}
+
+/// the number of values and the Value variants must match the columns, Null matches any column.
+/// Bytes are written as they are, so in a String column they must be UTF-8.
+fn check_data_types(row_number: usize, row: &Row, schema: &Schema) -> io::Result<()> {
+    if row.values.len() != schema.columns.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "data row {} has {} values instead of {}",
+                row_number,
+                row.values.len(),
+                schema.columns.len()
+            ),
+        ));
+    }
+    for (i, (value, column)) in row.values.iter().zip(schema.columns.iter()).enumerate() {
+        let found = match (value, &column.data_type) {
+            (Value::Null, _)
+            | (Value::String(_), DataType::String)
+            | (Value::Integer(_), DataType::Integer)
+            | (Value::Decimal(_), DataType::Decimal)
+            | (Value::Float(_), DataType::Float)
+            | (Value::Bool(_), DataType::Bool)
+            | (Value::Date(_), DataType::Date)
+            | (Value::Time(_), DataType::Time)
+            | (Value::DateTime(_), DataType::DateTime)
+            | (Value::Table(_), DataType::Table) => continue,
+            (Value::Bytes(b), DataType::String) if std::str::from_utf8(b).is_ok() => continue,
+            (Value::Bytes(_), DataType::String) => "Bytes that are not UTF-8",
+            (Value::String(_), _) => "String",
+            (Value::Integer(_), _) => "Integer",
+            (Value::Decimal(_), _) => "Decimal",
+            (Value::Float(_), _) => "Float",
+            (Value::Bool(_), _) => "Bool",
+            (Value::Date(_), _) => "Date",
+            (Value::Time(_), _) => "Time",
+            (Value::DateTime(_), _) => "DateTime",
+            (Value::Table(_), _) => "Table",
+            (Value::Bytes(_), _) => "Bytes",
+        };
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "data row {}, field {}, column {}: {} value instead of {}",
+                row_number,
+                i + 1,
+                column.name,
+                found,
+                column.data_type
+            ),
+        ));
+    }
+    Ok(())
+}
+
+impl Table {
+    /// write the table to qvs20 bytes in memory
+    /// writing to a Vec<u8> can fail only if the rows are malformed
+    pub fn to_qvs20_bytes(&self) -> io::Result<Vec<u8>> {
+        let mut wtr = Writer::new(Vec::with_capacity(1000));
+        wtr.write_table(self)?;
+        //return
+        Ok(wtr.into_inner())
+    }
+
+    /// the qvs20 text of the table as it is, for Display that cannot fail
+    /// nothing is checked, so the text of a wrong table is not valid qvs20
+    pub(crate) fn to_qvs20_bytes_unchecked(&self) -> Vec<u8> {
+        let mut wtr = Writer {
+            unchecked: true,
+            ..Writer::new(Vec::with_capacity(1000))
+        };
+        // without the checks only the io can fail, and Vec<u8> does not fail
+        let _ = wtr.write_table(self);
+        //return
+        wtr.into_inner()
+    }
+}
+
+impl Schema {
+    /// write the schema-only qvs20 bytes in memory
+    pub fn to_qvs20_bytes(&self) -> io::Result<Vec<u8>> {
+        let mut wtr = Writer::new(Vec::with_capacity(100));
+        wtr.write_schema(self)?;
         //return
-        self.s.as_bytes()
+        Ok(wtr.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use unwrap::unwrap;
+    #[test]
+    pub fn test_01_escape() {
+        let mut escaped_buffer = vec![];
+        // nothing to escape returns the same slice
+        let escaped = Writer::<Vec<u8>>::escape_qvs20_str(b"one", &mut escaped_buffer);
+        assert_eq!(escaped, b"one");
+        let escaped = Writer::<Vec<u8>>::escape_qvs20_str(b"1[2]3\\4\r5\n6\t", &mut escaped_buffer);
+        assert_eq!(escaped, br"1\[2\]3\\4\r5\n6\t");
+        // non ascii stays the same
+        let escaped = Writer::<Vec<u8>>::escape_qvs20_str("čćšß€[".as_bytes(), &mut escaped_buffer);
+        assert_eq!(escaped, r"čćšß€\[".as_bytes());
+    }
+    #[test]
+    pub fn test_02_round_trip() {
+        let s = r"[table\[name\]]
+[String][Integer][Decimal][Float][Bool][Date][Time][DateTime][Table]
+[][prop][][][][][][][]
+[s][i][d][f][b][date][time][date_time][t]
+[unescape\\\[\]\nNewLine\t\r][-42][0.0123][0.1][true][2020-02-29][23:59:59.500][2020-02-29T23:59:59][\[t\]1\[String\]1\[\]1\[s\]1\[a\]1]
+[][0][-0.5][-1e300][false][0001-01-01][00:00:00][2019-01-01T12:30:00.123456][\[t\]1\[String\]1\[\]1\[s\]1]
+";
+        let table = unwrap!(Table::from_qvs20_with_schema(s.as_bytes()));
+        let bytes = unwrap!(table.to_qvs20_bytes());
+        // the input is already in canonical text
+        assert_eq!(unwrap!(String::from_utf8(bytes.clone())), s);
+        let table2 = unwrap!(Table::from_qvs20_with_schema(&bytes));
+        assert_eq!(format!("{:?}", table), format!("{:?}", table2));
+    }
+    #[test]
+    pub fn test_03_row_delimiter() {
+        let s = "[t]1[String][Integer]1[][]1[name][id]1[one][1]1[two][2]1";
+        let table = unwrap!(Table::from_qvs20_with_schema(s.as_bytes()));
+        assert_eq!(table.schema.row_delimiter, b"1");
+        assert_eq!(unwrap!(table.to_qvs20_bytes()), s.as_bytes());
+    }
+    #[test]
+    pub fn test_04_malformed_row() {
+        let mut table = unwrap!(Table::from_qvs20_with_schema(
+            "[t]\n[String]\n[]\n[name]\n".as_bytes()
+        ));
+        table.rows.push(Row { values: vec![] });
+        let mut wtr = Writer::new(vec![]);
+        let err = wtr.write_table(&table).unwrap_err();
+        assert_eq!(err.to_string(), "data row 1 has 0 values instead of 1");
+        // the property violation stays a Qvs20ErrorTable inside of the io::Error
+        let mut table = unwrap!(Table::from_qvs20_with_schema(
+            "[t]\n[String]\n[unique]\n[name]\n".as_bytes()
+        ));
+        table.rows = vec![
+            Row {
+                values: vec![Value::String("a".to_string())]
+            };
+            2
+        ];
+        let err = unwrap!(table.to_qvs20_bytes().err());
+        assert!(matches!(
+            err.get_ref()
+                .and_then(|e| e.downcast_ref::<Qvs20ErrorTable>()),
+            Some(Qvs20ErrorTable::PropertyViolation { row_number: 2, .. })
+        ));
+    }
+    #[test]
+    pub fn test_05_trailing_backslash() {
+        let mut table = unwrap!(Table::from_qvs20_with_schema(
+            "[t]\n[String][String]\n[][]\n[description][repository]\n".as_bytes()
+        ));
+        table.rows.push(Row {
+            values: vec![
+                Value::String("ends with backslash\\".to_string()),
+                Value::String("https://github.com/\\]\\".to_string()),
+            ],
+        });
+        let bytes = unwrap!(table.to_qvs20_bytes());
+        let table2 = unwrap!(Table::from_qvs20_with_schema(&bytes));
+        assert_eq!(table2.rows[0].values, table.rows[0].values);
+    }
+    #[test]
+    pub fn test_06_wrong_data_type() {
+        let mut table = unwrap!(Table::from_qvs20_with_schema(
+            "[t]\n[String][Date]\n[][nullable]\n[name][date]\n".as_bytes()
+        ));
+        table.rows.push(Row {
+            values: vec![Value::String("one".to_string()), Value::Integer(1)],
+        });
+        let err = unwrap!(table.to_qvs20_bytes().err());
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(
+            err.to_string(),
+            "data row 1, field 2, column date: Integer value instead of Date"
+        );
+        // the Bytes from serde must be UTF-8 in a String column
+        table.rows[0].values = vec![Value::Bytes(vec![0xff]), Value::Null];
+        let mut wtr = Writer::new(vec![]);
+        let err = unwrap!(wtr.write_row(1, &table.rows[0], &table.schema).err());
+        assert_eq!(
+            err.to_string(),
+            "data row 1, field 1, column name: Bytes that are not UTF-8 value instead of String"
+        );
+        table.rows[0].values = vec![Value::Bytes(b"one".to_vec()), Value::Null];
+        assert_eq!(
+            unwrap!(table.to_qvs20_bytes()),
+            b"[t]\n[String][Date]\n[][nullable]\n[name][date]\n[one][\\0]\n"
+        );
     }
 }
-*/
@@ -0,0 +1,450 @@
+//! resolve_mod
+
+// Transitive dependency tree of one crate from the extracted tables:
+// crates.qvs20, versions.qvs20 and dependencies.qvs20.
+// Every requirement is resolved to the biggest non-yanked version that matches it.
+// There is no unification of versions like in cargo, every requirement is resolved on its own.
+// The dev dependencies are used only for the root crate, like cargo does.
+// A crate version that is already in the tree is not expanded again, it is marked with (*).
+
+use crate::reverse_dependencies_mod::{kind_name, DependencyRow};
+use crate::utils_mod::{self, SemverError, Version};
+use qvs20::qvs20_schema_mod::*;
+use qvs20::qvs20_serde_mod;
+use qvs20::qvs20_table_mod::*;
+
+use anyhow::{anyhow, Context};
+use serde_derive::Deserialize;
+use std::collections::{HashMap, HashSet};
+
+/// what dependencies to include
+#[derive(Clone, Debug, Default)]
+pub struct ResolveOptions {
+    /// include optional dependencies
+    pub include_optional: bool,
+    /// include the dev dependencies of the root crate
+    pub include_dev: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct CrateRow {
+    id: i64,
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct VersionRow {
+    id: i64,
+    crate_id: i64,
+    num: String,
+    yanked: bool,
+}
+
+/// one version of a crate
+#[derive(Debug)]
+struct VersionInfo {
+    id: i64,
+    version: Version,
+    yanked: bool,
+}
+
+/// the extracted tables prepared for the resolution
+pub struct DependencyGraph {
+    crate_ids: HashMap<String, i64>,
+    crate_names: HashMap<i64, String>,
+    /// crate_id -> versions
+    versions: HashMap<i64, Vec<VersionInfo>>,
+    /// version_id -> dependencies
+    dependencies: HashMap<i64, Vec<DependencyRow>>,
+    /// version_id and error of the versions that are not semver
+    skipped: Vec<(i64, SemverError)>,
+}
+
+/// the state of one resolution
+struct ResolveState<'a> {
+    options: &'a ResolveOptions,
+    /// the version ids already in the tree
+    expanded: HashSet<i64>,
+}
+
+/// one node of the resolved tree
+#[derive(Debug)]
+pub struct ResolvedNode {
+    pub name: String,
+    /// the requirement from the parent, for the root it is the requested requirement
+    pub req: String,
+    /// normal, build or dev
+    pub kind: String,
+    pub optional: bool,
+    /// None if no non-yanked version matches the requirement
+    pub version: Option<Version>,
+    /// this crate version is already expanded in the tree
+    pub repeated: bool,
+    pub dependencies: Vec<ResolvedNode>,
+}
+
+impl DependencyGraph {
+    /// the tables crates, versions and dependencies as extracted from the dump
+    pub fn from_tables(
+        crates: &Table,
+        versions: &Table,
+        dependencies: &Table,
+    ) -> anyhow::Result<DependencyGraph> {
+        let crate_rows: Vec<CrateRow> =
+            qvs20_serde_mod::from_table(crates).context("in the crates table")?;
+        let version_rows: Vec<VersionRow> =
+            qvs20_serde_mod::from_table(versions).context("in the versions table")?;
+        let dependency_rows: Vec<DependencyRow> =
+            qvs20_serde_mod::from_table(dependencies).context("in the dependencies table")?;
+        let mut graph = DependencyGraph {
+            crate_ids: HashMap::new(),
+            crate_names: HashMap::new(),
+            versions: HashMap::new(),
+            dependencies: HashMap::new(),
+            skipped: vec![],
+        };
+        for crate_row in crate_rows {
+            graph.crate_ids.insert(crate_row.name.clone(), crate_row.id);
+            graph.crate_names.insert(crate_row.id, crate_row.name);
+        }
+        for version_row in version_rows {
+            // a version that is not semver cannot be matched, it does not stop the resolution
+            let version = match utils_mod::parse_semver(&version_row.num) {
+                Ok(version) => version,
+                Err(error) => {
+                    graph.skipped.push((version_row.id, error));
+                    continue;
+                }
+            };
+            graph
+                .versions
+                .entry(version_row.crate_id)
+                .or_default()
+                .push(VersionInfo {
+                    id: version_row.id,
+                    version,
+                    yanked: version_row.yanked,
+                });
+        }
+        for dependency_row in dependency_rows {
+            // check the kind once
+            kind_name(dependency_row.kind)?;
+            graph
+                .dependencies
+                .entry(dependency_row.version_id)
+                .or_default()
+                .push(dependency_row);
+        }
+        //return
+        Ok(graph)
+    }
+
+    /// the versions that are not semver, they are not in the graph
+    pub fn skipped(&self) -> &[(i64, SemverError)] {
+        &self.skipped
+    }
+
+    /// the transitive dependency tree of the crate version that matches the requirement
+    pub fn resolve(
+        &self,
+        name: &str,
+        req: &str,
+        options: &ResolveOptions,
+    ) -> anyhow::Result<ResolvedNode> {
+        let crate_id = *self
+            .crate_ids
+            .get(name)
+            .ok_or_else(|| anyhow!("crate {:?} does not exist", name))?;
+        // the wrong requirement is an error only for the root
+        utils_mod::parse_version_req(req)?;
+        let mut state = ResolveState {
+            options,
+            expanded: HashSet::new(),
+        };
+        let root = self.resolve_node(crate_id, req, "normal", false, true, &mut state);
+        if root.version.is_none() {
+            return Err(anyhow!(
+                "no non-yanked version of {} matches {:?}",
+                name,
+                req
+            ));
+        }
+        //return
+        Ok(root)
+    }
+
+    /// the biggest non-yanked version that matches
+    fn last_matching_version(
+        &self,
+        crate_id: i64,
+        version_req: &utils_mod::VersionReq,
+    ) -> Option<&VersionInfo> {
+        self.versions
+            .get(&crate_id)?
+            .iter()
+            .filter(|v| !v.yanked && version_req.matches(&v.version))
+            .max_by(|a, b| a.version.cmp(&b.version))
+    }
+
+    /// recursive resolution
+    fn resolve_node(
+        &self,
+        crate_id: i64,
+        req: &str,
+        kind: &str,
+        optional: bool,
+        is_root: bool,
+        state: &mut ResolveState,
+    ) -> ResolvedNode {
+        let mut node = ResolvedNode {
+            name: self
+                .crate_names
+                .get(&crate_id)
+                .cloned()
+                .unwrap_or_else(|| format!("crate_id {}", crate_id)),
+            req: req.to_string(),
+            kind: kind.to_string(),
+            optional,
+            version: None,
+            repeated: false,
+            dependencies: vec![],
+        };
+        // an unparsable requirement stays unresolved
+        let version_info = match utils_mod::parse_version_req(req)
+            .ok()
+            .and_then(|version_req| self.last_matching_version(crate_id, &version_req))
+        {
+            Some(version_info) => version_info,
+            None => return node,
+        };
+        node.version = Some(version_info.version.clone());
+        if !state.expanded.insert(version_info.id) {
+            node.repeated = true;
+            return node;
+        }
+        for dependency in self
+            .dependencies
+            .get(&version_info.id)
+            .into_iter()
+            .flatten()
+        {
+            // kind_name was checked in from_tables
+            let kind = kind_name(dependency.kind).unwrap_or("normal");
+            if dependency.optional && !state.options.include_optional {
+                continue;
+            }
+            if kind == "dev" && !(is_root && state.options.include_dev) {
+                continue;
+            }
+            let child = self.resolve_node(
+                dependency.crate_id,
+                &dependency.req,
+                kind,
+                dependency.optional,
+                false,
+                state,
+            );
+            node.dependencies.push(child);
+        }
+        node
+    }
+}
+
+impl ResolvedNode {
+    /// the text of one node without the tree lines
+    fn label(&self) -> String {
+        let mut label = self.name.clone();
+        match &self.version {
+            Some(version) => label.push_str(&format!(" v{}", version)),
+            None => label.push_str(&format!(" {} (unresolved)", self.req)),
+        }
+        if self.kind != "normal" {
+            label.push_str(&format!(" ({})", self.kind));
+        }
+        if self.optional {
+            label.push_str(" (optional)");
+        }
+        if self.repeated {
+            label.push_str(" (*)");
+        }
+        label
+    }
+
+    /// the tree like cargo tree
+    pub fn tree_text(&self) -> String {
+        let mut text = format!("{}\n", self.label());
+        self.push_children(&mut text, "");
+        text
+    }
+
+    fn push_children(&self, text: &mut String, prefix: &str) {
+        for (i, child) in self.dependencies.iter().enumerate() {
+            let is_last = i + 1 == self.dependencies.len();
+            text.push_str(prefix);
+            text.push_str(if is_last { "└── " } else { "├── " });
+            text.push_str(&child.label());
+            text.push('\n');
+            let child_prefix = format!("{}{}", prefix, if is_last { "    " } else { "│   " });
+            child.push_children(text, &child_prefix);
+        }
+    }
+
+    /// the tree as a table, one row for every node in pre-order
+    pub fn to_table(&self) -> Table {
+        let mut table = Table {
+            schema: Schema {
+                table_name: "resolved".to_string(),
+                columns: vec![
+                    Column::new("depth", DataType::Integer),
+                    Column::new("parent", DataType::String),
+                    Column::new("name", DataType::String),
+                    Column::new("req", DataType::String),
+                    Column::new("version", DataType::String),
+                    Column::new("kind", DataType::String),
+                    Column::new("optional", DataType::Bool),
+                    Column::new("repeated", DataType::Bool),
+                ],
+                ..Default::default()
+            },
+            rows: vec![],
+        };
+        self.push_rows(&mut table, 0, "");
+        table
+    }
+
+    fn push_rows(&self, table: &mut Table, depth: i64, parent: &str) {
+        table.rows.push(Row {
+            values: vec![
+                Value::Integer(depth),
+                Value::String(parent.to_string()),
+                Value::String(self.name.clone()),
+                Value::String(self.req.clone()),
+                Value::String(
+                    self.version
+                        .as_ref()
+                        .map(|v| v.to_string())
+                        .unwrap_or_default(),
+                ),
+                Value::String(self.kind.clone()),
+                Value::Bool(self.optional),
+                Value::Bool(self.repeated),
+            ],
+        });
+        for child in self.dependencies.iter() {
+            child.push_rows(table, depth + 1, &self.name);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::dump_source_mod::DumpSource;
+    use crate::extract_and_save_mod::{extract, table_config};
+    use std::path::Path;
+    use unwrap::unwrap;
+
+    fn sample_graph() -> DependencyGraph {
+        let source = DumpSource::new(Path::new("sample_data/database"));
+        let configs = vec![
+            unwrap!(table_config("versions")),
+            unwrap!(table_config("dependencies")),
+        ];
+        let (crates_table, tables) = unwrap!(extract(&source, false, false, &configs, false));
+        unwrap!(DependencyGraph::from_tables(
+            &crates_table,
+            &tables[0],
+            &tables[1]
+        ))
+    }
+
+    #[test]
+    pub fn test_01_resolve_tree() {
+        let graph = sample_graph();
+        // * does not match the pre-release 2.1.0-beta.1
+        let root = unwrap!(graph.resolve("name_2", "*", &ResolveOptions::default()));
+        assert_eq!(
+            root.tree_text(),
+            "name_2 v2.0.0
+└── name_1 v0.10.0
+    └── name_2 v2.0.0 (build) (*)
+"
+        );
+        let table = root.to_table();
+        assert_eq!(table.rows.len(), 3);
+        assert_eq!(
+            table.rows[1].values,
+            vec![
+                Value::Integer(1),
+                Value::String("name_2".to_string()),
+                Value::String("name_1".to_string()),
+                Value::String("^0.10".to_string()),
+                Value::String("0.10.0".to_string()),
+                Value::String("normal".to_string()),
+                Value::Bool(false),
+                Value::Bool(false),
+            ]
+        );
+    }
+
+    #[test]
+    pub fn test_02_options() {
+        let graph = sample_graph();
+        let root = unwrap!(graph.resolve("name_2", "=2.1.0-beta.1", &ResolveOptions::default()));
+        assert_eq!(root.dependencies.len(), 1);
+        let options = ResolveOptions {
+            include_optional: true,
+            include_dev: true,
+        };
+        let root = unwrap!(graph.resolve("name_2", "=2.1.0-beta.1", &options));
+        assert_eq!(
+            root.tree_text(),
+            "name_2 v2.1.0-beta.1
+├── name_1 v0.9.0 (dev) (optional)
+└── name_1 v0.10.0
+    └── name_2 v2.0.0 (build)
+        └── name_1 v0.10.0 (*)
+"
+        );
+        // yanked 1.0.0 is not used
+        let root = unwrap!(graph.resolve("name_1", ">=0.10", &options));
+        assert_eq!(unwrap!(root.version).to_string(), "0.10.0");
+        // errors
+        assert!(graph.resolve("name_4", "*", &options).is_err());
+        assert!(graph.resolve("no_crate", "*", &options).is_err());
+        assert!(graph.resolve("name_1", "^5", &options).is_err());
+    }
+
+    #[test]
+    pub fn test_03_skipped_version() {
+        // the version that is not semver is skipped, the graph is built without it
+        let source = DumpSource::new(Path::new("sample_data/database"));
+        let configs = vec![
+            unwrap!(table_config("versions")),
+            unwrap!(table_config("dependencies")),
+        ];
+        let (crates_table, mut tables) = unwrap!(extract(&source, false, false, &configs, false));
+        let num_pos = unwrap!(tables[0].schema.column_position("num"));
+        tables[0].rows[0].values[num_pos] = Value::String("0.1".to_string());
+        let graph = unwrap!(DependencyGraph::from_tables(
+            &crates_table,
+            &tables[0],
+            &tables[1]
+        ));
+        assert_eq!(graph.skipped().len(), 1);
+        let id_pos = unwrap!(tables[0].schema.column_position("id"));
+        assert_eq!(
+            Value::Integer(graph.skipped()[0].0),
+            tables[0].rows[0].values[id_pos]
+        );
+        assert_eq!(
+            graph.skipped()[0].1.to_string(),
+            r#"Error: Version "0.1" must have major.minor.patch"#
+        );
+        let root = unwrap!(graph.resolve("name_2", "*", &ResolveOptions::default()));
+        assert_eq!(
+            root.version.map(|v| v.to_string()),
+            Some("2.0.0".to_string())
+        );
+    }
+}
@@ -0,0 +1,184 @@
+//! reverse_dependencies_mod
+
+// Derived table from the dependencies: for every crate the crates that depend on it.
+// Only the last non-yanked version of the dependent crate counts
+// and its requirement must match the last non-yanked version of the crate.
+// One row for every crate, kind and optional, the long format is easy to query:
+// [crate][last_version][kind][optional][count][dependents]
+
+use crate::extract_and_save_mod::{CrateData, VersionData};
+use crate::utils_mod;
+use qvs20::qvs20_schema_mod::*;
+use qvs20::qvs20_serde_mod;
+use qvs20::qvs20_table_mod::*;
+
+use anyhow::{anyhow, Context};
+use serde_derive::Deserialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+/// the table name and the output file name
+pub const REVERSE_DEPENDENCIES: &str = "reverse_dependencies";
+
+/// the columns of the dependencies table that are needed, also for the resolution
+#[derive(Debug, Deserialize)]
+pub struct DependencyRow {
+    pub version_id: i64,
+    pub crate_id: i64,
+    pub req: String,
+    pub kind: i64,
+    pub optional: bool,
+}
+
+/// the dependency kind in the dump: 0 normal, 1 build, 2 dev
+pub fn kind_name(kind: i64) -> anyhow::Result<&'static str> {
+    match kind {
+        0 => Ok("normal"),
+        1 => Ok("build"),
+        2 => Ok("dev"),
+        _ => Err(anyhow!("unknown dependency kind {}", kind)),
+    }
+}
+
+/// the reverse dependencies table from the extracted crates, last versions and dependencies
+pub fn reverse_dependencies_table(
+    crates: &[CrateData],
+    last_versions: &HashMap<String, VersionData>,
+    dependencies: &Table,
+) -> anyhow::Result<Table> {
+    let names: HashMap<&str, &str> = crates
+        .iter()
+        .map(|c| (c.id.as_str(), c.name.as_str()))
+        .collect();
+    // version_id of the last version -> crate_id
+    let last_version_ids: HashMap<String, &str> = last_versions
+        .values()
+        .map(|v| (v.id.clone(), v.crate_id.as_str()))
+        .collect();
+    // (crate name, kind, optional) -> names of the dependent crates
+    let mut groups: BTreeMap<(&str, &str, bool), BTreeSet<&str>> = BTreeMap::new();
+    let dependency_rows: Vec<DependencyRow> =
+        qvs20_serde_mod::from_table(dependencies).context("in the dependencies table")?;
+    for dependency in dependency_rows.iter() {
+        // the old versions of the dependent crate don't count
+        let dependent_crate_id = match last_version_ids.get(&dependency.version_id.to_string()) {
+            Some(crate_id) => *crate_id,
+            None => continue,
+        };
+        // the crate without non-yanked versions has no reverse dependencies
+        let crate_id = dependency.crate_id.to_string();
+        let last_version = match last_versions
+            .get(&crate_id)
+            .and_then(|v| v.version.as_ref())
+        {
+            Some(version) => version,
+            None => continue,
+        };
+        // an unparsable requirement cannot match
+        let is_match = utils_mod::parse_version_req(&dependency.req)
+            .map(|req| req.matches(last_version))
+            .unwrap_or(false);
+        if !is_match {
+            continue;
+        }
+        let (name, dependent_name) =
+            match (names.get(crate_id.as_str()), names.get(dependent_crate_id)) {
+                (Some(name), Some(dependent_name)) => (*name, *dependent_name),
+                _ => continue,
+            };
+        groups
+            .entry((name, kind_name(dependency.kind)?, dependency.optional))
+            .or_default()
+            .insert(dependent_name);
+    }
+    // the schema is written manually because the table can have no rows
+    let mut table = Table {
+        schema: Schema {
+            table_name: REVERSE_DEPENDENCIES.to_string(),
+            columns: vec![
+                Column::new("crate", DataType::String),
+                Column::new("last_version", DataType::String),
+                Column::new("kind", DataType::String),
+                Column::new("optional", DataType::Bool),
+                Column::new("count", DataType::Integer),
+                Column::new("dependents", DataType::String),
+            ],
+            ..Default::default()
+        },
+        rows: vec![],
+    };
+    let crate_ids: HashMap<&str, &str> = crates
+        .iter()
+        .map(|c| (c.name.as_str(), c.id.as_str()))
+        .collect();
+    for ((name, kind, optional), dependents) in groups {
+        let last_version = crate_ids
+            .get(name)
+            .and_then(|id| last_versions.get(*id))
+            .map(|v| v.num.clone())
+            .unwrap_or_default();
+        table.rows.push(Row {
+            values: vec![
+                Value::String(name.to_string()),
+                Value::String(last_version),
+                Value::String(kind.to_string()),
+                Value::Bool(optional),
+                Value::Integer(dependents.len() as i64),
+                Value::String(dependents.into_iter().collect::<Vec<_>>().join(",")),
+            ],
+        });
+    }
+    //return
+    Ok(table)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::dump_source_mod::DumpSource;
+    use crate::extract_and_save_mod::extract;
+    use qvs20::qvs20_table_mod::*;
+    use std::path::Path;
+    use unwrap::unwrap;
+    #[test]
+    pub fn test_01_reverse_dependencies() {
+        let source = DumpSource::new(Path::new("sample_data/database"));
+        let (_crates_table, tables) = unwrap!(extract(&source, false, false, &[], true));
+        assert_eq!(tables.len(), 1);
+        let table = &tables[0];
+        assert_eq!(table.schema.table_name, "reverse_dependencies");
+        // the dev dependency ~0.9.0 does not match 0.10.0,
+        // the build dependency >=2.0, <3 does not match the pre-release 2.1.0-beta.1
+        // and the old version 0.1.1 of name_1 does not count
+        assert_eq!(table.rows.len(), 1);
+        assert_eq!(
+            table.rows[0].values,
+            vec![
+                Value::String("name_1".to_string()),
+                Value::String("0.10.0".to_string()),
+                Value::String("normal".to_string()),
+                Value::Bool(false),
+                Value::Integer(1),
+                Value::String("name_2".to_string()),
+            ]
+        );
+        // without pre-releases the last version of name_2 is 2.0.0
+        let (_crates_table, tables) = unwrap!(extract(&source, true, false, &[], true));
+        let rows: Vec<String> = tables[0]
+            .rows
+            .iter()
+            .map(|r| {
+                r.values
+                    .iter()
+                    .map(|v| v.to_string())
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            })
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                "name_1 0.10.0 normal false 1 name_2",
+                "name_2 2.0.0 build false 1 name_1"
+            ]
+        );
+    }
+}
@@ -1,43 +1,619 @@
 // utils_mod
 
-use unwrap::unwrap;
-
-/// parse semver ex. 12.99.88alpha
-pub fn parse_semver(text: &str) -> (usize, usize, usize) {
-    let pos = 0;
-    let (major, pos) = parse_next_number(&text, pos);
-    // jump over dot
-    let pos = pos + 1;
-    let (minor, pos) = parse_next_number(&text, pos);
-    // jump over dot
-    let pos = pos + 1;
-    let (patch, _pos) = parse_next_number(&text, pos);
-    // return
-    (major, minor, patch)
-}
-
-/// parse next characters until is numeric or end
-fn parse_next_number(text: &str, pos: usize) -> (usize, usize) {
-    let mut pos = pos;
-    let mut number = String::new();
-    let mut one_char = text[pos..pos + 1].chars().next().unwrap();
-    while one_char.is_numeric() {
-        number.push(one_char);
-        pos += 1;
-        if pos > text.len() - 1 {
-            break;
-        }
-        one_char = text[pos..pos + 1].chars().next().unwrap();
-    }
-    let number: usize = unwrap!(number.parse());
-    // return
-    (number, pos)
-}
-
-/// version for sorting
-pub fn version_for_sorting(version: &str) -> String {
-    let (major, minor, patch) = parse_semver(version);
-    let version_for_sorting = format!("{:09}.{:09}.{:09}", major, minor, patch,);
-    // return
-    version_for_sorting
+// SemVer 2.0 https://semver.org/
+// major.minor.patch-pre.release+build.metadata
+// VersionReq is the version requirement of a dependency with the cargo rules:
+// https://doc.rust-lang.org/cargo/reference/specifying-dependencies.html
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
+
+#[derive(Error, Debug, Eq, PartialEq)]
+pub enum SemverError {
+    #[error("Error: Version {text:?} {msg}")]
+    Invalid { text: String, msg: String },
+}
+
+/// one dot separated identifier of the pre-release
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Identifier {
+    Numeric(u64),
+    AlphaNumeric(String),
+}
+
+/// SemVer 2.0 version
+/// Eq and Ord use the precedence rules: build metadata is ignored.
+#[derive(Clone, Debug, Default)]
+pub struct Version {
+    pub major: u64,
+    pub minor: u64,
+    pub patch: u64,
+    /// empty for a normal (not pre-release) version
+    pub pre: Vec<Identifier>,
+    /// build metadata does not change the precedence
+    pub build: Vec<String>,
+}
+
+/// parse semver ex. 12.99.88-alpha.1+build.5
+pub fn parse_semver(text: &str) -> Result<Version, SemverError> {
+    let err = |msg: &str| SemverError::Invalid {
+        text: text.to_string(),
+        msg: msg.to_string(),
+    };
+    // the build metadata is after the first +
+    let (text_without_build, build) = match text.find('+') {
+        Some(pos) => (&text[..pos], Some(&text[pos + 1..])),
+        None => (text, None),
+    };
+    // the pre-release is after the first -
+    let (core, pre) = match text_without_build.find('-') {
+        Some(pos) => (
+            &text_without_build[..pos],
+            Some(&text_without_build[pos + 1..]),
+        ),
+        None => (text_without_build, None),
+    };
+    let numbers: Vec<&str> = core.split('.').collect();
+    if numbers.len() != 3 {
+        return Err(err("must have major.minor.patch"));
+    }
+    let major = parse_number(numbers[0]).ok_or_else(|| err("major is not a number"))?;
+    let minor = parse_number(numbers[1]).ok_or_else(|| err("minor is not a number"))?;
+    let patch = parse_number(numbers[2]).ok_or_else(|| err("patch is not a number"))?;
+
+    let mut version = Version {
+        major,
+        minor,
+        patch,
+        pre: vec![],
+        build: vec![],
+    };
+    if let Some(pre) = pre {
+        for identifier in pre.split('.') {
+            if !is_identifier(identifier) {
+                return Err(err("wrong pre-release identifier"));
+            }
+            if identifier.bytes().all(|b| b.is_ascii_digit()) {
+                let number = parse_number(identifier)
+                    .ok_or_else(|| err("numeric pre-release identifier with leading zero"))?;
+                version.pre.push(Identifier::Numeric(number));
+            } else {
+                version
+                    .pre
+                    .push(Identifier::AlphaNumeric(identifier.to_string()));
+            }
+        }
+    }
+    if let Some(build) = build {
+        for identifier in build.split('.') {
+            if !is_identifier(identifier) {
+                return Err(err("wrong build metadata identifier"));
+            }
+            version.build.push(identifier.to_string());
+        }
+    }
+    //return
+    Ok(version)
+}
+
+/// numeric identifier: only digits and no leading zero
+fn parse_number(text: &str) -> Option<u64> {
+    if text.is_empty()
+        || !text.bytes().all(|b| b.is_ascii_digit())
+        || (text.len() > 1 && text.starts_with('0'))
+    {
+        return None;
+    }
+    text.parse().ok()
+}
+
+/// identifier: not empty, only ascii alphanumerics and hyphens
+fn is_identifier(text: &str) -> bool {
+    !text.is_empty() && text.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-')
+}
+
+impl Version {
+    /// pre-release versions are not stable
+    pub fn is_prerelease(&self) -> bool {
+        !self.pre.is_empty()
+    }
+}
+
+impl FromStr for Version {
+    type Err = SemverError;
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        parse_semver(text)
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)?;
+        for (i, identifier) in self.pre.iter().enumerate() {
+            f.write_str(if i == 0 { "-" } else { "." })?;
+            match identifier {
+                Identifier::Numeric(n) => write!(f, "{}", n)?,
+                Identifier::AlphaNumeric(s) => f.write_str(s)?,
+            }
+        }
+        if !self.build.is_empty() {
+            write!(f, "+{}", self.build.join("."))?;
+        }
+        Ok(())
+    }
+}
+
+impl Ord for Identifier {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Identifier::Numeric(a), Identifier::Numeric(b)) => a.cmp(b),
+            // numeric identifiers always have lower precedence
+            (Identifier::Numeric(_), Identifier::AlphaNumeric(_)) => Ordering::Less,
+            (Identifier::AlphaNumeric(_), Identifier::Numeric(_)) => Ordering::Greater,
+            // lexically in ASCII sort order
+            (Identifier::AlphaNumeric(a), Identifier::AlphaNumeric(b)) => a.cmp(b),
+        }
+    }
+}
+
+impl PartialOrd for Identifier {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.major
+            .cmp(&other.major)
+            .then(self.minor.cmp(&other.minor))
+            .then(self.patch.cmp(&other.patch))
+            .then_with(|| match (self.pre.is_empty(), other.pre.is_empty()) {
+                (true, true) => Ordering::Equal,
+                // a pre-release version has lower precedence than a normal version
+                (true, false) => Ordering::Greater,
+                (false, true) => Ordering::Less,
+                // a larger set of pre-release fields has a higher precedence
+                (false, false) => self.pre.cmp(&other.pre),
+            })
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Version {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Version {}
+
+/// the operator of one comparator
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Op {
+    /// =
+    Exact,
+    /// >
+    Greater,
+    /// >=
+    GreaterEq,
+    /// <
+    Less,
+    /// <=
+    LessEq,
+    /// ~
+    Tilde,
+    /// ^ and without operator
+    Caret,
+    /// 1.* or 1.2.x
+    Wildcard,
+}
+
+/// one comparator ex. >=1.2, the missing minor and patch are None
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Comparator {
+    pub op: Op,
+    pub major: u64,
+    pub minor: Option<u64>,
+    pub patch: Option<u64>,
+    pub pre: Vec<Identifier>,
+}
+
+/// comma separated comparators, all of them must match
+/// * is an empty list of comparators
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct VersionReq {
+    pub comparators: Vec<Comparator>,
+}
+
+/// parse the version requirement ex. ^1.2.3 or >= 1.0, < 2
+pub fn parse_version_req(text: &str) -> Result<VersionReq, SemverError> {
+    let err = |msg: &str| SemverError::Invalid {
+        text: text.to_string(),
+        msg: msg.to_string(),
+    };
+    let mut version_req = VersionReq::default();
+    if text.trim() == "*" {
+        return Ok(version_req);
+    }
+    for part in text.split(',') {
+        let part = part.trim();
+        let (op, rest) = if let Some(rest) = part.strip_prefix(">=") {
+            (Some(Op::GreaterEq), rest)
+        } else if let Some(rest) = part.strip_prefix("<=") {
+            (Some(Op::LessEq), rest)
+        } else if let Some(rest) = part.strip_prefix('>') {
+            (Some(Op::Greater), rest)
+        } else if let Some(rest) = part.strip_prefix('<') {
+            (Some(Op::Less), rest)
+        } else if let Some(rest) = part.strip_prefix('=') {
+            (Some(Op::Exact), rest)
+        } else if let Some(rest) = part.strip_prefix('~') {
+            (Some(Op::Tilde), rest)
+        } else if let Some(rest) = part.strip_prefix('^') {
+            (Some(Op::Caret), rest)
+        } else {
+            (None, part)
+        };
+        let rest = rest.trim();
+        // the build metadata is ignored
+        let rest = match rest.find('+') {
+            Some(pos) => &rest[..pos],
+            None => rest,
+        };
+        let (core, pre) = match rest.find('-') {
+            Some(pos) => (&rest[..pos], Some(&rest[pos + 1..])),
+            None => (rest, None),
+        };
+        let mut numbers: Vec<Option<u64>> = vec![];
+        let mut has_wildcard = false;
+        for number in core.split('.') {
+            if number == "*" || number == "x" || number == "X" {
+                has_wildcard = true;
+                numbers.push(None);
+            } else if has_wildcard {
+                return Err(err("number after wildcard"));
+            } else {
+                let number = parse_number(number).ok_or_else(|| err("wrong number"))?;
+                numbers.push(Some(number));
+            }
+        }
+        if numbers.len() > 3 {
+            return Err(err("must have at most major.minor.patch"));
+        }
+        let major = match numbers[0] {
+            Some(major) => major,
+            None => {
+                // * inside of a list of comparators
+                if numbers.len() == 1 && op.is_none() {
+                    continue;
+                }
+                return Err(err("major cannot be a wildcard"));
+            }
+        };
+        let op = match op {
+            Some(op) if has_wildcard => {
+                if op != Op::Exact {
+                    return Err(err("wildcard only without operator"));
+                }
+                Op::Wildcard
+            }
+            Some(op) => op,
+            None if has_wildcard => Op::Wildcard,
+            None => Op::Caret,
+        };
+        let mut comparator = Comparator {
+            op,
+            major,
+            minor: numbers.get(1).copied().flatten(),
+            patch: numbers.get(2).copied().flatten(),
+            pre: vec![],
+        };
+        if let Some(pre) = pre {
+            if comparator.patch.is_none() {
+                return Err(err("pre-release without patch"));
+            }
+            for identifier in pre.split('.') {
+                if !is_identifier(identifier) {
+                    return Err(err("wrong pre-release identifier"));
+                }
+                if identifier.bytes().all(|b| b.is_ascii_digit()) {
+                    let number = parse_number(identifier)
+                        .ok_or_else(|| err("numeric pre-release identifier with leading zero"))?;
+                    comparator.pre.push(Identifier::Numeric(number));
+                } else {
+                    comparator
+                        .pre
+                        .push(Identifier::AlphaNumeric(identifier.to_string()));
+                }
+            }
+        }
+        version_req.comparators.push(comparator);
+    }
+    //return
+    Ok(version_req)
+}
+
+impl FromStr for VersionReq {
+    type Err = SemverError;
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        parse_version_req(text)
+    }
+}
+
+impl VersionReq {
+    /// all the comparators must match.
+    /// A pre-release version matches only if a comparator
+    /// with the same major.minor.patch has a pre-release too.
+    pub fn matches(&self, version: &Version) -> bool {
+        if !self.comparators.iter().all(|c| c.matches(version)) {
+            return false;
+        }
+        if !version.is_prerelease() {
+            return true;
+        }
+        self.comparators.iter().any(|c| {
+            c.major == version.major
+                && c.minor == Some(version.minor)
+                && c.patch == Some(version.patch)
+                && !c.pre.is_empty()
+        })
+    }
+}
+
+/// compare pre-releases: the normal version (empty) is the biggest
+fn cmp_pre(a: &[Identifier], b: &[Identifier]) -> Ordering {
+    match (a.is_empty(), b.is_empty()) {
+        (true, true) => Ordering::Equal,
+        (true, false) => Ordering::Greater,
+        (false, true) => Ordering::Less,
+        (false, false) => a.cmp(b),
+    }
+}
+
+impl Comparator {
+    /// one comparator without the pre-release rule of the VersionReq
+    pub fn matches(&self, version: &Version) -> bool {
+        match self.op {
+            Op::Exact | Op::Wildcard => self.matches_exact(version),
+            Op::Greater => self.matches_greater(version),
+            Op::GreaterEq => self.matches_exact(version) || self.matches_greater(version),
+            Op::Less => self.matches_less(version),
+            Op::LessEq => self.matches_exact(version) || self.matches_less(version),
+            Op::Tilde => self.matches_tilde(version),
+            Op::Caret => self.matches_caret(version),
+        }
+    }
+
+    fn matches_exact(&self, version: &Version) -> bool {
+        version.major == self.major
+            && self.minor.unwrap_or(version.minor) == version.minor
+            && self.patch.unwrap_or(version.patch) == version.patch
+            && version.pre == self.pre
+    }
+
+    fn matches_greater(&self, version: &Version) -> bool {
+        if version.major != self.major {
+            return version.major > self.major;
+        }
+        match self.minor {
+            None => return false,
+            Some(minor) if version.minor != minor => return version.minor > minor,
+            Some(_) => {}
+        }
+        match self.patch {
+            None => return false,
+            Some(patch) if version.patch != patch => return version.patch > patch,
+            Some(_) => {}
+        }
+        cmp_pre(&version.pre, &self.pre) == Ordering::Greater
+    }
+
+    fn matches_less(&self, version: &Version) -> bool {
+        if version.major != self.major {
+            return version.major < self.major;
+        }
+        match self.minor {
+            None => return false,
+            Some(minor) if version.minor != minor => return version.minor < minor,
+            Some(_) => {}
+        }
+        match self.patch {
+            None => return false,
+            Some(patch) if version.patch != patch => return version.patch < patch,
+            Some(_) => {}
+        }
+        cmp_pre(&version.pre, &self.pre) == Ordering::Less
+    }
+
+    /// ~1.2.3 is >=1.2.3, <1.3.0
+    fn matches_tilde(&self, version: &Version) -> bool {
+        if version.major != self.major {
+            return false;
+        }
+        if let Some(minor) = self.minor {
+            if version.minor != minor {
+                return false;
+            }
+        }
+        if let Some(patch) = self.patch {
+            if version.patch != patch {
+                return version.patch > patch;
+            }
+        }
+        cmp_pre(&version.pre, &self.pre) != Ordering::Less
+    }
+
+    /// ^1.2.3 is >=1.2.3, <2.0.0 and ^0.2.3 is >=0.2.3, <0.3.0
+    fn matches_caret(&self, version: &Version) -> bool {
+        if version.major != self.major {
+            return false;
+        }
+        let minor = match self.minor {
+            None => return true,
+            Some(minor) => minor,
+        };
+        let patch = match self.patch {
+            None => {
+                return if self.major > 0 {
+                    version.minor >= minor
+                } else {
+                    version.minor == minor
+                }
+            }
+            Some(patch) => patch,
+        };
+        if self.major > 0 {
+            if version.minor != minor {
+                return version.minor > minor;
+            } else if version.patch != patch {
+                return version.patch > patch;
+            }
+        } else if minor > 0 {
+            if version.minor != minor {
+                return false;
+            } else if version.patch != patch {
+                return version.patch > patch;
+            }
+        } else if version.minor != minor || version.patch != patch {
+            return false;
+        }
+        cmp_pre(&version.pre, &self.pre) != Ordering::Less
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use unwrap::unwrap;
+    #[test]
+    pub fn test_01_parse() {
+        let version = unwrap!(parse_semver("12.99.88-alpha.1+build.5"));
+        assert_eq!(version.major, 12);
+        assert_eq!(version.minor, 99);
+        assert_eq!(version.patch, 88);
+        assert_eq!(
+            version.pre,
+            vec![
+                Identifier::AlphaNumeric("alpha".to_string()),
+                Identifier::Numeric(1)
+            ]
+        );
+        assert_eq!(version.build, vec!["build".to_string(), "5".to_string()]);
+        assert_eq!(version.to_string(), "12.99.88-alpha.1+build.5");
+        // hyphen after the first one is part of the pre-release identifier
+        let version = unwrap!(parse_semver("1.0.0-x-y.z+b-1"));
+        assert_eq!(version.to_string(), "1.0.0-x-y.z+b-1");
+        // build metadata can have leading zeros
+        assert!(parse_semver("1.0.0+001").is_ok());
+    }
+    #[test]
+    pub fn test_02_parse_errors() {
+        for text in &[
+            "",
+            "1.0",
+            "1.0.0.0",
+            "01.0.0",
+            "1.0.x",
+            "1.0.0-",
+            "1.0.0-alpha..1",
+            "1.0.0-01",
+            "1.0.0+",
+            "1.0.0-al$pha",
+            "1.0.0 ",
+        ] {
+            assert!(parse_semver(text).is_err(), "{:?} must be an error", text);
+        }
+        assert_eq!(
+            unwrap!(parse_semver("1.0").err()).to_string(),
+            r#"Error: Version "1.0" must have major.minor.patch"#
+        );
+    }
+    #[test]
+    pub fn test_03_precedence() {
+        // example from semver.org
+        let ordered = [
+            "1.0.0-alpha",
+            "1.0.0-alpha.1",
+            "1.0.0-alpha.beta",
+            "1.0.0-beta",
+            "1.0.0-beta.2",
+            "1.0.0-beta.11",
+            "1.0.0-rc.1",
+            "1.0.0",
+            "1.0.1",
+            "1.1.0",
+            "1.10.0",
+            "2.0.0",
+        ];
+        for pair in ordered.windows(2) {
+            let a: Version = unwrap!(pair[0].parse());
+            let b: Version = unwrap!(pair[1].parse());
+            assert!(a < b, "{} < {}", a, b);
+        }
+        // build metadata is ignored
+        let a: Version = unwrap!("1.0.0+build.1".parse());
+        let b: Version = unwrap!("1.0.0+build.2".parse());
+        assert_eq!(a, b);
+        assert!(!a.is_prerelease());
+        assert!(unwrap!("1.0.0-rc.1".parse::<Version>()).is_prerelease());
+    }
+    #[test]
+    pub fn test_04_version_req() {
+        let matches = |req: &str, version: &str| {
+            let req: VersionReq = unwrap!(req.parse());
+            let version: Version = unwrap!(version.parse());
+            req.matches(&version)
+        };
+        // (requirement, matching versions, not matching versions)
+        let cases: &[(&str, &[&str], &[&str])] = &[
+            (
+                "1.2.3",
+                &["1.2.3", "1.9.0"],
+                &["1.2.2", "2.0.0", "1.3.0-alpha"],
+            ),
+            ("^0.2.3", &["0.2.3", "0.2.9"], &["0.3.0", "0.2.2"]),
+            ("^0.0.3", &["0.0.3"], &["0.0.4"]),
+            ("^0", &["0.0.1", "0.9.9"], &["1.0.0"]),
+            ("~1.2", &["1.2.0", "1.2.9"], &["1.3.0"]),
+            ("~1.2.3", &["1.2.3", "1.2.4"], &["1.3.0", "1.2.2"]),
+            ("=1.2.3", &["1.2.3"], &["1.2.4"]),
+            (">1.2", &["1.3.0"], &["1.2.9"]),
+            (">=1.2.3, <1.5", &["1.2.3", "1.4.9"], &["1.5.0", "1.2.2"]),
+            ("<=1.2", &["1.2.9", "0.1.0"], &["1.3.0"]),
+            ("<2", &["1.9.9"], &["2.0.0"]),
+            ("1.*", &["1.0.0", "1.9.0"], &["2.0.0"]),
+            ("1.2.x", &["1.2.7"], &["1.3.0"]),
+            ("*", &["0.0.1", "9.9.9"], &["1.0.0-rc.1"]),
+            (
+                "^1.0.0-beta.2",
+                &["1.0.0-beta.2", "1.0.0-rc.1", "1.0.0", "1.5.0"],
+                &["1.0.0-beta.1", "1.1.0-rc.1"],
+            ),
+        ];
+        for (req, yes, no) in cases.iter() {
+            for version in yes.iter() {
+                assert!(matches(req, version), "{} must match {}", req, version);
+            }
+            for version in no.iter() {
+                assert!(!matches(req, version), "{} must not match {}", req, version);
+            }
+        }
+        for text in &[
+            "", "1.2.3.4", "a.b", "1.*.3", ">1.*", "~>1.2", "1.2-beta", "^01.2",
+        ] {
+            assert!(
+                parse_version_req(text).is_err(),
+                "{:?} must be an error",
+                text
+            );
+        }
+    }
 }